@@ -0,0 +1,186 @@
+use bevy::prelude::*;
+use ggrs::{InputStatus, PlayerHandle};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::components::{Direction, Player, Segment, Snake};
+use crate::config::parse_arg;
+use crate::export::GameStateQuery;
+use crate::net::{encode_turns, pressed_turns, BoxInput, GGRSConfig, PlayerInputs};
+
+// The snake an external agent steers. The others are left to the bots.
+pub const AGENT_HANDLE: PlayerHandle = 0;
+// How long the agent gets to answer a state before it's sent a newer one. A late or missing answer
+// just means the snake keeps going the way it was.
+pub const AGENT_REPLY_TIMEOUT: Duration = Duration::from_millis(50);
+
+// The local TCP port an external agent connects to, set with `--agent-port <port>`.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct AgentPort(pub Option<u16>);
+
+impl AgentPort {
+    pub fn from_args(args: &[String]) -> Result<AgentPort, String> {
+        let port = parse_arg(args, "--agent-port", "a port number", |port| {
+            port.parse().ok()
+        })?;
+        Ok(AgentPort(port))
+    }
+}
+
+// The game's end of the connection to the agent thread. States only queue one deep: while the
+// agent is busy or not connected yet, newer frames are dropped rather than piling up.
+pub struct AgentLink {
+    states: Mutex<SyncSender<String>>,
+    directions: Mutex<Receiver<Direction>>,
+}
+
+impl AgentLink {
+    pub fn send_state(&self, state: String) {
+        if let Ok(states) = self.states.lock() {
+            match states.try_send(state) {
+                Ok(()) | Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => warn!("The agent connection has stopped"),
+            }
+        }
+    }
+
+    // The newest direction the agent asked for since the last call, if any.
+    pub fn take_direction(&self) -> Option<Direction> {
+        let directions = self.directions.lock().ok()?;
+        let mut latest = None;
+        loop {
+            match directions.try_recv() {
+                Ok(dir) => latest = Some(dir),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => return latest,
+            }
+        }
+    }
+}
+
+// Accepts "Up", "Down", "Left" or "Right" in any case, with or without the trailing newline.
+pub fn parse_direction(line: &str) -> Option<Direction> {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "up" => Some(Direction::Up),
+        "down" => Some(Direction::Down),
+        "left" => Some(Direction::Left),
+        "right" => Some(Direction::Right),
+        _ => None,
+    }
+}
+
+// Talks to one agent at a time: every state is sent as a line of JSON, and one line back is waited
+// for. When an agent disconnects the next one can connect.
+fn serve_agents(listener: TcpListener, states: Receiver<String>, directions: Sender<Direction>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Failed to accept an agent: {}", err);
+                continue;
+            }
+        };
+        info!("Agent connected from {:?}", stream.peer_addr());
+        match serve_agent(stream, &states, &directions) {
+            Ok(()) => return,
+            Err(err) => info!("Agent disconnected: {}", err),
+        }
+    }
+}
+
+// Returns `Ok` once the game has gone away, and the error if the agent did.
+fn serve_agent(
+    stream: TcpStream,
+    states: &Receiver<String>,
+    directions: &Sender<Direction>,
+) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(AGENT_REPLY_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    loop {
+        let state = match states.recv() {
+            Ok(state) => state,
+            Err(_) => return Ok(()),
+        };
+        writeln!(writer, "{}", state)?;
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => {
+                if let Some(dir) = parse_direction(&line) {
+                    if directions.send(dir).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// Binds the port on the main thread, so a port that's taken is reported straight away, and leaves
+// the blocking socket work to a thread of its own. It waits on the socket for as long as the game
+// runs, so on the `IoTaskPool` it would hold up asset loading.
+pub fn start_agent_listener(mut commands: Commands, port: Res<AgentPort>) {
+    let port = match **port {
+        Some(port) => port,
+        None => return,
+    };
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to listen for an agent on port {}: {}", port, err);
+            return;
+        }
+    };
+    info!("Waiting for an agent on port {}", port);
+    let (state_sender, state_receiver) = mpsc::sync_channel(1);
+    let (direction_sender, direction_receiver) = mpsc::channel();
+    let spawned = thread::Builder::new()
+        .name("agent".to_string())
+        .spawn(move || serve_agents(listener, state_receiver, direction_sender));
+    if let Err(err) = spawned {
+        error!("Failed to start the agent thread: {}", err);
+        return;
+    }
+    commands.insert_resource(AgentLink {
+        states: Mutex::new(state_sender),
+        directions: Mutex::new(direction_receiver),
+    });
+}
+
+// Feeds the agent's turn in as `AGENT_HANDLE`'s input, the way `local_input` does for the
+// keyboard. Every other handle gets no input.
+pub fn agent_input(
+    agent: Option<Res<AgentLink>>,
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    mut inputs: ResMut<PlayerInputs<GGRSConfig>>,
+) {
+    let dir = agent.and_then(|agent| agent.take_direction());
+    for (handle, (input, status)) in inputs.iter_mut().enumerate() {
+        let pressed = dir.filter(|_| handle == AGENT_HANDLE);
+        let turns = pressed_turns(pressed, handle, &snake_query, &segment_query);
+        *input = BoxInput {
+            inp: encode_turns(&turns),
+        };
+        *status = InputStatus::Confirmed;
+    }
+}
+
+// Runs at the end of every simulated frame, so the agent always answers the newest state.
+pub fn send_agent_state(agent: Option<Res<AgentLink>>, game_state: GameStateQuery) {
+    let agent = match agent {
+        Some(agent) => agent,
+        None => return,
+    };
+    match serde_json::to_string(&game_state.state()) {
+        Ok(json) => agent.send_state(json),
+        Err(err) => warn!("Failed to serialize the state for the agent: {}", err),
+    }
+}
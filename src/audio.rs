@@ -0,0 +1,72 @@
+use bevy::{asset::LoadState, prelude::*};
+use ggrs::InputStatus;
+
+use crate::net::{GGRSConfig, PlayerInputs};
+use crate::systems::GameEvent;
+
+pub const EAT_SOUND_PATH: &str = "sounds/eat.ogg";
+pub const DEATH_SOUND_PATH: &str = "sounds/death.ogg";
+
+pub struct AudioAssets {
+    pub eat: Handle<AudioSource>,
+    pub death: Handle<AudioSource>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sound {
+    Eat,
+    Death,
+}
+
+// Sounds waiting for `play_sounds`. Only `queue_sounds` in the simulation pushes here, and it's not
+// rolled back, so a clip queued once stays queued however often the frame is re-simulated.
+#[derive(Default, Deref, DerefMut)]
+pub struct SoundQueue(pub Vec<Sound>);
+
+pub fn load_audio(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(AudioAssets {
+        eat: asset_server.load(EAT_SOUND_PATH),
+        death: asset_server.load(DEATH_SOUND_PATH),
+    });
+}
+
+// A frame simulated on predicted input may be rolled back and simulated again, so its sounds are
+// dropped. Once every input for a frame is confirmed GGRS won't re-simulate it, so each sound is
+// queued exactly once, if a little later than the frame was first shown.
+pub fn queue_sounds(
+    mut game_events: EventReader<GameEvent>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    mut queue: ResMut<SoundQueue>,
+) {
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    for event in game_events.iter() {
+        if !confirmed {
+            continue;
+        }
+        match event {
+            GameEvent::FoodEaten { .. } => queue.push(Sound::Eat),
+            GameEvent::SnakeDied { .. } => queue.push(Sound::Death),
+            _ => {}
+        }
+    }
+}
+
+// Clips that failed to load, e.g. because the file isn't in `assets/sounds`, are skipped.
+pub fn play_sounds(
+    mut queue: ResMut<SoundQueue>,
+    audio: Res<Audio>,
+    audio_assets: Res<AudioAssets>,
+    asset_server: Res<AssetServer>,
+) {
+    for sound in queue.drain(..) {
+        let clip = match sound {
+            Sound::Eat => &audio_assets.eat,
+            Sound::Death => &audio_assets.death,
+        };
+        if asset_server.get_load_state(clip) != LoadState::Failed {
+            audio.play(clip.clone());
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::BotHandles;
+use crate::resources::GameRng;
+use crate::{build_headless_app, AppState};
+
+// Every benchmark starts from the same seed, so runs of the same build simulate the same matches.
+pub const BENCH_SEED: u64 = 0x5eed;
+
+// Counts heap allocations for `--bench`, passing everything straight on to `System`. The binary
+// only installs it as its `#[global_allocator]` when built with the `bench` feature.
+pub struct CountingAllocator;
+
+pub static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BenchReport {
+    pub frames: u32,
+    pub matches: u32,
+    pub elapsed: Duration,
+    pub allocations: usize,
+}
+
+impl BenchReport {
+    pub fn fps(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl std::fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} frames ({} matches) in {:.3}s: {:.0} fps, {} allocations",
+            self.frames,
+            self.matches,
+            self.elapsed.as_secs_f64(),
+            self.fps(),
+            self.allocations,
+        )
+    }
+}
+
+// Runs `frames` frames of two bots playing each other on the headless app, starting a new match
+// whenever one ends.
+pub fn run_bench(frames: u32) -> BenchReport {
+    let mut app = build_headless_app();
+    app.insert_resource(GameRng::new(BENCH_SEED))
+        .insert_resource(BotHandles(vec![0, 1]));
+    let mut matches = 1;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..frames {
+        app.update();
+        let mut state = app
+            .world
+            .get_resource_mut::<State<AppState>>()
+            .expect("the headless app has an AppState");
+        if state.current() == &AppState::GameOver && state.set(AppState::InGame).is_ok() {
+            matches += 1;
+        }
+    }
+    BenchReport {
+        frames,
+        matches,
+        elapsed: start.elapsed(),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_short_bench_reports_a_positive_fps() {
+        let report = run_bench(100);
+        assert_eq!(report.frames, 100);
+        assert!(report.matches >= 1);
+        assert!(report.fps() > 0.);
+    }
+}
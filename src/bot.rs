@@ -0,0 +1,116 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+use crate::components::{
+    BotController, Direction, Food, FoodKind, FoodValue, Obstacle, Segment, Snake,
+};
+use crate::config::{BoundaryMode, GridConfig, GridPos};
+
+// Greedy choice of the next direction: among the turns that don't reverse the snake or step onto
+// a blocked cell, take the one that ends closest to any food. Ties go to the first direction in
+// `Direction::ALL`, so every peer picks the same move.
+pub fn choose_direction(
+    head: GridPos,
+    head_seg: &Segment,
+    blocked: &HashSet<GridPos>,
+    food: &[GridPos],
+    grid: &GridConfig,
+    boundary_mode: BoundaryMode,
+) -> Direction {
+    let next_cell = |dir: Direction| {
+        let cell = head.step(dir);
+        match boundary_mode {
+            BoundaryMode::Wall => cell,
+            BoundaryMode::Wrap => grid.wrap(cell),
+        }
+    };
+    let distance_to_food = |cell: GridPos| {
+        food.iter()
+            .map(|food| food.manhattan(cell))
+            .min()
+            .unwrap_or(0)
+    };
+
+    Direction::ALL
+        .iter()
+        .copied()
+        .filter(|dir| *dir != head_seg.curr_dir.opposite())
+        .filter(|dir| {
+            let cell = next_cell(*dir);
+            grid.in_bounds(cell) && !blocked.contains(&cell)
+        })
+        .min_by_key(|dir| {
+            // Without food in play, prefer to keep going straight.
+            let turn_penalty = if *dir == head_seg.curr_dir { 0 } else { 1 };
+            (distance_to_food(next_cell(*dir)), turn_penalty)
+        })
+        .unwrap_or(head_seg.curr_dir)
+}
+
+pub fn bot_steering(
+    bot_query: Query<&Snake, With<BotController>>,
+    mut segment_query: Query<&mut Segment>,
+    obstacle_query: Query<&Transform, With<Obstacle>>,
+    food_query: Query<(&Transform, Option<&FoodValue>), With<Food>>,
+    grid: Res<GridConfig>,
+    boundary_mode: Res<BoundaryMode>,
+) {
+    // Poison is steered around like any other obstacle.
+    let is_poison =
+        |value: Option<&FoodValue>| value.map_or(false, |value| value.kind == FoodKind::Poison);
+    let mut blocked = obstacle_query
+        .iter()
+        .map(|trans| grid.cell_at(trans.translation))
+        .chain(segment_query.iter().map(|seg| seg.pos))
+        .collect::<HashSet<_>>();
+    blocked.extend(
+        food_query
+            .iter()
+            .filter(|(_, value)| is_poison(*value))
+            .map(|(trans, _)| grid.cell_at(trans.translation)),
+    );
+    let mut food = food_query
+        .iter()
+        .filter(|(_, value)| !is_poison(*value))
+        .map(|(trans, _)| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    food.sort_unstable();
+
+    for snake in bot_query.iter() {
+        let entity = match snake.first() {
+            Some(entity) => *entity,
+            None => continue,
+        };
+        let mut head_seg = match segment_query.get_mut(entity) {
+            Ok(head_seg) => head_seg,
+            Err(_) => continue,
+        };
+        let head = head_seg.pos;
+        head_seg.next_dir =
+            choose_direction(head, &head_seg, &blocked, &food, &grid, *boundary_mode);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bot_turns_towards_food_on_its_right() {
+        let head = GridPos::new(3, 5);
+        let head_seg = Segment {
+            pos: head,
+            curr_dir: Direction::Up,
+            next_dir: Direction::Up,
+        };
+        let dir = choose_direction(
+            head,
+            &head_seg,
+            &HashSet::new(),
+            &[GridPos::new(6, 5)],
+            &GridConfig::default(),
+            BoundaryMode::Wall,
+        );
+        assert_eq!(dir, Direction::Right);
+    }
+}
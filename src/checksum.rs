@@ -0,0 +1,97 @@
+use bevy::prelude::*;
+use ggrs::InputStatus;
+
+use crate::components::{Player, Segment, Snake};
+use crate::config::GridPos;
+use crate::net::{GGRSConfig, PlayerInputs};
+use crate::resources::{FrameCount, GameRng, Score};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv_hash(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+// FNV-1a over the snakes' cells, by handle and then head to tail, followed by the score and the
+// RNG's seed and draw count. Everything is hashed in a fixed order and byte layout, so peers in the
+// same state get the same checksum whatever their platform.
+pub fn state_checksum(snakes: &[(usize, Vec<GridPos>)], score: u32, rng: &GameRng) -> u64 {
+    let mut snakes = snakes.iter().collect::<Vec<_>>();
+    snakes.sort_by_key(|(handle, _)| *handle);
+    let mut hash = FNV_OFFSET;
+    for (handle, cells) in snakes {
+        hash = fnv_hash(hash, &(*handle as u32).to_le_bytes());
+        hash = fnv_hash(hash, &(cells.len() as u32).to_le_bytes());
+        for cell in cells {
+            hash = fnv_hash(hash, &cell.x.to_le_bytes());
+            hash = fnv_hash(hash, &cell.y.to_le_bytes());
+        }
+    }
+    hash = fnv_hash(hash, &score.to_le_bytes());
+    hash = fnv_hash(hash, &rng.seed().to_le_bytes());
+    fnv_hash(hash, &rng.draws().to_le_bytes())
+}
+
+// Logs a checksum of the game state for every confirmed frame, at debug level. Comparing two
+// peers' logs finds the first frame they diverged on. Predicted frames are skipped since they may
+// still be rolled back.
+pub fn compute_state_checksum(
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    score: Res<Score>,
+    rng: Res<GameRng>,
+    frame_count: Res<FrameCount>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+) {
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    if !confirmed {
+        return;
+    }
+    let snakes = snake_query
+        .iter()
+        .map(|(player, snake)| {
+            let cells = snake
+                .iter()
+                .filter_map(|seg| segment_query.get(*seg).ok())
+                .map(|seg| seg.pos)
+                .collect();
+            (player.handle, cells)
+        })
+        .collect::<Vec<_>>();
+    let checksum = state_checksum(&snakes, **score, &rng);
+    debug!("Frame {} checksum {:016x}", **frame_count, checksum);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snakes() -> Vec<(usize, Vec<GridPos>)> {
+        vec![
+            (0, vec![GridPos::new(3, 6), GridPos::new(3, 5)]),
+            (1, vec![GridPos::new(6, 13)]),
+        ]
+    }
+
+    #[test]
+    fn only_a_different_state_changes_the_checksum() {
+        let rng = GameRng::new(7);
+        let checksum = state_checksum(&snakes(), 2, &rng);
+        let mut reordered = snakes();
+        reordered.reverse();
+        assert_eq!(state_checksum(&reordered, 2, &GameRng::new(7)), checksum);
+
+        let mut moved = snakes();
+        moved[1].1[0] = GridPos::new(6, 12);
+        assert_ne!(state_checksum(&moved, 2, &rng), checksum);
+        assert_ne!(state_checksum(&snakes(), 3, &rng), checksum);
+        let mut drawn = GameRng::new(7);
+        drawn.gen_index(4);
+        assert_ne!(state_checksum(&snakes(), 2, &drawn), checksum);
+    }
+}
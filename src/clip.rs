@@ -0,0 +1,212 @@
+use bevy::{app::AppExit, prelude::*};
+use gif::{Encoder, EncodingError, Frame, Repeat};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::components::{Food, Obstacle, Portal, PowerUp, Segment};
+use crate::config::{arg_value, GridConfig, Palette};
+use crate::AppState;
+
+// A frame is captured every 0.1 seconds, i.e. at 10 fps, which is also the delay each one is shown
+// for in the GIF.
+pub const GIF_CAPTURE_SECS: f32 = 0.1;
+// Every grid cell becomes a square of this many pixels.
+pub const GIF_SCALE: u32 = 4;
+// NeuQuant speed for building each frame's palette, 1 the slowest and best to 30 the fastest.
+pub const GIF_QUANTIZE_SPEED: i32 = 10;
+
+// Frames of the match being recorded with `--record-gif <file>`, written out when the app exits.
+// Bevy 0.7 can't read the rendered image back, so frames are drawn from the board instead: one
+// square per cell, in the color of the sprite on it.
+pub struct GifRecorder {
+    pub path: Option<String>,
+    pub timer: Timer,
+    pub width: u16,
+    pub height: u16,
+    // RGBA, `width` by `height` pixels each.
+    pub frames: Vec<Vec<u8>>,
+    // Set once capturing failed, after which the match carries on unrecorded.
+    pub failed: bool,
+}
+
+impl Default for GifRecorder {
+    fn default() -> GifRecorder {
+        GifRecorder {
+            path: None,
+            timer: Timer::from_seconds(GIF_CAPTURE_SECS, true),
+            width: 0,
+            height: 0,
+            frames: Vec::new(),
+            failed: false,
+        }
+    }
+}
+
+impl GifRecorder {
+    pub fn from_args(args: &[String]) -> GifRecorder {
+        GifRecorder {
+            path: arg_value(args, "--record-gif"),
+            ..default()
+        }
+    }
+
+    // The size of a frame showing `grid`, which a GIF has to fit in 16 bits.
+    pub fn frame_size(grid: &GridConfig) -> Result<(u16, u16), String> {
+        let size = |cells: u32| {
+            u16::try_from(cells * GIF_SCALE)
+                .ok()
+                .filter(|size| *size > 0)
+        };
+        match (size(grid.width), size(grid.height)) {
+            (Some(width), Some(height)) => Ok((width, height)),
+            _ => Err(format!(
+                "a {}x{} board doesn't fit in a GIF",
+                grid.width, grid.height
+            )),
+        }
+    }
+
+    // Paints `cells` over a `background` frame. Cells off the board, like the walls, are left out.
+    pub fn draw_frame(
+        grid: &GridConfig,
+        background: Color,
+        cells: impl Iterator<Item = (Vec3, Color)>,
+    ) -> Vec<u8> {
+        let (width, height) = (grid.width * GIF_SCALE, grid.height * GIF_SCALE);
+        let mut pixels = background
+            .as_rgba_f32()
+            .map(|channel| (channel.clamp(0., 1.) * 255.) as u8)
+            .repeat((width * height) as usize);
+        for (translation, color) in cells {
+            let cell = grid.cell_at(translation);
+            if !grid.in_bounds(cell) {
+                continue;
+            }
+            let rgba = color
+                .as_rgba_f32()
+                .map(|channel| (channel.clamp(0., 1.) * 255.) as u8);
+            // GIF rows run top to bottom, grid rows bottom to top.
+            let top = (grid.height - 1 - cell.y as u32) * GIF_SCALE;
+            let left = cell.x as u32 * GIF_SCALE;
+            for y in top..top + GIF_SCALE {
+                for x in left..left + GIF_SCALE {
+                    let i = ((y * width + x) * 4) as usize;
+                    pixels[i..i + 4].copy_from_slice(&rgba);
+                }
+            }
+        }
+        pixels
+    }
+
+    // Writes `frames` as a looping GIF, quantizing each to its own palette.
+    pub fn encode(
+        writer: impl Write,
+        width: u16,
+        height: u16,
+        frames: &[Vec<u8>],
+    ) -> Result<(), EncodingError> {
+        let mut encoder = Encoder::new(writer, width, height, &[])?;
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = (GIF_CAPTURE_SECS * 100.).round() as u16;
+        for pixels in frames {
+            let mut pixels = pixels.clone();
+            let mut frame = Frame::from_rgba_speed(width, height, &mut pixels, GIF_QUANTIZE_SPEED);
+            frame.delay = delay;
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), EncodingError> {
+        let file = File::create(path)?;
+        GifRecorder::encode(BufWriter::new(file), self.width, self.height, &self.frames)
+    }
+}
+
+pub fn capture_gif_frame(
+    time: Res<Time>,
+    app_state: Res<State<AppState>>,
+    mut recorder: ResMut<GifRecorder>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    sprite_query: Query<
+        (&Transform, &Sprite, Option<&Segment>),
+        Or<(
+            With<Segment>,
+            With<Food>,
+            With<Obstacle>,
+            With<PowerUp>,
+            With<Portal>,
+        )>,
+    >,
+) {
+    if recorder.path.is_none() || recorder.failed || app_state.current() != &AppState::InGame {
+        return;
+    }
+    if !recorder.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    match GifRecorder::frame_size(&grid) {
+        Ok((width, height)) => {
+            recorder.width = width;
+            recorder.height = height;
+        }
+        Err(err) => {
+            warn!("Not recording a GIF: {}", err);
+            recorder.failed = true;
+            return;
+        }
+    }
+    // Snakes go last, so a head is drawn over anything sharing its cell.
+    let mut sprites = sprite_query.iter().collect::<Vec<_>>();
+    sprites.sort_by_key(|(_, _, segment)| segment.is_some());
+    let frame = GifRecorder::draw_frame(
+        &grid,
+        palette.background,
+        sprites
+            .into_iter()
+            .map(|(trans, sprite, _)| (trans.translation, sprite.color)),
+    );
+    recorder.frames.push(frame);
+}
+
+// Runs in `CoreStage::Last`, after anything that could have asked the app to exit this frame.
+pub fn save_gif_on_exit(mut exit: EventReader<AppExit>, recorder: Res<GifRecorder>) {
+    if exit.iter().next().is_none() {
+        return;
+    }
+    let path = match &recorder.path {
+        Some(path) if !recorder.frames.is_empty() => path,
+        _ => return,
+    };
+    match recorder.save(path) {
+        Ok(()) => info!("Saved {} frames to {}", recorder.frames.len(), path),
+        Err(err) => warn!("Failed to save the GIF to {}: {}", path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_few_frames_encode_to_a_gif() {
+        let grid = GridConfig::default();
+        let (width, height) = GifRecorder::frame_size(&grid).unwrap();
+        let frames = (0..3)
+            .map(|y| {
+                let head = (grid.cell_center(3, y).extend(0.), Color::GREEN);
+                GifRecorder::draw_frame(&grid, Color::BLACK, std::iter::once(head))
+            })
+            .collect::<Vec<_>>();
+        assert!(frames
+            .iter()
+            .all(|frame| frame.len() == width as usize * height as usize * 4));
+
+        let mut bytes = Vec::new();
+        GifRecorder::encode(&mut bytes, width, height, &frames).unwrap();
+        assert!(bytes.starts_with(b"GIF89a"));
+        assert_eq!(bytes.last(), Some(&0x3b));
+    }
+}
@@ -0,0 +1,624 @@
+use bevy::prelude::*;
+use ggrs::PlayerHandle;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+use crate::config::{GridConfig, GridPos, Palette};
+use crate::net::INPUT_DIR_MASK;
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Reflect, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Default for Direction {
+    fn default() -> Direction {
+        Direction::Up
+    }
+}
+
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Up,
+        Direction::Down,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    // The step in grid cells taken when moving this way.
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+        }
+    }
+
+    pub fn from_bits(bits: u8) -> Direction {
+        match bits & INPUT_DIR_MASK {
+            0 => Direction::Up,
+            1 => Direction::Down,
+            2 => Direction::Left,
+            _ => Direction::Right,
+        }
+    }
+
+    pub fn from_key(key: KeyCode) -> Option<Direction> {
+        match key {
+            KeyCode::Up => Some(Direction::Up),
+            KeyCode::Down => Some(Direction::Down),
+            KeyCode::Left => Some(Direction::Left),
+            KeyCode::Right => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    pub fn from_wasd(key: KeyCode) -> Option<Direction> {
+        match key {
+            KeyCode::W => Some(Direction::Up),
+            KeyCode::S => Some(Direction::Down),
+            KeyCode::A => Some(Direction::Left),
+            KeyCode::D => Some(Direction::Right),
+            _ => None,
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+
+    // The way a right turn from this one leads.
+    pub fn clockwise(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Right,
+            Direction::Right => Direction::Down,
+            Direction::Down => Direction::Left,
+            Direction::Left => Direction::Up,
+        }
+    }
+
+    // The rotation, counter-clockwise, from a sprite drawn facing up to one facing this way.
+    pub fn angle(self) -> f32 {
+        use std::f32::consts::{FRAC_PI_2, PI};
+        match self {
+            Direction::Up => 0.,
+            Direction::Left => FRAC_PI_2,
+            Direction::Down => PI,
+            Direction::Right => -FRAC_PI_2,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Head;
+
+#[derive(Component)]
+pub struct MainCamera;
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Player {
+    pub handle: PlayerHandle,
+}
+
+// Points scored by one snake, next to the shared `Score` that the speed and high score follow.
+#[derive(Component, Copy, Clone, Debug, Default, Deref, DerefMut, Reflect)]
+#[reflect(Component)]
+pub struct PlayerScore(pub u32);
+
+// Marks a player entity whose snake is steered by `bot_steering` instead of GGRS input.
+#[derive(Component, Copy, Clone, Debug, Default)]
+pub struct BotController;
+
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Component)]
+pub struct Snake(pub Vec<Entity>);
+
+// Turns waiting to be taken, one per move, so two quick presses within a tick both count. It's
+// rolled back as a whole value.
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect_value(Component)]
+pub struct InputBuffer(pub VecDeque<Direction>);
+
+impl InputBuffer {
+    pub const CAPACITY: usize = 2;
+
+    // Queues `dir` unless the buffer is full or it isn't a turn from the direction the snake will
+    // be heading in by then.
+    pub fn push(&mut self, dir: Direction, curr_dir: Direction) {
+        let last = self.0.back().copied().unwrap_or(curr_dir);
+        if self.0.len() < InputBuffer::CAPACITY && dir != last && dir != last.opposite() {
+            self.0.push_back(dir);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Direction> {
+        self.0.pop_front()
+    }
+}
+
+// `pos` is where the segment really is. Its `Transform` only follows it for drawing, so the
+// simulation never depends on floating point positions adding up the same way on every peer.
+#[derive(Component, Copy, Clone, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct Segment {
+    pub pos: GridPos,
+    pub curr_dir: Direction,
+    pub next_dir: Direction,
+}
+
+impl Segment {
+    pub fn new_sprite_bundle(x: f32, y: f32, size: f32, color: Color) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(size, size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+}
+
+// How a segment is drawn so the body reads as one shape: straight cells fill the whole cell, a
+// corner is rounded on its outside, and the head and tail are rounded at their ends. Every image is
+// drawn travelling up, and a corner turning right.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SegmentShape {
+    Straight,
+    Corner,
+    Head,
+    Tail,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SegmentSprite {
+    pub shape: SegmentShape,
+    // The way the image is rotated to face.
+    pub facing: Direction,
+    // Set for a corner turning left, which is the right turn mirrored.
+    pub flip_x: bool,
+}
+
+impl SegmentSprite {
+    // `in_dir` is the way the segment moved into its cell and `out_dir` the way the segment ahead
+    // moved out of it. The head has nothing ahead and the tail nothing coming in behind it.
+    pub fn for_dirs(in_dir: Option<Direction>, out_dir: Option<Direction>) -> SegmentSprite {
+        let sprite = |shape, facing, flip_x| SegmentSprite {
+            shape,
+            facing,
+            flip_x,
+        };
+        match (in_dir, out_dir) {
+            (Some(in_dir), Some(out_dir)) if out_dir == in_dir.clockwise() => {
+                sprite(SegmentShape::Corner, in_dir, false)
+            }
+            (Some(in_dir), Some(out_dir)) if out_dir == in_dir.clockwise().opposite() => {
+                sprite(SegmentShape::Corner, in_dir, true)
+            }
+            (Some(in_dir), Some(_)) => sprite(SegmentShape::Straight, in_dir, false),
+            (Some(in_dir), None) => sprite(SegmentShape::Head, in_dir, false),
+            (None, Some(out_dir)) => sprite(SegmentShape::Tail, out_dir, false),
+            (None, None) => sprite(SegmentShape::Head, Direction::default(), false),
+        }
+    }
+}
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Food;
+
+impl Food {
+    pub fn new_sprite_bundle(x: f32, y: f32, size: f32, color: Color) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(size, size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Reflect, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoodKind {
+    Normal,
+    Golden,
+    // Takes `segments` off the snake's tail instead of adding them.
+    Poison,
+    // Drifts towards the nearest head, see `move_food`.
+    Magnet,
+}
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub struct FoodValue {
+    pub kind: FoodKind,
+    pub segments: u8,
+    pub score: u32,
+}
+
+impl Default for FoodValue {
+    fn default() -> FoodValue {
+        FoodValue::NORMAL
+    }
+}
+
+impl FoodValue {
+    pub const NORMAL: FoodValue = FoodValue {
+        kind: FoodKind::Normal,
+        segments: 1,
+        score: 1,
+    };
+    pub const GOLDEN: FoodValue = FoodValue {
+        kind: FoodKind::Golden,
+        segments: 3,
+        score: 5,
+    };
+    pub const POISON: FoodValue = FoodValue {
+        kind: FoodKind::Poison,
+        segments: 2,
+        score: 0,
+    };
+    pub const MAGNET: FoodValue = FoodValue {
+        kind: FoodKind::Magnet,
+        segments: 1,
+        score: 2,
+    };
+
+    pub fn color(&self, palette: &Palette) -> Color {
+        match self.kind {
+            FoodKind::Normal => palette.food,
+            FoodKind::Golden => palette.golden_food,
+            FoodKind::Poison => palette.poison_food,
+            FoodKind::Magnet => palette.magnet_food,
+        }
+    }
+
+    // Whether eating this would leave a snake of `len` segments without any.
+    pub fn kills(&self, len: usize) -> bool {
+        self.kind == FoodKind::Poison && len <= self.segments as usize
+    }
+}
+
+// A pickup waiting on the board until a head runs over it.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Reflect)]
+#[reflect(Component)]
+pub enum PowerUp {
+    Speed,
+    Shield,
+}
+
+impl Default for PowerUp {
+    fn default() -> PowerUp {
+        PowerUp::Speed
+    }
+}
+
+impl PowerUp {
+    pub const ALL: [PowerUp; 2] = [PowerUp::Speed, PowerUp::Shield];
+
+    pub fn color(&self, palette: &Palette) -> Color {
+        match self {
+            PowerUp::Speed => palette.power_up,
+            PowerUp::Shield => palette.shield,
+        }
+    }
+
+    pub fn new_sprite_bundle(x: f32, y: f32, size: f32, color: Color) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(size, size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+}
+
+// Frames left of a snake's speed boost. While they last the snake moves on its own timer, at half
+// the shared interval, and both are rolled back with the rest of the snake.
+#[derive(Component, Copy, Clone, Debug, Default, Reflect)]
+#[reflect(Component)]
+pub struct SpeedBoost {
+    pub frames: u32,
+    pub move_timer: f32,
+}
+
+impl SpeedBoost {
+    pub fn active(&self) -> bool {
+        self.frames > 0
+    }
+
+    // Seconds between this snake's moves when everyone else moves every `interval`.
+    pub fn interval(&self, interval: f32) -> f32 {
+        if self.active() {
+            interval / 2.
+        } else {
+            interval
+        }
+    }
+}
+
+// Frames left of a snake's shield. While they last nothing it runs into kills it.
+#[derive(Component, Copy, Clone, Debug, Default, Deref, DerefMut, Reflect)]
+#[reflect(Component)]
+pub struct Invincible(pub u32);
+
+impl Invincible {
+    pub fn active(&self) -> bool {
+        self.0 > 0
+    }
+}
+
+// The glow behind a segment, a child of it that's only shown while its snake is shielded.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ShieldGlow;
+
+impl ShieldGlow {
+    pub const SCALE: f32 = 1.4;
+    pub const Z: f32 = -0.5;
+}
+
+// The icons `AccessibilityConfig::colorblind_shapes` draws over food and obstacles, so each kind
+// can be told apart without its color.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ShapeIcon {
+    Circle,
+    Cross,
+    Star,
+    Ring,
+    Square,
+}
+
+impl ShapeIcon {
+    pub const ALL: [ShapeIcon; 5] = [
+        ShapeIcon::Circle,
+        ShapeIcon::Cross,
+        ShapeIcon::Star,
+        ShapeIcon::Ring,
+        ShapeIcon::Square,
+    ];
+
+    pub fn for_food(kind: FoodKind) -> ShapeIcon {
+        match kind {
+            FoodKind::Normal => ShapeIcon::Circle,
+            FoodKind::Golden => ShapeIcon::Star,
+            FoodKind::Poison => ShapeIcon::Cross,
+            FoodKind::Magnet => ShapeIcon::Ring,
+        }
+    }
+}
+
+// An icon drawn over a food or obstacle, as a child of it like the shield glow.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ShapeOverlay(pub ShapeIcon);
+
+impl ShapeOverlay {
+    pub const SCALE: f32 = 0.7;
+    pub const Z: f32 = 0.5;
+}
+
+// A fading mark on a cell a snake just moved off. Like the shield glow it's only drawn.
+#[derive(Component, Clone, Debug)]
+pub struct Trail {
+    pub timer: Timer,
+}
+
+impl Trail {
+    pub const ALPHA: f32 = 0.4;
+    pub const Z: f32 = -0.6;
+}
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Obstacle;
+
+impl Obstacle {
+    pub fn new_sprite_bundle(x: f32, y: f32, size: f32, color: Color) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(size, size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+}
+
+// One end of a portal pair, leading to the cell of the other end.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Portal {
+    pub exit: GridPos,
+}
+
+impl Portal {
+    pub fn new_sprite_bundle(x: f32, y: f32, size: f32, color: Color) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(size, size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+}
+
+// A faint line between cells. It's only drawn, below everything else, and never collides.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct GridLine;
+
+impl GridLine {
+    pub const WIDTH: f32 = 1.;
+    pub const Z: f32 = -1.;
+
+    // One line on every cell boundary, including the board's edges.
+    pub fn grid_lines(grid: &GridConfig, color: Color) -> Vec<SpriteBundle> {
+        let (half_width, half_height) = (grid.half_width(), grid.half_height());
+        let line = |x: f32, y: f32, width: f32, height: f32| SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(width, height)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, GridLine::Z),
+            ..default()
+        };
+        let vertical = (0..=grid.width).map(|i| {
+            let x = -half_width + i as f32 * grid.box_size;
+            line(x, 0., GridLine::WIDTH, 2. * half_height)
+        });
+        let horizontal = (0..=grid.height).map(|i| {
+            let y = -half_height + i as f32 * grid.box_size;
+            line(0., y, 2. * half_width, GridLine::WIDTH)
+        });
+        vertical.chain(horizontal).collect()
+    }
+}
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Wall;
+
+impl Wall {
+    pub fn new_sprite_bundle(
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        color: Color,
+    ) -> SpriteBundle {
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(width, height)),
+                ..default()
+            },
+            transform: Transform::from_xyz(x, y, 0.),
+            ..default()
+        }
+    }
+
+    pub fn boundary_walls(grid: &GridConfig, color: Color) -> Vec<(Wall, SpriteBundle)> {
+        let box_size = grid.box_size;
+        let half_width = grid.half_width();
+        let half_height = grid.half_height();
+        // The top and bottom walls extend over the corner cells so there's no seam to slip through.
+        let outer_width = 2. * half_width + 2. * box_size;
+        let inner_height = 2. * half_height;
+        vec![
+            (
+                Wall,
+                Wall::new_sprite_bundle(
+                    0.,
+                    half_height + box_size / 2.,
+                    outer_width,
+                    box_size,
+                    color,
+                ),
+            ),
+            (
+                Wall,
+                Wall::new_sprite_bundle(
+                    0.,
+                    -half_height - box_size / 2.,
+                    outer_width,
+                    box_size,
+                    color,
+                ),
+            ),
+            (
+                Wall,
+                Wall::new_sprite_bundle(
+                    -half_width - box_size / 2.,
+                    0.,
+                    box_size,
+                    inner_height,
+                    color,
+                ),
+            ),
+            (
+                Wall,
+                Wall::new_sprite_bundle(
+                    half_width + box_size / 2.,
+                    0.,
+                    box_size,
+                    inner_height,
+                    color,
+                ),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normal_food_is_plain_red() {
+        let color = FoodValue::NORMAL.color(&Palette::default());
+        assert_eq!(color, Color::rgb(1., 0., 0.));
+        assert_eq!(color.as_rgba_f32(), [1., 0., 0., 1.]);
+    }
+
+    #[test]
+    fn each_pair_of_directions_gets_its_segment_sprite() {
+        use Direction::*;
+        let sprite = |shape, facing, flip_x| SegmentSprite {
+            shape,
+            facing,
+            flip_x,
+        };
+        for (in_dir, out_dir, expected) in [
+            (Up, Up, sprite(SegmentShape::Straight, Up, false)),
+            (Left, Left, sprite(SegmentShape::Straight, Left, false)),
+            (Up, Right, sprite(SegmentShape::Corner, Up, false)),
+            (Up, Left, sprite(SegmentShape::Corner, Up, true)),
+            (Right, Down, sprite(SegmentShape::Corner, Right, false)),
+            (Down, Right, sprite(SegmentShape::Corner, Down, true)),
+            (Left, Up, sprite(SegmentShape::Corner, Left, false)),
+        ] {
+            assert_eq!(
+                SegmentSprite::for_dirs(Some(in_dir), Some(out_dir)),
+                expected,
+                "{:?} then {:?}",
+                in_dir,
+                out_dir
+            );
+        }
+        assert_eq!(
+            SegmentSprite::for_dirs(Some(Right), None),
+            sprite(SegmentShape::Head, Right, false)
+        );
+        assert_eq!(
+            SegmentSprite::for_dirs(None, Some(Down)),
+            sprite(SegmentShape::Tail, Down, false)
+        );
+    }
+}
@@ -0,0 +1,904 @@
+use bevy::{
+    prelude::*,
+    window::{PresentMode, WindowResizeConstraints},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::components::Direction;
+use crate::resources::BASE_MOVE_INTERVAL;
+use crate::FPS;
+
+pub const HEIGHT_BOXES: u32 = 20;
+pub const WIDTH_BOXES: u32 = 10;
+pub const BOX_SIZE: f32 = 26.;
+pub const MIN_PLAYERS: u8 = 2;
+pub const MAX_PLAYERS: u8 = 4;
+// Seconds between food spawns, and how many can be on the board at once, at Normal difficulty.
+pub const FOOD_SPAWN_INTERVAL: f32 = 2.;
+pub const MAX_ACTIVE_FOOD: u32 = 3;
+pub const DEFAULT_WINDOW_TITLE: &str = "Snek";
+// Small enough for any display, big enough that every cell stays a few pixels wide.
+pub const MIN_CELL_PIXELS: f32 = 8.;
+pub const MIN_TICK_RATE: u32 = 10;
+pub const MAX_TICK_RATE: u32 = 240;
+// The board sizes `--width` and `--height` accept. Smaller boards can't fit four snakes, and the
+// cap keeps every cell count well inside the `u16`s the lobby and GIF clips store them in.
+pub const MIN_GRID_CELLS: u32 = 6;
+pub const MAX_GRID_CELLS: u32 = 256;
+
+#[derive(Copy, Clone, Debug)]
+pub struct GridConfig {
+    pub width: u32,
+    pub height: u32,
+    pub box_size: f32,
+}
+
+impl Default for GridConfig {
+    fn default() -> GridConfig {
+        GridConfig {
+            width: WIDTH_BOXES,
+            height: HEIGHT_BOXES,
+            box_size: BOX_SIZE,
+        }
+    }
+}
+
+impl GridConfig {
+    pub fn from_args(args: &[String]) -> Result<GridConfig, String> {
+        let default = GridConfig::default();
+        let expected = format!(
+            "a number of cells from {} to {}",
+            MIN_GRID_CELLS, MAX_GRID_CELLS
+        );
+        let cells = |flag: &str, default: u32| {
+            parse_arg(args, flag, &expected, |cells| {
+                cells
+                    .parse()
+                    .ok()
+                    .filter(|cells| GridConfig::valid_cells(*cells))
+            })
+            .map(|cells| cells.unwrap_or(default))
+        };
+        Ok(GridConfig {
+            width: cells("--width", default.width)?,
+            height: cells("--height", default.height)?,
+            ..default
+        })
+    }
+
+    pub fn valid_cells(cells: u32) -> bool {
+        (MIN_GRID_CELLS..=MAX_GRID_CELLS).contains(&cells)
+    }
+
+    pub fn half_width(&self) -> f32 {
+        self.box_size * self.width as f32 / 2.
+    }
+
+    pub fn half_height(&self) -> f32 {
+        self.box_size * self.height as f32 / 2.
+    }
+
+    // Cells are indexed from (0, 0) in the bottom left corner and the board is centred on the
+    // origin, so even and odd sized boards are both symmetric.
+    pub fn cell_center(&self, x: i32, y: i32) -> Vec2 {
+        Vec2::new(
+            (x as f32 - self.width as f32 / 2. + 0.5) * self.box_size,
+            (y as f32 - self.height as f32 / 2. + 0.5) * self.box_size,
+        )
+    }
+
+    pub fn cell_at(&self, translation: Vec3) -> GridPos {
+        GridPos::from_translation(translation, self)
+    }
+
+    pub fn in_bounds(&self, pos: GridPos) -> bool {
+        (0..self.width as i32).contains(&pos.x) && (0..self.height as i32).contains(&pos.y)
+    }
+
+    // Moves a cell that left the board back in from the opposite edge.
+    pub fn wrap(&self, pos: GridPos) -> GridPos {
+        GridPos::new(
+            pos.x.rem_euclid(self.width as i32),
+            pos.y.rem_euclid(self.height as i32),
+        )
+    }
+
+    // Which ring of the board a cell is on, counting in from 0 at the edge.
+    pub fn ring(&self, pos: GridPos) -> i32 {
+        let (width, height) = (self.width as i32, self.height as i32);
+        pos.x
+            .min(pos.y)
+            .min(width - 1 - pos.x)
+            .min(height - 1 - pos.y)
+    }
+
+    // How many rings can close before there'd be nothing left to play on.
+    pub fn closable_rings(&self) -> u32 {
+        (self.width.min(self.height).saturating_sub(1)) / 2
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = GridPos> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        (0..width).flat_map(move |x| (0..height).map(move |y| GridPos::new(x, y)))
+    }
+}
+
+// A cell on the board, indexed like `GridConfig::cell_center`. Systems reason about positions in
+// cells and only convert to pixels when reading or writing a `Transform`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect, Serialize)]
+pub struct GridPos {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl GridPos {
+    pub fn new(x: i32, y: i32) -> GridPos {
+        GridPos { x, y }
+    }
+
+    pub fn to_translation(self, grid: &GridConfig) -> Vec3 {
+        grid.cell_center(self.x, self.y).extend(0.)
+    }
+
+    pub fn from_translation(translation: Vec3, grid: &GridConfig) -> GridPos {
+        GridPos::new(
+            (translation.x / grid.box_size + grid.width as f32 / 2. - 0.5).round() as i32,
+            (translation.y / grid.box_size + grid.height as f32 / 2. - 0.5).round() as i32,
+        )
+    }
+
+    pub fn step(self, dir: Direction) -> GridPos {
+        let (dx, dy) = dir.offset();
+        GridPos::new(self.x + dx, self.y + dy)
+    }
+
+    pub fn manhattan(self, other: GridPos) -> i32 {
+        (self.x - other.x).abs() + (self.y - other.y).abs()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BoundaryMode {
+    Wall,
+    Wrap,
+}
+
+impl Default for BoundaryMode {
+    fn default() -> BoundaryMode {
+        BoundaryMode::Wall
+    }
+}
+
+// Grid cells that get an `Obstacle` when a game starts, in order: the difficulty decides how many
+// of them are used. Cells outside the board are skipped.
+#[derive(Clone, Debug, Deref, DerefMut)]
+pub struct ObstacleLayout(pub Vec<GridPos>);
+
+impl Default for ObstacleLayout {
+    fn default() -> ObstacleLayout {
+        ObstacleLayout(vec![
+            GridPos::new(2, 4),
+            GridPos::new(7, 4),
+            GridPos::new(2, 15),
+            GridPos::new(7, 15),
+            GridPos::new(0, 9),
+            GridPos::new(9, 9),
+            GridPos::new(4, 12),
+            GridPos::new(5, 7),
+        ])
+    }
+}
+
+// Pairs of cells linked by a `Portal`: a head stepping onto either comes out of the other. Empty,
+// so there are no portals, unless the game is started with `--portals`.
+#[derive(Clone, Debug, Default, Deref, DerefMut)]
+pub struct PortalLayout(pub Vec<(GridPos, GridPos)>);
+
+impl PortalLayout {
+    pub fn from_args(args: &[String]) -> PortalLayout {
+        if args.iter().any(|arg| arg == "--portals") {
+            PortalLayout(vec![(GridPos::new(1, 17), GridPos::new(8, 2))])
+        } else {
+            PortalLayout::default()
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for Difficulty {
+    fn default() -> Difficulty {
+        Difficulty::Normal
+    }
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    // The byte peers swap in the lobby to agree on a difficulty.
+    pub fn bits(self) -> u8 {
+        match self {
+            Difficulty::Easy => 0,
+            Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+        }
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Difficulty> {
+        Difficulty::ALL.iter().copied().find(|d| d.bits() == bits)
+    }
+
+    pub fn from_args(args: &[String]) -> Result<Difficulty, String> {
+        let parse = |difficulty: &str| match difficulty {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        };
+        let difficulty = parse_arg(args, "--difficulty", "easy, normal or hard", parse)?;
+        Ok(difficulty.unwrap_or_default())
+    }
+}
+
+// The rules a `Difficulty` plays by, applied when a game starts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DifficultySettings {
+    // Seconds between moves before any food is eaten.
+    pub move_interval: f32,
+    pub food: FoodSettings,
+    // How many cells of the `ObstacleLayout` get an obstacle.
+    pub obstacles: usize,
+    // Length of the `GracePeriod`, in frames.
+    pub grace_frames: u32,
+}
+
+impl Default for DifficultySettings {
+    fn default() -> DifficultySettings {
+        DifficultySettings::for_difficulty(Difficulty::Normal)
+    }
+}
+
+impl DifficultySettings {
+    pub fn for_difficulty(difficulty: Difficulty) -> DifficultySettings {
+        match difficulty {
+            Difficulty::Easy => DifficultySettings {
+                move_interval: 0.14,
+                food: FoodSettings {
+                    spawn_interval: FOOD_SPAWN_INTERVAL * 3. / 4.,
+                    max_active: MAX_ACTIVE_FOOD + 1,
+                },
+                obstacles: 0,
+                grace_frames: 3 * FPS as u32,
+            },
+            Difficulty::Normal => DifficultySettings {
+                move_interval: BASE_MOVE_INTERVAL,
+                food: FoodSettings::default(),
+                obstacles: 4,
+                grace_frames: FPS as u32,
+            },
+            Difficulty::Hard => DifficultySettings {
+                move_interval: 0.07,
+                food: FoodSettings {
+                    spawn_interval: FOOD_SPAWN_INTERVAL * 3. / 2.,
+                    max_active: MAX_ACTIVE_FOOD - 1,
+                },
+                obstacles: 8,
+                grace_frames: 0,
+            },
+        }
+    }
+}
+
+// How often `spawn_food` adds food, and how much it lets pile up. Spawning waits while the board
+// already holds `max_active`, so the timer picks up where it left off once some is eaten.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FoodSettings {
+    pub spawn_interval: f32,
+    pub max_active: u32,
+}
+
+impl Default for FoodSettings {
+    fn default() -> FoodSettings {
+        FoodSettings {
+            spawn_interval: FOOD_SPAWN_INTERVAL,
+            max_active: MAX_ACTIVE_FOOD,
+        }
+    }
+}
+
+impl FoodSettings {
+    // The interval in whole frames, so the simulation counts it the same way on every peer.
+    pub fn spawn_frames(&self) -> u32 {
+        ((self.spawn_interval * FPS as f32).round() as u32).max(1)
+    }
+}
+
+// How many segments, head included, each snake starts with.
+#[derive(Copy, Clone, Debug, Deref, DerefMut)]
+pub struct StartLength(pub u32);
+
+impl Default for StartLength {
+    fn default() -> StartLength {
+        StartLength(1)
+    }
+}
+
+impl StartLength {
+    pub fn from_args(args: &[String]) -> Result<StartLength, String> {
+        let len = parse_arg(
+            args,
+            "--start-length",
+            "a number of segments above 0",
+            |len| len.parse::<u32>().ok().filter(|len| *len > 0),
+        )?;
+        Ok(len.map(StartLength).unwrap_or_default())
+    }
+}
+
+// How many snakes a session is for, each with its own GGRS player handle.
+#[derive(Copy, Clone, Debug, Deref, DerefMut, PartialEq, Eq)]
+pub struct MaxPlayers(pub u8);
+
+impl Default for MaxPlayers {
+    fn default() -> MaxPlayers {
+        MaxPlayers(MIN_PLAYERS)
+    }
+}
+
+impl MaxPlayers {
+    pub fn from_args(args: &[String]) -> Result<MaxPlayers, String> {
+        let expected = format!(
+            "a number of players from {} to {}",
+            MIN_PLAYERS, MAX_PLAYERS
+        );
+        let players = parse_arg(args, "--players", &expected, |players| {
+            players
+                .parse::<u8>()
+                .ok()
+                .filter(|players| (MIN_PLAYERS..=MAX_PLAYERS).contains(players))
+        })?;
+        Ok(players.map(MaxPlayers).unwrap_or_default())
+    }
+
+    pub fn count(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// Sprite and background colors, so they can be themed without touching the systems that spawn the
+// sprites. A theme file only needs the fields it changes, the rest keep their defaults.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    // One per player handle.
+    pub heads: [Color; MAX_PLAYERS as usize],
+    pub body: Color,
+    pub food: Color,
+    pub golden_food: Color,
+    pub poison_food: Color,
+    pub magnet_food: Color,
+    pub power_up: Color,
+    pub shield: Color,
+    pub portal: Color,
+    pub obstacle: Color,
+    pub wall: Color,
+    pub background: Color,
+    pub grid: Color,
+    // The icons drawn with `--colorblind-shapes`.
+    pub shape_overlay: Color,
+}
+
+// `Color::rgb` takes components in 0.0-1.0.
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            heads: [
+                Color::rgb(0.1, 0.45, 0.15),
+                Color::rgb(0.15, 0.3, 0.75),
+                Color::rgb(0.55, 0.2, 0.6),
+                Color::rgb(0.85, 0.45, 0.1),
+            ],
+            body: Color::rgb(0., 0., 0.),
+            food: Color::rgb(1., 0., 0.),
+            golden_food: Color::rgb(1., 0.84, 0.),
+            poison_food: Color::rgb(0.6, 0.9, 0.1),
+            magnet_food: Color::rgb(1., 0.4, 0.7),
+            power_up: Color::rgb(0.2, 0.85, 0.95),
+            shield: Color::rgba(1., 1., 1., 0.45),
+            portal: Color::rgb(0.35, 0.1, 0.55),
+            obstacle: Color::rgb(0.25, 0.25, 0.25),
+            wall: Color::rgb(0.2, 0.2, 0.2),
+            background: Color::rgb(0.4, 0.4, 0.4),
+            grid: Color::rgba(1., 1., 1., 0.08),
+            shape_overlay: Color::rgba(0., 0., 0., 0.7),
+        }
+    }
+}
+
+impl Palette {
+    pub fn head(&self, handle: usize) -> Color {
+        self.heads[handle % self.heads.len()]
+    }
+}
+
+// The keys the local player steers, pauses, restarts and quits with. Like `Palette`, a bindings file
+// only needs the keys it changes, e.g. `(up: W, down: S, left: A, right: D)`.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub pause: KeyCode,
+    pub restart: KeyCode,
+    // Only read on the game-over screen.
+    pub quit: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            up: KeyCode::Up,
+            down: KeyCode::Down,
+            left: KeyCode::Left,
+            right: KeyCode::Right,
+            pause: KeyCode::Escape,
+            restart: KeyCode::R,
+            quit: KeyCode::Escape,
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn direction(&self, key: KeyCode) -> Option<Direction> {
+        match key {
+            key if key == self.up => Some(Direction::Up),
+            key if key == self.down => Some(Direction::Down),
+            key if key == self.left => Some(Direction::Left),
+            key if key == self.right => Some(Direction::Right),
+            _ => None,
+        }
+    }
+}
+
+// Reads a RON config file such as a `Palette` theme or `KeyBindings`.
+pub fn load_ron<T: DeserializeOwned>(path: &str) -> Result<T, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    ron::from_str(&contents).map_err(|err| err.to_string())
+}
+
+// Hotseat play on one keyboard: both snakes are driven by `local_input` and the simulation runs on
+// a plain fixed timestep instead of a GGRS session.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct LocalMultiplayer(pub bool);
+
+impl LocalMultiplayer {
+    pub fn from_args(args: &[String]) -> LocalMultiplayer {
+        LocalMultiplayer(args.iter().any(|arg| arg == "--local"))
+    }
+}
+
+// A game for one snake on this machine, set with `--singleplayer`. Like local multiplayer it runs
+// on a plain fixed timestep, without matchbox or a GGRS session.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct SinglePlayer(pub bool);
+
+impl SinglePlayer {
+    pub fn from_args(args: &[String]) -> SinglePlayer {
+        SinglePlayer(args.iter().any(|arg| arg == "--singleplayer"))
+    }
+}
+
+// A race to `target_len` segments, set with `--time-trial <len>`. The `TrialTimer` runs from the
+// end of the countdown until the first snake is that long.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeTrial {
+    pub target_len: u32,
+}
+
+impl TimeTrial {
+    pub fn from_args(args: &[String]) -> Result<Option<TimeTrial>, String> {
+        let target_len = parse_arg(
+            args,
+            "--time-trial",
+            "a number of segments above 0",
+            |len| len.parse::<u32>().ok().filter(|len| *len > 0),
+        )?;
+        Ok(target_len.map(|target_len| TimeTrial { target_len }))
+    }
+}
+
+// Options that make the game easier to read. `colorblind_shapes`, set with `--colorblind-shapes`,
+// draws an icon over every food and obstacle so they don't differ only by color.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AccessibilityConfig {
+    pub colorblind_shapes: bool,
+}
+
+impl AccessibilityConfig {
+    pub fn from_args(args: &[String]) -> AccessibilityConfig {
+        AccessibilityConfig {
+            colorblind_shapes: args.iter().any(|arg| arg == "--colorblind-shapes"),
+        }
+    }
+}
+
+// The wall assist, set with `--assist` or in the settings form: a single-player snake about to
+// move off the board stays put that move instead of dying, until the player turns away. It's
+// ignored with more than one player so nobody gets an edge online.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct AssistMode(pub bool);
+
+impl AssistMode {
+    pub fn from_args(args: &[String]) -> AssistMode {
+        AssistMode(args.iter().any(|arg| arg == "--assist"))
+    }
+
+    pub fn active(&self, max_players: MaxPlayers) -> bool {
+        self.0 && max_players.count() == 1
+    }
+}
+
+// A calendar day in UTC, so everyone playing the daily challenge on the same day gets the same
+// one wherever they are.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UtcDate {
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl UtcDate {
+    pub fn new(year: i32, month: u32, day: u32) -> UtcDate {
+        UtcDate { year, month, day }
+    }
+
+    // Howard Hinnant's `civil_from_days`, for days counted from 1970-01-01.
+    pub fn from_days_since_epoch(days: i64) -> UtcDate {
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+        let year = (year_of_era + era * 400 + if month <= 2 { 1 } else { 0 }) as i32;
+        UtcDate::new(year, month, day)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn today() -> UtcDate {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        UtcDate::from_days_since_epoch((secs / 86_400) as i64)
+    }
+
+    // `SystemTime` isn't available in the browser.
+    #[cfg(target_arch = "wasm32")]
+    pub fn today() -> UtcDate {
+        let millis = js_sys::Date::now();
+        UtcDate::from_days_since_epoch((millis / 86_400_000.).floor() as i64)
+    }
+
+    // FNV-1a over the date written out as `YYYY-MM-DD`.
+    pub fn seed(self) -> u64 {
+        self.to_string()
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+}
+
+impl std::fmt::Display for UtcDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+// The daily challenge, set with `--daily`: every game on this machine starts its `GameRng` from
+// today's date, so the food falls the same way for everyone playing that day. An online match
+// keeps the seed its peers agreed on.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct DailyChallenge(pub Option<UtcDate>);
+
+impl DailyChallenge {
+    pub fn from_args(args: &[String]) -> DailyChallenge {
+        DailyChallenge(args.iter().any(|arg| arg == "--daily").then(UtcDate::today))
+    }
+}
+
+// The window's title, set with `--title <title>`, and a PNG to use as its icon with
+// `--icon <file>`. Without an icon, or if it can't be loaded, the window keeps the system default.
+// `--present-mode <fifo|mailbox|immediate>` picks how frames are presented, vsynced by default.
+// It only paces rendering: the simulation keeps its own fixed timestep whatever is picked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowSettings {
+    pub title: String,
+    pub icon: Option<String>,
+    pub present_mode: PresentMode,
+}
+
+impl Default for WindowSettings {
+    fn default() -> WindowSettings {
+        WindowSettings {
+            title: DEFAULT_WINDOW_TITLE.to_string(),
+            icon: None,
+            present_mode: PresentMode::Fifo,
+        }
+    }
+}
+
+impl WindowSettings {
+    pub fn from_args(args: &[String]) -> Result<WindowSettings, String> {
+        let present_mode = parse_arg(
+            args,
+            "--present-mode",
+            "fifo, mailbox or immediate",
+            parse_present_mode,
+        )?;
+        Ok(WindowSettings {
+            title: arg_value(args, "--title").unwrap_or_else(|| DEFAULT_WINDOW_TITLE.to_string()),
+            icon: arg_value(args, "--icon"),
+            present_mode: present_mode.unwrap_or(PresentMode::Fifo),
+        })
+    }
+
+    // Sized to fit the board with a cell and a half of margin on every side.
+    pub fn descriptor(&self, grid: &GridConfig) -> WindowDescriptor {
+        WindowDescriptor {
+            title: self.title.clone(),
+            present_mode: self.present_mode,
+            width: (grid.width + 3) as f32 * grid.box_size,
+            height: (grid.height + 3) as f32 * grid.box_size,
+            resizable: true,
+            resize_constraints: WindowResizeConstraints {
+                min_width: (grid.width + 3) as f32 * MIN_CELL_PIXELS,
+                min_height: (grid.height + 3) as f32 * MIN_CELL_PIXELS,
+                ..default()
+            },
+            ..default()
+        }
+    }
+}
+
+pub fn parse_present_mode(mode: &str) -> Option<PresentMode> {
+    match mode.to_ascii_lowercase().as_str() {
+        "fifo" | "vsync" => Some(PresentMode::Fifo),
+        "mailbox" => Some(PresentMode::Mailbox),
+        "immediate" => Some(PresentMode::Immediate),
+        _ => None,
+    }
+}
+
+// How many simulation ticks run per real second, set with `--tick-rate <ticks>`. GGRS steps the
+// session at this rate, so every peer has to pick the same one. It doesn't change how fast the
+// game plays: the move timers advance by a tick's length, and every duration written in frames at
+// `FPS` a second is scaled to ticks with `ticks`, so a higher rate only steps the game more finely.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SimConfig {
+    pub ticks_per_second: u32,
+}
+
+impl Default for SimConfig {
+    fn default() -> SimConfig {
+        SimConfig {
+            ticks_per_second: FPS as u32,
+        }
+    }
+}
+
+impl SimConfig {
+    pub fn new(ticks_per_second: u32) -> SimConfig {
+        SimConfig {
+            ticks_per_second: ticks_per_second.clamp(MIN_TICK_RATE, MAX_TICK_RATE),
+        }
+    }
+
+    pub fn from_args(args: &[String]) -> Result<SimConfig, String> {
+        let expected = format!(
+            "a number of ticks a second from {} to {}",
+            MIN_TICK_RATE, MAX_TICK_RATE
+        );
+        let ticks = parse_arg(args, "--tick-rate", &expected, |ticks| {
+            ticks
+                .parse::<u32>()
+                .ok()
+                .filter(|ticks| (MIN_TICK_RATE..=MAX_TICK_RATE).contains(ticks))
+        })?;
+        Ok(ticks.map(SimConfig::new).unwrap_or_default())
+    }
+
+    pub fn tick_secs(&self) -> f64 {
+        1. / self.ticks_per_second as f64
+    }
+
+    // The ticks that take as long as `frames` frames at `FPS` a second, rounded to the nearest one
+    // but never down to none.
+    pub fn ticks(&self, frames: u32) -> u32 {
+        let ticks = (frames as u64 * self.ticks_per_second as u64 + FPS as u64 / 2) / FPS as u64;
+        (ticks as u32).max(frames.min(1))
+    }
+
+    pub fn hundredths(&self, ticks: u32) -> u32 {
+        (ticks as u64 * 100 / self.ticks_per_second as u64) as u32
+    }
+}
+
+// Battle royale: with an interval set, the outermost open ring of the board closes every that
+// many frames once the countdown is over, walling off cells and killing whatever is caught in
+// them.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct ShrinkingArena(pub Option<u32>);
+
+impl ShrinkingArena {
+    pub fn from_args(args: &[String]) -> Result<ShrinkingArena, String> {
+        let frames = parse_arg(
+            args,
+            "--shrink-every",
+            "a number of seconds above 0",
+            |secs| {
+                secs.parse::<f32>()
+                    .ok()
+                    .filter(|secs| *secs > 0.)
+                    .map(|secs| ((secs * FPS as f32) as u32).max(1))
+            },
+        )?;
+        Ok(ShrinkingArena(frames))
+    }
+}
+
+// Player handles whose snakes get a `BotController` when they're spawned.
+#[derive(Clone, Debug, Default, Deref, DerefMut)]
+pub struct BotHandles(pub Vec<usize>);
+
+impl BoundaryMode {
+    pub fn from_args(args: &[String]) -> BoundaryMode {
+        if args.iter().any(|arg| arg == "--wrap") {
+            BoundaryMode::Wrap
+        } else {
+            BoundaryMode::Wall
+        }
+    }
+}
+
+// `flag`'s value turned into a `T` by `parse`, or `None` without the flag. A value `parse` turns
+// down is an error saying it must be `expected`.
+pub fn parse_arg<T>(
+    args: &[String],
+    flag: &str,
+    expected: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Result<Option<T>, String> {
+    match arg_value(args, flag) {
+        Some(value) => parse(&value)
+            .map(Some)
+            .ok_or_else(|| format!("invalid {} {:?}: it must be {}", flag, value, expected)),
+        None => Ok(None),
+    }
+}
+
+// Accepts both `--flag value` and `--flag=value`.
+pub fn arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == flag {
+            args.get(i + 1).cloned()
+        } else {
+            arg.strip_prefix(flag)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(str::to_string)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_edge_cells_are_on_the_board_and_the_next_ones_are_not() {
+        let grid = GridConfig::default();
+        let (right, top) = (grid.width as i32 - 1, grid.height as i32 - 1);
+        for cell in [
+            GridPos::new(0, 0),
+            GridPos::new(right, 0),
+            GridPos::new(0, top),
+            GridPos::new(right, top),
+        ] {
+            assert!(grid.in_bounds(cell), "{:?}", cell);
+        }
+        for cell in [
+            GridPos::new(-1, 5),
+            GridPos::new(right + 1, 5),
+            GridPos::new(5, -1),
+            GridPos::new(5, top + 1),
+        ] {
+            assert!(!grid.in_bounds(cell), "{:?}", cell);
+        }
+    }
+
+    #[test]
+    fn every_cell_round_trips_through_its_translation() {
+        for (width, height) in [(10, 20), (7, 9)] {
+            let grid = GridConfig {
+                width,
+                height,
+                ..GridConfig::default()
+            };
+            for cell in grid.cells() {
+                assert_eq!(grid.cell_at(cell.to_translation(&grid)), cell);
+            }
+        }
+    }
+
+    #[test]
+    fn hard_moves_faster_than_easy() {
+        let easy = DifficultySettings::for_difficulty(Difficulty::Easy);
+        let hard = DifficultySettings::for_difficulty(Difficulty::Hard);
+        assert!(hard.move_interval < easy.move_interval);
+        for difficulty in Difficulty::ALL {
+            assert_eq!(Difficulty::from_bits(difficulty.bits()), Some(difficulty));
+        }
+    }
+
+    #[test]
+    fn a_custom_title_reaches_the_window_descriptor() {
+        let args: Vec<String> = ["snek", "--title", "Snek Arena"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let grid = GridConfig::default();
+        let window = WindowSettings::from_args(&args).unwrap();
+        assert_eq!(window.descriptor(&grid).title, "Snek Arena");
+        let window = WindowSettings::from_args(&args[..1]).unwrap();
+        assert_eq!(window.descriptor(&grid).title, DEFAULT_WINDOW_TITLE);
+    }
+
+    #[test]
+    fn the_chosen_present_mode_reaches_the_window_descriptor() {
+        let grid = GridConfig::default();
+        for (flag, mode) in [
+            ("vsync", PresentMode::Fifo),
+            ("Mailbox", PresentMode::Mailbox),
+            ("immediate", PresentMode::Immediate),
+        ] {
+            let args = [
+                "snek".to_string(),
+                "--present-mode".to_string(),
+                flag.to_string(),
+            ];
+            let window = WindowSettings::from_args(&args).unwrap();
+            assert_eq!(window.descriptor(&grid).present_mode, mode, "{}", flag);
+        }
+        let args = ["snek", "--present-mode", "uncapped"].map(String::from);
+        assert!(WindowSettings::from_args(&args).is_err());
+    }
+
+    #[test]
+    fn the_same_day_always_gets_the_same_seed() {
+        let new_year = UtcDate::from_days_since_epoch(19_723);
+        assert_eq!(new_year, UtcDate::new(2024, 1, 1));
+        assert_eq!(new_year.to_string(), "2024-01-01");
+        assert_eq!(new_year.seed(), UtcDate::new(2024, 1, 1).seed());
+        assert_ne!(new_year.seed(), UtcDate::new(2024, 1, 2).seed());
+        assert_ne!(new_year.seed(), UtcDate::new(2023, 1, 1).seed());
+        assert_eq!(UtcDate::from_days_since_epoch(0), UtcDate::new(1970, 1, 1));
+    }
+}
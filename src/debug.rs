@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use ggrs::{InputStatus, PlayerHandle};
+use std::collections::VecDeque;
+
+use crate::components::{Food, Player, Segment, Snake};
+use crate::config::{GridConfig, GridPos, Palette};
+use crate::net::{GGRSConfig, PlayerInputs};
+use crate::resources::FrameCount;
+
+pub const HISTORY_FRAMES: usize = 300;
+pub const SCRUB_BACK_KEY: KeyCode = KeyCode::LBracket;
+pub const SCRUB_FORWARD_KEY: KeyCode = KeyCode::RBracket;
+// Drawn over the live board, which keeps running underneath.
+pub const GHOST_Z: f32 = 1.;
+pub const GHOST_ALPHA: f32 = 0.6;
+
+// `--debug` turns on developer tools such as the rewind view.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct DebugMode(pub bool);
+
+impl DebugMode {
+    pub fn from_args(args: &[String]) -> DebugMode {
+        DebugMode(args.iter().any(|arg| arg == "--debug"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameSnapshot {
+    pub frame: u32,
+    // By handle, each head first.
+    pub snakes: Vec<(PlayerHandle, Vec<GridPos>)>,
+    pub food: Vec<GridPos>,
+}
+
+// The last `HISTORY_FRAMES` confirmed frames, oldest first. `cursor` counts back from the newest
+// while scrubbing and is `None` when showing the live game.
+#[derive(Default)]
+pub struct FrameHistory {
+    frames: VecDeque<FrameSnapshot>,
+    cursor: Option<usize>,
+}
+
+impl FrameHistory {
+    pub fn push(&mut self, snapshot: FrameSnapshot) {
+        if self.frames.len() == HISTORY_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(snapshot);
+    }
+
+    pub fn scrub_back(&mut self) {
+        if self.frames.is_empty() {
+            return;
+        }
+        let back = self.cursor.map_or(0, |back| back + 1);
+        self.cursor = Some(back.min(self.frames.len() - 1));
+    }
+
+    // Stepping forward past the newest frame goes back to live.
+    pub fn scrub_forward(&mut self) {
+        self.cursor = self.cursor.and_then(|back| back.checked_sub(1));
+    }
+
+    pub fn current(&self) -> Option<&FrameSnapshot> {
+        let back = self.cursor?;
+        self.frames.get(self.frames.len().checked_sub(back + 1)?)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.cursor = None;
+    }
+}
+
+#[derive(Component)]
+pub struct HistoryGhost;
+
+// Only confirmed frames are kept, since a predicted one may be rolled back and simulated again.
+pub fn record_history(
+    debug: Option<Res<DebugMode>>,
+    mut history: ResMut<FrameHistory>,
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    food_query: Query<&Transform, With<Food>>,
+    grid: Res<GridConfig>,
+    frame_count: Res<FrameCount>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+) {
+    if !debug.map_or(false, |debug| **debug) {
+        return;
+    }
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    if !confirmed {
+        return;
+    }
+    let mut snakes = snake_query
+        .iter()
+        .map(|(player, snake)| {
+            let cells = snake
+                .iter()
+                .filter_map(|seg| segment_query.get(*seg).ok())
+                .map(|seg| seg.pos)
+                .collect();
+            (player.handle, cells)
+        })
+        .collect::<Vec<_>>();
+    snakes.sort_by_key(|(handle, _)| *handle);
+    let mut food = food_query
+        .iter()
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    food.sort_unstable();
+    history.push(FrameSnapshot {
+        frame: **frame_count,
+        snakes,
+        food,
+    });
+}
+
+pub fn scrub_history(keys: Res<Input<KeyCode>>, mut history: ResMut<FrameHistory>) {
+    if keys.just_pressed(SCRUB_BACK_KEY) {
+        history.scrub_back();
+    } else if keys.just_pressed(SCRUB_FORWARD_KEY) {
+        history.scrub_forward();
+    } else {
+        return;
+    }
+    match history.current() {
+        Some(snapshot) => info!("Rewound to frame {}", snapshot.frame),
+        None => info!("Back to the live game"),
+    }
+}
+
+// Draws the scrubbed-to frame as translucent ghosts over the board. Nothing is re-simulated.
+pub fn draw_history(
+    mut commands: Commands,
+    history: Res<FrameHistory>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    mut shown: Local<Option<u32>>,
+    ghost_query: Query<Entity, With<HistoryGhost>>,
+) {
+    let frame = history.current().map(|snapshot| snapshot.frame);
+    if frame == *shown {
+        return;
+    }
+    *shown = frame;
+    for entity in ghost_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    let snapshot = match history.current() {
+        Some(snapshot) => snapshot,
+        None => return,
+    };
+    let ghost = |cell: GridPos, mut color: Color| {
+        let pos = cell.to_translation(&grid);
+        color.set_a(GHOST_ALPHA);
+        SpriteBundle {
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::new(grid.box_size, grid.box_size)),
+                ..default()
+            },
+            transform: Transform::from_xyz(pos.x, pos.y, GHOST_Z),
+            ..default()
+        }
+    };
+    for (handle, cells) in snapshot.snakes.iter() {
+        for (i, cell) in cells.iter().enumerate() {
+            let color = if i == 0 {
+                palette.head(*handle)
+            } else {
+                palette.body
+            };
+            commands
+                .spawn_bundle(ghost(*cell, color))
+                .insert(HistoryGhost);
+        }
+    }
+    for cell in snapshot.food.iter() {
+        commands
+            .spawn_bundle(ghost(*cell, palette.food))
+            .insert(HistoryGhost);
+    }
+}
+
+pub fn clear_history(mut history: ResMut<FrameHistory>) {
+    history.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(frame: u32) -> FrameSnapshot {
+        FrameSnapshot {
+            frame,
+            snakes: vec![(0, vec![GridPos::new(3, frame as i32)])],
+            food: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn scrubbing_walks_back_through_the_stored_frames() {
+        let mut history = FrameHistory::default();
+        for frame in 0..HISTORY_FRAMES as u32 + 5 {
+            history.push(snapshot(frame));
+        }
+        assert_eq!(history.current(), None);
+        history.scrub_back();
+        assert_eq!(history.current(), Some(&snapshot(304)));
+        history.scrub_back();
+        history.scrub_back();
+        assert_eq!(history.current(), Some(&snapshot(302)));
+        history.scrub_forward();
+        assert_eq!(history.current(), Some(&snapshot(303)));
+
+        // The oldest frames past the cap are gone, so scrubbing stops at the first one kept.
+        for _ in 0..HISTORY_FRAMES {
+            history.scrub_back();
+        }
+        assert_eq!(history.current(), Some(&snapshot(5)));
+        for _ in 0..HISTORY_FRAMES {
+            history.scrub_forward();
+        }
+        assert_eq!(history.current(), None);
+    }
+}
@@ -0,0 +1,177 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use ggrs::{InputStatus, PlayerHandle};
+use serde::Serialize;
+
+use crate::components::{
+    Direction, Food, FoodKind, FoodValue, Player, PlayerScore, Segment, Snake,
+};
+use crate::config::{GridConfig, GridPos};
+use crate::net::{GGRSConfig, PlayerInputs};
+use crate::resources::{FrameCount, Score};
+
+// Whether every simulated frame is printed to stdout as a line of JSON, for bots and analysis
+// scripts reading the game from outside. Set with `--dump-state-on-tick`.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct DumpState(pub bool);
+
+impl DumpState {
+    pub fn from_args(args: &[String]) -> DumpState {
+        DumpState(args.iter().any(|arg| arg == "--dump-state-on-tick"))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SnakeStateJson {
+    pub handle: PlayerHandle,
+    pub direction: Direction,
+    pub score: u32,
+    // Head first.
+    pub cells: Vec<GridPos>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FoodStateJson {
+    pub cell: GridPos,
+    pub kind: FoodKind,
+}
+
+// One frame of the game in board cells. Snakes are listed by handle and food by cell, so the same
+// state always serializes to the same line.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct GameStateJson {
+    pub frame: u32,
+    pub width: u32,
+    pub height: u32,
+    pub score: u32,
+    pub snakes: Vec<SnakeStateJson>,
+    pub food: Vec<FoodStateJson>,
+}
+
+pub fn serialize_state(
+    frame: u32,
+    grid: &GridConfig,
+    score: u32,
+    mut snakes: Vec<SnakeStateJson>,
+    mut food: Vec<FoodStateJson>,
+) -> GameStateJson {
+    snakes.sort_by_key(|snake| snake.handle);
+    food.sort_by_key(|food| food.cell);
+    GameStateJson {
+        frame,
+        width: grid.width,
+        height: grid.height,
+        score,
+        snakes,
+        food,
+    }
+}
+
+// Everything `serialize_state` reads, for the systems that need the current frame as JSON.
+#[derive(SystemParam)]
+pub struct GameStateQuery<'w, 's> {
+    snake_query: Query<'w, 's, (&'static Player, &'static Snake, &'static PlayerScore)>,
+    segment_query: Query<'w, 's, &'static Segment>,
+    food_query: Query<'w, 's, (&'static Transform, Option<&'static FoodValue>), With<Food>>,
+    grid: Res<'w, GridConfig>,
+    score: Res<'w, Score>,
+    frame_count: Res<'w, FrameCount>,
+}
+
+impl<'w, 's> GameStateQuery<'w, 's> {
+    pub fn state(&self) -> GameStateJson {
+        let grid = &self.grid;
+        let snakes = self
+            .snake_query
+            .iter()
+            .map(|(player, snake, player_score)| {
+                let segments = snake
+                    .iter()
+                    .filter_map(|seg| self.segment_query.get(*seg).ok())
+                    .collect::<Vec<_>>();
+                SnakeStateJson {
+                    handle: player.handle,
+                    direction: segments
+                        .first()
+                        .map_or_else(Direction::default, |seg| seg.curr_dir),
+                    score: **player_score,
+                    cells: segments.iter().map(|seg| seg.pos).collect(),
+                }
+            })
+            .collect();
+        let food = self
+            .food_query
+            .iter()
+            .map(|(trans, value)| FoodStateJson {
+                cell: grid.cell_at(trans.translation),
+                kind: value.copied().unwrap_or_default().kind,
+            })
+            .collect();
+        serialize_state(**self.frame_count, grid, **self.score, snakes, food)
+    }
+}
+
+// Like the checksum, only confirmed frames are printed, so a line is never taken back by a
+// rollback. Offline every frame is confirmed.
+pub fn dump_state(
+    dump: Res<DumpState>,
+    game_state: GameStateQuery,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+) {
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    if !**dump || !confirmed {
+        return;
+    }
+    let state = game_state.state();
+    match serde_json::to_string(&state) {
+        Ok(json) => println!("{}", json),
+        Err(err) => warn!("Failed to serialize frame {}: {}", state.frame, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = concat!(
+        r#"{"frame":12,"width":10,"height":20,"score":1,"#,
+        r#""snakes":["#,
+        r#"{"handle":0,"direction":"up","score":1,"cells":[{"x":3,"y":6},{"x":3,"y":5}]},"#,
+        r#"{"handle":1,"direction":"down","score":0,"cells":[{"x":6,"y":13}]}"#,
+        r#"],"food":["#,
+        r#"{"cell":{"x":1,"y":2},"kind":"normal"},"#,
+        r#"{"cell":{"x":4,"y":9},"kind":"golden"}"#,
+        r#"]}"#,
+    );
+
+    #[test]
+    fn a_known_state_serializes_to_the_fixture() {
+        let snakes = vec![
+            SnakeStateJson {
+                handle: 1,
+                direction: Direction::Down,
+                score: 0,
+                cells: vec![GridPos::new(6, 13)],
+            },
+            SnakeStateJson {
+                handle: 0,
+                direction: Direction::Up,
+                score: 1,
+                cells: vec![GridPos::new(3, 6), GridPos::new(3, 5)],
+            },
+        ];
+        let food = vec![
+            FoodStateJson {
+                cell: GridPos::new(4, 9),
+                kind: FoodKind::Golden,
+            },
+            FoodStateJson {
+                cell: GridPos::new(1, 2),
+                kind: FoodKind::Normal,
+            },
+        ];
+        let state = serialize_state(12, &GridConfig::default(), 1, snakes, food);
+        assert_eq!(serde_json::to_string(&state).unwrap(), FIXTURE);
+    }
+}
@@ -0,0 +1,76 @@
+use bevy::{
+    input::gamepad::{GamepadEvent, GamepadEventType},
+    prelude::*,
+};
+
+use crate::components::Direction;
+
+// How far the left stick has to be pushed along an axis before it counts as a turn.
+pub const STICK_THRESHOLD: f32 = 0.5;
+
+// The gamepad steering the local player: the first one connected, replaced by another connected
+// pad if it's unplugged.
+#[derive(Default, Deref, DerefMut)]
+pub struct LocalGamepad(pub Option<Gamepad>);
+
+pub fn track_gamepads(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    gamepads: Res<Gamepads>,
+    mut local_gamepad: ResMut<LocalGamepad>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected if local_gamepad.is_none() => {
+                info!("Steering with gamepad {:?}", gamepad);
+                **local_gamepad = Some(*gamepad);
+            }
+            GamepadEventType::Disconnected if **local_gamepad == Some(*gamepad) => {
+                **local_gamepad = gamepads.iter().copied().find(|pad| pad != gamepad);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn stick_direction(x: f32, y: f32) -> Option<Direction> {
+    if x.abs() < STICK_THRESHOLD && y.abs() < STICK_THRESHOLD {
+        None
+    } else if x.abs() > y.abs() && x > 0. {
+        Some(Direction::Right)
+    } else if x.abs() > y.abs() {
+        Some(Direction::Left)
+    } else if y > 0. {
+        Some(Direction::Up)
+    } else {
+        Some(Direction::Down)
+    }
+}
+
+// A D-pad press, or the left stick being pushed into a new direction. Holding the stick only
+// turns once, like holding a key.
+pub fn gamepad_direction(
+    gamepad: Gamepad,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+    last_stick: &mut Option<Direction>,
+) -> Option<Direction> {
+    let dpad = [
+        (GamepadButtonType::DPadUp, Direction::Up),
+        (GamepadButtonType::DPadDown, Direction::Down),
+        (GamepadButtonType::DPadLeft, Direction::Left),
+        (GamepadButtonType::DPadRight, Direction::Right),
+    ]
+    .iter()
+    .find(|(button, _)| buttons.just_pressed(GamepadButton(gamepad, *button)))
+    .map(|(_, dir)| *dir);
+
+    let axis = |axis_type| axes.get(GamepadAxis(gamepad, axis_type)).unwrap_or(0.);
+    let stick = stick_direction(
+        axis(GamepadAxisType::LeftStickX),
+        axis(GamepadAxisType::LeftStickY),
+    );
+    let stick_turn = stick.filter(|dir| *last_stick != Some(*dir));
+    *last_stick = stick;
+
+    dpad.or(stick_turn)
+}
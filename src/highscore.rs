@@ -0,0 +1,167 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::config::{SimConfig, TimeTrial};
+use crate::resources::{Score, TrialTimer};
+
+pub const HIGH_SCORE_FILE: &str = "high_score.json";
+pub const BEST_TIMES_FILE: &str = "best_times.json";
+
+// The best score on this machine, kept across runs. It's saved as JSON in the platform config
+// directory, or in the browser's local storage on wasm.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deref, DerefMut, Serialize, Deserialize)]
+pub struct HighScore(pub u32);
+
+impl HighScore {
+    // Returns whether `score` beat the stored value and replaced it.
+    pub fn update(&mut self, score: u32) -> bool {
+        if score > self.0 {
+            self.0 = score;
+            true
+        } else {
+            false
+        }
+    }
+
+    // A missing or unreadable high score starts over from zero.
+    pub fn load() -> HighScore {
+        storage::read(HIGH_SCORE_FILE)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        storage::write(HIGH_SCORE_FILE, &json)
+    }
+}
+
+// The fastest time trial on this machine for each target length, in hundredths of a second so it
+// holds at any tick rate, stored like the high score.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deref, DerefMut, Serialize, Deserialize)]
+pub struct BestTimes(pub BTreeMap<u32, u32>);
+
+impl BestTimes {
+    pub fn best(&self, target_len: u32) -> Option<u32> {
+        self.0.get(&target_len).copied()
+    }
+
+    // Returns whether `hundredths` beat the stored time for `target_len`, or was the first one.
+    pub fn update(&mut self, target_len: u32, hundredths: u32) -> bool {
+        if self
+            .best(target_len)
+            .map_or(false, |best| best <= hundredths)
+        {
+            return false;
+        }
+        self.0.insert(target_len, hundredths);
+        true
+    }
+
+    pub fn load() -> BestTimes {
+        storage::read(BEST_TIMES_FILE)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|err| err.to_string())?;
+        storage::write(BEST_TIMES_FILE, &json)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod storage {
+    use directories::ProjectDirs;
+    use std::path::PathBuf;
+
+    fn path(file: &str) -> Option<PathBuf> {
+        ProjectDirs::from("", "", "game2d").map(|dirs| dirs.config_dir().join(file))
+    }
+
+    pub fn read(file: &str) -> Option<String> {
+        std::fs::read_to_string(path(file)?).ok()
+    }
+
+    pub fn write(file: &str, json: &str) -> Result<(), String> {
+        let path = path(file).ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        std::fs::write(path, json).map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod storage {
+    fn local_storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+
+    pub fn read(file: &str) -> Option<String> {
+        local_storage()?.get_item(file).ok()?
+    }
+
+    pub fn write(file: &str, json: &str) -> Result<(), String> {
+        local_storage()
+            .ok_or("no local storage")?
+            .set_item(file, json)
+            .map_err(|err| format!("{:?}", err))
+    }
+}
+
+pub fn load_high_score(mut commands: Commands) {
+    commands.insert_resource(HighScore::load());
+}
+
+pub fn save_high_score(score: Res<Score>, mut high_score: ResMut<HighScore>) {
+    if high_score.update(**score) {
+        if let Err(err) = high_score.save() {
+            warn!("Failed to save the high score: {}", err);
+        }
+    }
+}
+
+pub fn load_best_times(mut commands: Commands) {
+    commands.insert_resource(BestTimes::load());
+}
+
+// Like the high score, a finished time trial is saved once the game is over.
+pub fn save_best_time(
+    trial: Option<Res<TimeTrial>>,
+    timer: Res<TrialTimer>,
+    sim: Res<SimConfig>,
+    mut best_times: ResMut<BestTimes>,
+) {
+    let trial = match trial {
+        Some(trial) if timer.finished => trial,
+        _ => return,
+    };
+    if best_times.update(trial.target_len, sim.hundredths(timer.frames)) {
+        if let Err(err) = best_times.save() {
+            warn!("Failed to save the best time: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_a_higher_score_replaces_the_high_score() {
+        let mut high_score = HighScore(5);
+        assert!(high_score.update(8));
+        assert_eq!(high_score, HighScore(8));
+        assert!(!high_score.update(3));
+        assert!(!high_score.update(8));
+        assert_eq!(high_score, HighScore(8));
+
+        let json = serde_json::to_string(&high_score).unwrap();
+        assert_eq!(
+            serde_json::from_str::<HighScore>(&json).unwrap(),
+            HighScore(8)
+        );
+    }
+}
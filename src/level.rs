@@ -0,0 +1,142 @@
+use bevy::prelude::*;
+use bevy_ggrs::*;
+
+use crate::components::{Direction, Food, FoodValue};
+use crate::config::{GridConfig, GridPos, Palette};
+use crate::resources::Occupancy;
+use crate::systems::spawn_positions;
+
+pub const LEVEL_WALL: char = '#';
+pub const LEVEL_EMPTY: char = '.';
+pub const LEVEL_OBSTACLE: char = 'O';
+pub const LEVEL_SPAWN: char = 'S';
+pub const LEVEL_FOOD: char = 'F';
+
+// A hand-drawn board loaded with `--level <file>`, which replaces the procedural obstacles, spawn
+// corners and first food. The map is one line per row, top row first, and sits in the bottom left
+// corner of a board at least as big as it. Cells are listed in reading order, so the first `S`
+// is player 1's.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Level {
+    pub width: u32,
+    pub height: u32,
+    pub walls: Vec<GridPos>,
+    pub obstacles: Vec<GridPos>,
+    pub spawns: Vec<GridPos>,
+    pub food: Vec<GridPos>,
+}
+
+impl Level {
+    // Trailing blank lines are dropped and short rows count as empty past their end.
+    pub fn parse(map: &str) -> Result<Level, String> {
+        let rows = map.lines().map(str::trim_end).collect::<Vec<_>>();
+        let rows = match rows.iter().rposition(|row| !row.is_empty()) {
+            Some(last) => &rows[..=last],
+            None => return Err("the level is empty".to_string()),
+        };
+        let width = rows.iter().map(|row| row.chars().count()).max();
+        let mut level = Level {
+            width: width.unwrap_or(0) as u32,
+            height: rows.len() as u32,
+            ..default()
+        };
+        for (i, row) in rows.iter().enumerate() {
+            let y = level.height as i32 - 1 - i as i32;
+            for (x, tile) in row.chars().enumerate() {
+                let cell = GridPos::new(x as i32, y);
+                match tile {
+                    LEVEL_WALL => level.walls.push(cell),
+                    LEVEL_EMPTY => {}
+                    LEVEL_OBSTACLE => level.obstacles.push(cell),
+                    LEVEL_SPAWN => level.spawns.push(cell),
+                    LEVEL_FOOD => level.food.push(cell),
+                    _ => {
+                        return Err(format!(
+                            "unknown tile {:?} on line {}, column {}",
+                            tile,
+                            i + 1,
+                            x + 1
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(level)
+    }
+
+    pub fn check_fits(&self, grid: &GridConfig) -> Result<(), String> {
+        if self.width > grid.width || self.height > grid.height {
+            return Err(format!(
+                "a {}x{} level doesn't fit on a {}x{} board",
+                self.width, self.height, grid.width, grid.height
+            ));
+        }
+        Ok(())
+    }
+
+    // The level's spawns in order, each facing the centre vertically like the usual corners. Any
+    // players left over take the usual spawns that are still free.
+    pub fn spawn_positions(
+        &self,
+        player_count: usize,
+        grid: &GridConfig,
+    ) -> Vec<(GridPos, Direction)> {
+        let mut positions = self
+            .spawns
+            .iter()
+            .filter(|cell| grid.in_bounds(**cell))
+            .take(player_count)
+            .map(|cell| {
+                let dir = if cell.y < grid.height as i32 / 2 {
+                    Direction::Up
+                } else {
+                    Direction::Down
+                };
+                (*cell, dir)
+            })
+            .collect::<Vec<_>>();
+        for fallback in spawn_positions(player_count, grid) {
+            if positions.len() >= player_count {
+                break;
+            }
+            if !positions.iter().any(|(cell, _)| *cell == fallback.0) {
+                positions.push(fallback);
+            }
+        }
+        positions
+    }
+}
+
+pub fn load_level(path: &str) -> Result<Level, String> {
+    let map = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    Level::parse(&map)
+}
+
+// Puts a normal food on every `F` of the level when a game starts, before the timer spawns any.
+pub fn spawn_level_food(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    level: Option<Res<Level>>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    mut occupancy: ResMut<Occupancy>,
+) {
+    let level = match level {
+        Some(level) => level,
+        None => return,
+    };
+    for cell in level.food.iter().filter(|cell| grid.in_bounds(**cell)) {
+        occupancy.insert(*cell);
+        let pos = cell.to_translation(&grid);
+        commands
+            .spawn_bundle(Food::new_sprite_bundle(
+                pos.x,
+                pos.y,
+                grid.box_size,
+                FoodValue::NORMAL.color(&palette),
+            ))
+            .insert(Food)
+            .insert(FoodValue::NORMAL)
+            .insert(Rollback::new(rip.next_id()));
+    }
+}
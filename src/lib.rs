@@ -0,0 +1,537 @@
+use bevy::{
+    core::FixedTimestep, diagnostic::FrameTimeDiagnosticsPlugin,
+    ecs::schedule::IntoSystemDescriptor, prelude::*, transform::TransformSystem,
+};
+use bevy_ggrs::*;
+use ggrs::InputStatus;
+
+pub mod agent;
+pub mod audio;
+pub mod bench;
+pub mod bot;
+pub mod checksum;
+pub mod clip;
+pub mod components;
+pub mod config;
+pub mod debug;
+pub mod export;
+pub mod gamepad;
+pub mod highscore;
+pub mod level;
+pub mod net;
+pub mod replay;
+pub mod resources;
+pub mod systems;
+pub mod touch;
+pub mod ui;
+
+use agent::{agent_input, send_agent_state, start_agent_listener, AgentPort, AGENT_HANDLE};
+use audio::{load_audio, play_sounds, queue_sounds, SoundQueue};
+use bot::bot_steering;
+use checksum::compute_state_checksum;
+use clip::{capture_gif_frame, save_gif_on_exit, GifRecorder};
+use components::{
+    FoodValue, InputBuffer, Invincible, PlayerScore, PowerUp, Segment, Snake, SpeedBoost,
+};
+use config::{
+    AccessibilityConfig, AssistMode, BotHandles, BoundaryMode, DailyChallenge, Difficulty,
+    DifficultySettings, FoodSettings, GridConfig, KeyBindings, LocalMultiplayer, MaxPlayers,
+    ObstacleLayout, Palette, PortalLayout, ShrinkingArena, SimConfig, SinglePlayer, StartLength,
+    WindowSettings,
+};
+use debug::{clear_history, draw_history, record_history, scrub_history, DebugMode, FrameHistory};
+use export::{dump_state, DumpState};
+use gamepad::{track_gamepads, LocalGamepad};
+use highscore::{load_best_times, load_high_score, save_best_time, save_high_score, HighScore};
+use level::spawn_level_food;
+use net::{
+    handle_session_events, input, local_input, offline_handle, offline_step, start_matchbox_socket,
+    store_offline_input, wait_for_players, BoxInput, GGRSConfig, LobbyError, MatchboxConfig,
+    NetConfig, PlayerInputs, PlayingOffline, SessionKind, SignalingStatus, WaitingForPeer,
+};
+use replay::{
+    record_inputs, replay_input, save_on_game_over, start_recording, RecordPath, ReplayPlayback,
+    ReplayRecorder,
+};
+use resources::{
+    ClosedRings, FoodTimer, FrameCount, GameRng, GameSpeed, GracePeriod, MatchOutcome, MatchOver,
+    MoveTimer, Occupancy, Paused, RestartRequested, Score, ScreenShake, SegmentPool,
+    StartCountdown, TrialTimer,
+};
+use systems::*;
+use ui::{
+    cleanup_game_over_screen, cleanup_lobby_text, cleanup_menu, cleanup_settings, menu_interaction,
+    multiplayer_scoreboard, pause_menu_interaction, play_offline_interaction, settings_interaction,
+    setup_countdown_text, setup_daily_text, setup_game_over_screen, setup_lobby_text, setup_menu,
+    setup_multiplayer_scoreboard, setup_network_stats_text, setup_peer_wait_text,
+    setup_perf_overlay_text, setup_scoreboard, setup_settings, setup_trial_timer_text,
+    show_play_offline_button, update_countdown_text, update_lobby_text, update_minimap,
+    update_network_stats, update_pause_menu, update_peer_wait_text, update_perf_overlay,
+    update_scoreboard, update_trial_timer_text, ShowNetworkStats, ShowPerfOverlay,
+};
+
+pub const FPS: usize = 60;
+pub const ROLLBACK_DEFAULT: &str = "rollback_default";
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum AppState {
+    MainMenu,
+    Settings,
+    Lobby,
+    InGame,
+    GameOver,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct FixedUpdateStage;
+
+// Installs the whole game: the GGRS rollback schedule running `simulation_stage`, the app states
+// and their systems, and default resources. Insert a `GridConfig`, `BoundaryMode`, `Difficulty`
+// or `MatchboxConfig` before adding the plugin to override the defaults, `SinglePlayer(true)` to
+// play alone without a network session, `LocalMultiplayer(true)` to play both snakes on one
+// keyboard, an `AgentPort` to let an external program play, or a `ReplayPlayback` to watch a
+// recorded match.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        let local = app
+            .world
+            .get_resource::<LocalMultiplayer>()
+            .map_or(false, |local| **local);
+        let single = app
+            .world
+            .get_resource::<SinglePlayer>()
+            .map_or(false, |single| **single);
+        let agent_port = app
+            .world
+            .get_resource::<AgentPort>()
+            .copied()
+            .unwrap_or_default();
+        if app.world.contains_resource::<ReplayPlayback>() {
+            build_offline(app, replay_input.before(update_dir));
+        } else if agent_port.is_some() {
+            build_agent(app);
+        } else if local {
+            build_offline(app, local_input.before(update_dir));
+        } else if single {
+            build_singleplayer(app);
+        } else {
+            build_online(app);
+        }
+
+        app.init_resource::<GridConfig>()
+            .init_resource::<BoundaryMode>()
+            .init_resource::<ObstacleLayout>()
+            .init_resource::<PortalLayout>()
+            .init_resource::<BotHandles>()
+            .init_resource::<Palette>()
+            .init_resource::<KeyBindings>()
+            .init_resource::<LocalGamepad>()
+            .init_resource::<StartLength>()
+            .init_resource::<MaxPlayers>()
+            .init_resource::<Difficulty>()
+            .init_resource::<DifficultySettings>()
+            .init_resource::<FoodSettings>()
+            .init_resource::<GracePeriod>()
+            .init_resource::<ShrinkingArena>()
+            .init_resource::<ClosedRings>()
+            .init_resource::<LocalMultiplayer>()
+            .init_resource::<SinglePlayer>()
+            .init_resource::<AssistMode>()
+            .init_resource::<AccessibilityConfig>()
+            .init_resource::<DailyChallenge>()
+            .init_resource::<WindowSettings>()
+            .init_resource::<SimConfig>()
+            .init_resource::<MatchboxConfig>()
+            .init_resource::<NetConfig>()
+            .init_resource::<SessionKind>()
+            .init_resource::<RecordPath>()
+            .init_resource::<ReplayRecorder>()
+            .init_resource::<GifRecorder>()
+            .init_resource::<DumpState>()
+            .init_resource::<FrameCount>()
+            .init_resource::<Occupancy>()
+            .init_resource::<StartCountdown>()
+            .init_resource::<Score>()
+            .init_resource::<MatchOutcome>()
+            .init_resource::<HighScore>()
+            .init_resource::<FoodTimer>()
+            .init_resource::<GameSpeed>()
+            .init_resource::<MoveTimer>()
+            .init_resource::<Paused>()
+            .init_resource::<RestartRequested>()
+            .init_resource::<ShowNetworkStats>()
+            .init_resource::<ShowPerfOverlay>()
+            .init_resource::<GameRng>()
+            .init_resource::<SegmentPool>()
+            .init_resource::<TrialTimer>()
+            .init_resource::<MatchOver>()
+            .init_resource::<SoundQueue>()
+            .init_resource::<ScreenShake>()
+            .add_event::<CollisionEvent>()
+            .add_event::<GameEvent>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::InGame)
+                    .with_system(apply_difficulty)
+                    .with_system(setup.after(apply_difficulty))
+                    .with_system(spawn_obstacles.after(apply_difficulty))
+                    .with_system(spawn_portals)
+                    .with_system(spawn_players)
+                    .with_system(spawn_level_food)
+                    .with_system(reset_daily_seed)
+                    .with_system(start_recording.after(reset_daily_seed)),
+            )
+            .add_system_set(SystemSet::on_update(AppState::InGame).with_system(toggle_pause))
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver)
+                    .with_system(save_on_game_over)
+                    .with_system(save_high_score)
+                    .with_system(save_best_time)
+                    .with_system(setup_game_over_screen.after(save_high_score)),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::GameOver)
+                    .with_system(restart_on_key)
+                    .with_system(quit_on_key),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup_game))
+            .add_system_set(
+                SystemSet::on_exit(AppState::GameOver)
+                    .with_system(cleanup_game)
+                    .with_system(cleanup_game_over_screen),
+            )
+            .add_plugin(FrameTimeDiagnosticsPlugin::default())
+            .add_startup_system(setup_camera)
+            .add_startup_system(setup_segment_images)
+            .add_startup_system(setup_shape_images)
+            .add_startup_system(setup_scoreboard)
+            .add_startup_system(setup_multiplayer_scoreboard)
+            .add_startup_system(setup_countdown_text)
+            .add_startup_system(setup_network_stats_text)
+            .add_startup_system(setup_daily_text)
+            .add_startup_system(setup_perf_overlay_text)
+            .add_startup_system(setup_trial_timer_text)
+            .add_startup_system(load_audio)
+            .add_startup_system(load_high_score)
+            .add_startup_system(load_best_times)
+            .add_system(draw_grid)
+            .add_system(pulse_boosted_snakes)
+            .add_system(shape_segments)
+            .add_system(spawn_shield_glow)
+            .add_system(draw_shield_glow)
+            .add_system(spawn_shape_overlays)
+            .add_system(despawn_orphaned_overlays)
+            .add_system(spawn_trails)
+            .add_system(fade_trails)
+            .add_system(fit_camera_on_resize)
+            .add_system(set_window_icon)
+            .add_system(screen_shake)
+            .add_system(track_gamepads)
+            .add_system(update_scoreboard)
+            .add_system(multiplayer_scoreboard)
+            .add_system(update_countdown_text)
+            .add_system(update_pause_menu)
+            .add_system(pause_menu_interaction)
+            .add_system(update_network_stats)
+            .add_system(update_minimap)
+            .add_system(update_perf_overlay)
+            .add_system(update_trial_timer_text)
+            .add_system(play_sounds)
+            .add_system(capture_gif_frame)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                sync_segment_transforms.before(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate_movement
+                    .after(sync_segment_transforms)
+                    .before(TransformSystem::TransformPropagate),
+            )
+            .add_system_to_stage(CoreStage::Last, save_gif_on_exit);
+
+        let debug = app
+            .world
+            .get_resource::<DebugMode>()
+            .map_or(false, |debug| **debug);
+        // `record_history` is part of the simulation either way, but does nothing without `--debug`.
+        app.init_resource::<FrameHistory>();
+        if debug {
+            app.add_system_set(SystemSet::on_enter(AppState::InGame).with_system(clear_history))
+                .add_system(scrub_history)
+                .add_system(draw_history);
+        }
+    }
+}
+
+fn build_online(app: &mut App) {
+    let sim = app
+        .world
+        .get_resource::<SimConfig>()
+        .copied()
+        .unwrap_or_default();
+    GGRSPlugin::<GGRSConfig>::new()
+        .with_update_frequency(sim.ticks_per_second as usize)
+        .with_input_system(input)
+        .register_rollback_type::<Transform>()
+        .register_rollback_type::<Segment>()
+        .register_rollback_type::<Snake>()
+        .register_rollback_type::<PlayerScore>()
+        .register_rollback_type::<InputBuffer>()
+        .register_rollback_type::<FoodValue>()
+        .register_rollback_type::<PowerUp>()
+        .register_rollback_type::<SpeedBoost>()
+        .register_rollback_type::<Invincible>()
+        .register_rollback_type::<Score>()
+        .register_rollback_type::<FoodTimer>()
+        .register_rollback_type::<GameRng>()
+        .register_rollback_type::<GameSpeed>()
+        .register_rollback_type::<MoveTimer>()
+        .register_rollback_type::<FrameCount>()
+        .register_rollback_type::<Occupancy>()
+        .register_rollback_type::<StartCountdown>()
+        .register_rollback_type::<ClosedRings>()
+        .register_rollback_type::<TrialTimer>()
+        .register_rollback_type::<MatchOver>()
+        .with_rollback_schedule(
+            Schedule::default().with_stage(ROLLBACK_DEFAULT, simulation_stage()),
+        )
+        .build(app);
+
+    // The matchbox socket is only opened once Play is picked from the menu and the match settings
+    // are filled in. A match cut short by a disconnect comes back to the menu too, leaving its board
+    // to be cleaned up. If the signaling server can't be reached, the lobby offers a game on this
+    // machine instead, which runs on its own stage outside GGRS.
+    app.init_resource::<WaitingForPeer>()
+        .init_resource::<SignalingStatus>()
+        .init_resource::<LobbyError>()
+        .init_resource::<PlayingOffline>()
+        .add_state(AppState::MainMenu)
+        .add_system_set(
+            SystemSet::on_enter(AppState::MainMenu)
+                .with_system(setup_menu)
+                .with_system(cleanup_game),
+        )
+        .add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(menu_interaction))
+        .add_system_set(SystemSet::on_exit(AppState::MainMenu).with_system(cleanup_menu))
+        .add_system_set(SystemSet::on_enter(AppState::Settings).with_system(setup_settings))
+        .add_system_set(SystemSet::on_update(AppState::Settings).with_system(settings_interaction))
+        .add_system_set(SystemSet::on_exit(AppState::Settings).with_system(cleanup_settings))
+        .add_system_set(
+            SystemSet::on_enter(AppState::Lobby)
+                .with_system(start_matchbox_socket)
+                .with_system(setup_lobby_text),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Lobby)
+                .with_system(wait_for_players)
+                .with_system(update_lobby_text)
+                .with_system(show_play_offline_button)
+                .with_system(play_offline_interaction),
+        )
+        .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(cleanup_lobby_text))
+        .add_startup_system(setup_peer_wait_text)
+        .add_system(handle_session_events)
+        .add_system(update_peer_wait_text)
+        .add_stage_after(
+            CoreStage::Update,
+            FixedUpdateStage,
+            simulation_stage()
+                .with_run_criteria(offline_step)
+                .with_system(
+                    offline_handle
+                        .chain(input)
+                        .chain(store_offline_input)
+                        .before(update_dir),
+                ),
+        );
+}
+
+// Without a network session there's no lobby and no rollback: `input_system` writes straight into
+// `PlayerInputs` ahead of the simulation each frame.
+fn build_offline<Params>(app: &mut App, input_system: impl IntoSystemDescriptor<Params>) {
+    let players = app
+        .world
+        .get_resource::<MaxPlayers>()
+        .copied()
+        .unwrap_or_default();
+    let sim = app
+        .world
+        .get_resource::<SimConfig>()
+        .copied()
+        .unwrap_or_default();
+    let inputs: PlayerInputs<GGRSConfig> =
+        vec![(BoxInput { inp: 0 }, InputStatus::Confirmed); players.count()];
+    app.insert_resource(RollbackIdProvider::default())
+        .insert_resource(inputs)
+        .add_state(AppState::InGame)
+        .add_stage_after(
+            CoreStage::Update,
+            FixedUpdateStage,
+            simulation_stage()
+                .with_run_criteria(FixedTimestep::step(sim.tick_secs()))
+                .with_system(input_system),
+        );
+}
+
+// One snake, steered like it would be online, with the food seeded from `thread_rng` since there
+// are no peers to agree with.
+fn build_singleplayer(app: &mut App) {
+    app.insert_resource(MaxPlayers(1));
+    if !app.world.contains_resource::<GameRng>() {
+        app.insert_resource(GameRng::from_entropy());
+    }
+    build_offline(
+        app,
+        offline_handle
+            .chain(input)
+            .chain(store_offline_input)
+            .before(update_dir),
+    );
+}
+
+// An external agent on a local TCP port plays `AGENT_HANDLE` without a network session, and bots
+// play everyone else unless `BotHandles` was set already.
+fn build_agent(app: &mut App) {
+    let players = app
+        .world
+        .get_resource::<MaxPlayers>()
+        .copied()
+        .unwrap_or_default();
+    let has_bots = app
+        .world
+        .get_resource::<BotHandles>()
+        .map_or(false, |bots| !bots.is_empty());
+    if !has_bots {
+        let bots = (0..players.count()).filter(|handle| *handle != AGENT_HANDLE);
+        app.insert_resource(BotHandles(bots.collect()));
+    }
+    build_offline(app, agent_input.before(update_dir));
+    app.init_resource::<AgentPort>()
+        .add_startup_system(start_agent_listener)
+        .add_system_to_stage(
+            FixedUpdateStage,
+            send_agent_state
+                .after(tick_frame)
+                .after(add_segment)
+                .after(spawn_power_up),
+        );
+}
+
+// The systems advancing the game by one frame, shared by the GGRS rollback schedule and the
+// headless app.
+pub fn simulation_stage() -> SystemStage {
+    SystemStage::parallel()
+        .with_system(tick_frame)
+        .with_system(update_dir)
+        .with_system(record_inputs.after(tick_frame).after(update_dir))
+        .with_system(bot_steering)
+        .with_system(tick_countdown)
+        .with_system(
+            move_snake
+                .after(tick_frame)
+                .after(update_dir)
+                .after(bot_steering)
+                .after(tick_countdown),
+        )
+        .with_system(move_food.after(tick_frame).after(move_snake))
+        .with_system(shrink_arena.after(move_food).after(tick_frame))
+        .with_system(check_collisions.after(move_food).after(tick_frame))
+        .with_system(add_segment.after(check_collisions))
+        .with_system(game_over.after(check_collisions).after(shrink_arena))
+        .with_system(queue_sounds.after(game_over))
+        .with_system(
+            queue_screen_shake
+                .after(check_collisions)
+                .after(shrink_arena),
+        )
+        .with_system(update_game_speed.after(add_segment))
+        .with_system(tick_trial_timer.after(add_segment))
+        // Only once `check_collisions` has eaten this frame's food and freed its cells.
+        .with_system(spawn_food.after(check_collisions).after(add_segment))
+        .with_system(tick_invincibility.after(check_collisions))
+        .with_system(collect_power_ups.after(tick_invincibility))
+        .with_system(
+            spawn_power_up
+                .after(collect_power_ups)
+                .after(spawn_food)
+                .after(tick_frame),
+        )
+        .with_system(record_history.after(tick_frame).after(spawn_power_up))
+        .with_system(
+            compute_state_checksum
+                .after(tick_frame)
+                .after(update_game_speed)
+                .after(spawn_power_up),
+        )
+        .with_system(
+            dump_state
+                .after(tick_frame)
+                .after(add_segment)
+                .after(spawn_power_up),
+        )
+}
+
+// Builds the simulation without a window, renderer or network session so it can be stepped one
+// frame per `app.update()`. Inputs are read from the `PlayerInputs` resource, which starts out
+// with no direction pressed for every player.
+pub fn build_headless_app() -> App {
+    let players = MaxPlayers::default();
+    let inputs: PlayerInputs<GGRSConfig> =
+        vec![(BoxInput { inp: 0 }, InputStatus::Confirmed); players.count()];
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(GridConfig::default())
+        .insert_resource(BoundaryMode::Wall)
+        .insert_resource(ObstacleLayout::default())
+        .insert_resource(PortalLayout::default())
+        .insert_resource(DumpState::default())
+        .insert_resource(BotHandles::default())
+        .insert_resource(Palette::default())
+        .insert_resource(StartLength::default())
+        .insert_resource(players)
+        .insert_resource(AssistMode::default())
+        .insert_resource(Difficulty::default())
+        .insert_resource(DifficultySettings::default())
+        .insert_resource(FoodSettings::default())
+        .insert_resource(GracePeriod::default())
+        .insert_resource(SimConfig::default())
+        .insert_resource(ShrinkingArena::default())
+        .insert_resource(ClosedRings::default())
+        .insert_resource(Score::default())
+        .insert_resource(MatchOutcome::default())
+        .insert_resource(MatchOver::default())
+        .insert_resource(FoodTimer::default())
+        .insert_resource(GameSpeed::default())
+        .insert_resource(MoveTimer::default())
+        .insert_resource(FrameCount::default())
+        .insert_resource(Occupancy::default())
+        .insert_resource(SegmentPool::default())
+        .insert_resource(TrialTimer::default())
+        .insert_resource(StartCountdown::default())
+        .insert_resource(ReplayRecorder::default())
+        .insert_resource(Paused::default())
+        .insert_resource(RestartRequested::default())
+        .insert_resource(GameRng::default())
+        .insert_resource(SoundQueue::default())
+        .insert_resource(ScreenShake::default())
+        .insert_resource(FrameHistory::default())
+        .insert_resource(RollbackIdProvider::default())
+        .insert_resource(inputs)
+        .add_event::<CollisionEvent>()
+        .add_event::<GameEvent>()
+        .add_state(AppState::InGame)
+        .add_system_set(
+            SystemSet::on_enter(AppState::InGame)
+                .with_system(apply_difficulty)
+                .with_system(setup.after(apply_difficulty))
+                .with_system(spawn_obstacles.after(apply_difficulty))
+                .with_system(spawn_portals)
+                .with_system(spawn_players),
+        )
+        .add_system_set(SystemSet::on_exit(AppState::InGame).with_system(cleanup_game))
+        .add_system_set(SystemSet::on_exit(AppState::GameOver).with_system(cleanup_game))
+        .add_stage_after(CoreStage::Update, FixedUpdateStage, simulation_stage());
+    app
+}
@@ -0,0 +1,1032 @@
+use bevy::{
+    ecs::schedule::ShouldRun,
+    input::{keyboard::KeyboardInput, ElementState},
+    prelude::*,
+    tasks::IoTaskPool,
+};
+use bevy_ggrs::*;
+use bytemuck::{Pod, Zeroable};
+use ggrs::{
+    Config, GGRSEvent, InputStatus, Message, NonBlockingSocket, P2PSession, PlayerHandle,
+    PlayerType, SessionBuilder,
+};
+use matchbox_socket::WebRtcSocket;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::components::{Direction, InputBuffer, Player, Segment, Snake};
+use crate::config::{
+    arg_value, parse_arg, Difficulty, GridConfig, KeyBindings, MaxPlayers, SimConfig,
+};
+use crate::gamepad::{gamepad_direction, LocalGamepad};
+use crate::resources::GameRng;
+use crate::touch::{swipe_direction, SwipeTracker};
+use crate::AppState;
+
+pub const INPUT_SIZE: usize = std::mem::size_of::<u8>();
+pub const DEFAULT_SERVER_URL: &str = "ws://127.0.0.1:3536";
+pub const ROOM_CODE_LEN: usize = 6;
+pub const ROOM_PATH_PREFIX: &str = "/game2d_";
+// Bits 0-1 of `BoxInput::inp` hold the direction, bit 2 marks that a direction was pressed. A
+// second turn pressed in the same frame goes in the same layout shifted up by `INPUT_TURN_BITS`.
+pub const INPUT_DIR_MASK: u8 = 0b011;
+pub const INPUT_DIR_SET: u8 = 0b100;
+pub const INPUT_TURN_BITS: u8 = 3;
+// How long a peer can go silent before the match is given up on.
+pub const DISCONNECT_TIMEOUT_SECS: u64 = 10;
+// GGRS's own defaults, kept unless changed with `--max-prediction` or `--input-delay`.
+pub const DEFAULT_MAX_PREDICTION_FRAMES: usize = 8;
+pub const DEFAULT_INPUT_DELAY: usize = 0;
+pub const SIGNALING_ERROR: &str = "Could not reach signaling server";
+// The lobby's settings go over the unreliable channel, so each peer's pick is sent again until
+// that peer acks it, and the match is given up on if the swap isn't done in time.
+pub const SETTINGS_RESEND_SECS: f64 = 0.25;
+pub const SETTINGS_TIMEOUT_SECS: f64 = 10.;
+pub const SETTINGS_ERROR: &str = "Could not agree on match settings with the other players";
+// GGRS's own messages take at least 6 bytes, so neither these nor the 5 bytes of settings can be
+// mistaken for one.
+const SETTINGS_ACK: u8 = 0xff;
+const SPECTATOR_HELLO: u8 = 0xfe;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Pod, Zeroable)]
+pub struct BoxInput {
+    pub inp: u8,
+}
+
+#[derive(Debug)]
+pub struct GGRSConfig;
+
+impl Config for GGRSConfig {
+    type Input = BoxInput;
+    type State = u8;
+    type Address = String;
+}
+
+// The per-frame inputs bevy_ggrs hands to the rollback schedule, indexed by `PlayerHandle`.
+pub type PlayerInputs<T> = Vec<(<T as Config>::Input, InputStatus)>;
+
+pub fn encode_input(dir: Option<Direction>) -> u8 {
+    match dir {
+        Some(dir) => INPUT_DIR_SET | dir.bits(),
+        None => 0,
+    }
+}
+
+pub fn decode_input(inp: u8) -> Option<Direction> {
+    if inp & INPUT_DIR_SET == 0 {
+        return None;
+    }
+    Some(Direction::from_bits(inp))
+}
+
+// Packs up to `InputBuffer::CAPACITY` turns, in the order they were pressed.
+pub fn encode_turns(turns: &[Direction]) -> u8 {
+    turns
+        .iter()
+        .take(InputBuffer::CAPACITY)
+        .enumerate()
+        .fold(0, |inp, (i, dir)| {
+            inp | encode_input(Some(*dir)) << (i as u8 * INPUT_TURN_BITS)
+        })
+}
+
+pub fn decode_turns(inp: u8) -> Vec<Direction> {
+    (0..InputBuffer::CAPACITY as u8)
+        .map_while(|i| decode_input(inp >> (i * INPUT_TURN_BITS)))
+        .collect()
+}
+
+// FNV-1a over the sorted peer ids, so both peers derive the same seed without extra messages.
+pub fn session_seed(mut peer_ids: Vec<String>) -> u64 {
+    peer_ids.sort();
+    peer_ids
+        .iter()
+        .flat_map(|id| id.bytes().chain(std::iter::once(0)))
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
+// What a match is played with: the difficulty and board size picked in the settings form, or on
+// the command line.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MatchSettings {
+    pub difficulty: Difficulty,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl MatchSettings {
+    pub fn new(difficulty: Difficulty, grid: &GridConfig) -> MatchSettings {
+        MatchSettings {
+            difficulty,
+            width: grid.width,
+            height: grid.height,
+        }
+    }
+
+    // The difficulty's byte, then the width and height as little-endian `u16`s.
+    pub fn to_bytes(self) -> Box<[u8]> {
+        let size = |cells: u32| u16::try_from(cells).unwrap_or(u16::MAX).to_le_bytes();
+        let ([w0, w1], [h0, h1]) = (size(self.width), size(self.height));
+        Box::new([self.difficulty.bits(), w0, w1, h0, h1])
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<MatchSettings> {
+        match *bytes {
+            [difficulty, w0, w1, h0, h1] => Some(MatchSettings {
+                difficulty: Difficulty::from_bits(difficulty)?,
+                width: u16::from_le_bytes([w0, w1]) as u32,
+                height: u16::from_le_bytes([h0, h1]) as u32,
+            })
+            .filter(|settings| {
+                GridConfig::valid_cells(settings.width) && GridConfig::valid_cells(settings.height)
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn apply(self, difficulty: &mut Difficulty, grid: &mut GridConfig) {
+        *difficulty = self.difficulty;
+        grid.width = self.width;
+        grid.height = self.height;
+    }
+}
+
+// What goes over the socket in the lobby: a player's settings, a spectator's hello in their place,
+// and the acks for both.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum LobbyPacket {
+    Settings(MatchSettings),
+    SpectatorHello,
+    Ack,
+}
+
+impl LobbyPacket {
+    fn parse(bytes: &[u8]) -> Option<LobbyPacket> {
+        match *bytes {
+            [SETTINGS_ACK] => Some(LobbyPacket::Ack),
+            [SPECTATOR_HELLO] => Some(LobbyPacket::SpectatorHello),
+            _ => MatchSettings::from_bytes(bytes).map(LobbyPacket::Settings),
+        }
+    }
+}
+
+// The settings every peer picked before the lobby, swapped over the socket while waiting in it.
+// The game is played with the ones picked by the host, the peer with the lowest id, so every peer,
+// spectators included, settles on the same rules and the same board.
+#[derive(Default)]
+pub struct LobbySettings {
+    acked_by: HashSet<String>,
+    received: HashMap<String, MatchSettings>,
+    // Peers that said hello as spectators. They're never waited on for settings.
+    spectators: HashSet<String>,
+    now: f64,
+    last_sent: Option<f64>,
+    // When the last peer connected, to time the swap from.
+    connected_at: Option<f64>,
+}
+
+impl LobbySettings {
+    // Sends this peer's pick, or a spectator's hello without one, to peers that haven't acked it
+    // yet, every `SETTINGS_RESEND_SECS`, and collects and acks theirs. A pick heard again is acked
+    // again, in case the first ack was lost.
+    fn exchange(&mut self, socket: &mut WebRtcSocket, own: Option<MatchSettings>, now: f64) {
+        self.now = now;
+        let resend = self
+            .last_sent
+            .map_or(true, |last_sent| now - last_sent >= SETTINGS_RESEND_SECS);
+        if resend {
+            self.last_sent = Some(now);
+            let packet = own.map_or_else(
+                || Box::new([SPECTATOR_HELLO]) as Box<[u8]>,
+                MatchSettings::to_bytes,
+            );
+            for peer in socket.connected_peers() {
+                if !self.acked_by.contains(&peer) {
+                    socket.send(packet.clone(), peer);
+                }
+            }
+        }
+        for (peer, packet) in socket.receive() {
+            if self.read(&peer, &packet) {
+                socket.send(Box::new([SETTINGS_ACK]), peer);
+            }
+        }
+    }
+
+    // Takes in one packet from `peer`, returning whether it has to be acked.
+    fn read(&mut self, peer: &str, packet: &[u8]) -> bool {
+        match LobbyPacket::parse(packet) {
+            Some(LobbyPacket::Settings(settings)) => {
+                self.received.insert(peer.to_string(), settings);
+                true
+            }
+            Some(LobbyPacket::SpectatorHello) => {
+                self.spectators.insert(peer.to_string());
+                true
+            }
+            Some(LobbyPacket::Ack) => {
+                self.acked_by.insert(peer.to_string());
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn is_spectator(&self, peer: &str) -> bool {
+        self.spectators.contains(peer)
+    }
+
+    fn has_heard_from<'a>(&self, mut peers: impl Iterator<Item = &'a String>) -> bool {
+        peers.all(|peer| self.received.contains_key(peer))
+    }
+
+    fn is_acked_by<'a>(&self, mut peers: impl Iterator<Item = &'a String>) -> bool {
+        peers.all(|peer| self.acked_by.contains(peer))
+    }
+
+    // Whether the swap has gone on for longer than `SETTINGS_TIMEOUT_SECS` since every peer
+    // connected. The clock only runs while they all are.
+    fn timed_out(&mut self, connected: bool) -> bool {
+        if !connected {
+            self.connected_at = None;
+            return false;
+        }
+        let connected_at = *self.connected_at.get_or_insert(self.now);
+        self.now - connected_at > SETTINGS_TIMEOUT_SECS
+    }
+}
+
+// The lobby's socket once GGRS has it. A peer still in the lobby keeps sending its settings or
+// hello until this one acks them, so they're acked here, and kept from GGRS along with late acks,
+// since it can't read either.
+pub struct SessionSocket(WebRtcSocket);
+
+impl NonBlockingSocket<String> for SessionSocket {
+    fn send_to(&mut self, msg: &Message, addr: &String) {
+        self.0.send_to(msg, addr);
+    }
+
+    fn receive_all_messages(&mut self) -> Vec<(String, Message)> {
+        let mut messages = Vec::new();
+        for (peer, packet) in self.0.receive() {
+            match LobbyPacket::parse(&packet) {
+                Some(LobbyPacket::Ack) => {}
+                Some(_) => self.0.send(Box::new([SETTINGS_ACK]), peer),
+                None => match bincode::deserialize(&packet) {
+                    Ok(msg) => messages.push((peer, msg)),
+                    Err(err) => warn!("Dropping an unreadable packet from {}: {}", peer, err),
+                },
+            }
+        }
+        messages
+    }
+}
+
+fn remote_id(player: &PlayerType<String>) -> Option<&String> {
+    match player {
+        PlayerType::Remote(id) => Some(id),
+        _ => None,
+    }
+}
+
+// Whether enough peers have connected for the match to start, counting this one.
+pub fn lobby_full(connected: usize, max_players: MaxPlayers) -> bool {
+    connected >= max_players.count()
+}
+
+pub fn agreed_settings<'a>(
+    choices: impl IntoIterator<Item = (&'a String, MatchSettings)>,
+    fallback: MatchSettings,
+) -> MatchSettings {
+    choices
+        .into_iter()
+        .min_by_key(|(peer, _)| *peer)
+        .map_or(fallback, |(_, settings)| settings)
+}
+
+// Whether this peer plays or only watches. A spectator connects to the room like a player but
+// runs a GGRS spectator session fed by `host`, never sending input of its own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SessionKind {
+    Player,
+    Spectator { host: String },
+}
+
+impl Default for SessionKind {
+    fn default() -> SessionKind {
+        SessionKind::Player
+    }
+}
+
+impl SessionKind {
+    pub fn from_args(args: &[String]) -> SessionKind {
+        match arg_value(args, "--spectate") {
+            Some(host) => SessionKind::Spectator { host },
+            None => SessionKind::Player,
+        }
+    }
+}
+
+// A short code naming a private matchbox room, for a host to read out and friends to type in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomCode(String);
+
+impl RoomCode {
+    pub fn generate(rng: &mut impl Rng) -> RoomCode {
+        let code = (0..ROOM_CODE_LEN)
+            .map(|_| rng.gen_range(b'A'..=b'Z') as char)
+            .collect();
+        RoomCode(code)
+    }
+
+    // Accepts lower case too, since it's typed in by hand.
+    pub fn parse(code: &str) -> Option<RoomCode> {
+        let code = code.trim().to_ascii_uppercase();
+        if code.len() == ROOM_CODE_LEN && code.bytes().all(|b| b.is_ascii_uppercase()) {
+            Some(RoomCode(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn room_path(&self) -> String {
+        format!("{}{}", ROOM_PATH_PREFIX, self.0)
+    }
+
+    pub fn from_room_path(path: &str) -> Option<RoomCode> {
+        path.strip_prefix(ROOM_PATH_PREFIX)
+            .and_then(RoomCode::parse)
+    }
+}
+
+// Matchbox's `next_<n>` rooms group whoever connects next into rooms of `n` peers.
+pub fn default_room_url(players: MaxPlayers) -> String {
+    format!("{}/next_{}", DEFAULT_SERVER_URL, players.count())
+}
+
+pub struct MatchboxConfig {
+    pub room_url: String,
+    // Set when hosting or joining a private room rather than being matched with anyone.
+    pub room_code: Option<RoomCode>,
+}
+
+impl Default for MatchboxConfig {
+    fn default() -> MatchboxConfig {
+        MatchboxConfig {
+            room_url: default_room_url(MaxPlayers::default()),
+            room_code: None,
+        }
+    }
+}
+
+impl MatchboxConfig {
+    // `--host` opens a private room under a fresh code and `--join <code>` joins one, both on the
+    // server given by `--server` instead of the one in `--room-url`.
+    pub fn from_args(args: &[String]) -> Result<MatchboxConfig, String> {
+        let room_code = match arg_value(args, "--join") {
+            Some(code) => Some(
+                RoomCode::parse(&code).ok_or_else(|| format!("invalid room code {:?}", code))?,
+            ),
+            None if args.iter().any(|arg| arg == "--host") => {
+                Some(RoomCode::generate(&mut rand::thread_rng()))
+            }
+            None => None,
+        };
+        let room_url = match &room_code {
+            Some(code) => {
+                let server =
+                    arg_value(args, "--server").unwrap_or_else(|| DEFAULT_SERVER_URL.to_string());
+                format!("{}{}", server.trim_end_matches('/'), code.room_path())
+            }
+            None => match arg_value(args, "--room-url") {
+                Some(room_url) => room_url,
+                None => default_room_url(MaxPlayers::from_args(args)?),
+            },
+        };
+        Ok(MatchboxConfig {
+            room_url,
+            room_code,
+        })
+    }
+}
+
+// How the P2P session trades responsiveness for rollbacks. Delaying local input by a few frames
+// gives remote input longer to arrive before it has to be predicted, at the cost of every turn
+// taking effect that much later. The prediction window is how far ahead of the last confirmed
+// frame a peer may run before it waits for the others.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetConfig {
+    pub max_prediction_frames: usize,
+    pub input_delay: usize,
+}
+
+impl Default for NetConfig {
+    fn default() -> NetConfig {
+        NetConfig {
+            max_prediction_frames: DEFAULT_MAX_PREDICTION_FRAMES,
+            input_delay: DEFAULT_INPUT_DELAY,
+        }
+    }
+}
+
+impl NetConfig {
+    pub fn from_args(args: &[String]) -> Result<NetConfig, String> {
+        let frames = |flag: &str, default: usize| {
+            parse_arg(args, flag, "a number of frames", |frames| {
+                frames.parse().ok()
+            })
+            .map(|frames| frames.unwrap_or(default))
+        };
+        Ok(NetConfig {
+            max_prediction_frames: frames("--max-prediction", DEFAULT_MAX_PREDICTION_FRAMES)?,
+            input_delay: frames("--input-delay", DEFAULT_INPUT_DELAY)?,
+        })
+    }
+
+    pub fn apply(&self, builder: SessionBuilder<GGRSConfig>) -> SessionBuilder<GGRSConfig> {
+        builder
+            .with_max_prediction_window(self.max_prediction_frames)
+            .with_input_delay(self.input_delay)
+    }
+}
+
+// The first turns pressed this frame, each one a real turn from the one before it, starting from
+// the snake's current direction. `update_dir` checks them again against its buffer.
+pub(crate) fn pressed_turns(
+    pressed: impl IntoIterator<Item = Direction>,
+    handle: PlayerHandle,
+    snake_query: &Query<(&Player, &Snake)>,
+    segment_query: &Query<&Segment>,
+) -> Vec<Direction> {
+    let mut last = snake_query
+        .iter()
+        .find(|(player, _)| player.handle == handle)
+        .and_then(|(_, snake)| snake.first())
+        .and_then(|head| segment_query.get(*head).ok())
+        .map(|seg| seg.curr_dir);
+    let mut turns = Vec::new();
+    for dir in pressed {
+        if last.map_or(false, |last| dir == last || dir == last.opposite()) {
+            continue;
+        }
+        turns.push(dir);
+        last = Some(dir);
+    }
+    turns.truncate(InputBuffer::CAPACITY);
+    turns
+}
+
+fn pressed_keys(key_events: &mut EventReader<KeyboardInput>) -> Vec<KeyCode> {
+    key_events
+        .iter()
+        .filter(|event| matches!(event.state, ElementState::Pressed))
+        .filter_map(|event| event.key_code)
+        .collect()
+}
+
+pub fn input(
+    handle: In<PlayerHandle>,
+    mut key_events: EventReader<KeyboardInput>,
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    session_kind: Res<SessionKind>,
+    bindings: Res<KeyBindings>,
+    local_gamepad: Res<LocalGamepad>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut last_stick: Local<Option<Direction>>,
+    touches: Res<Touches>,
+    mut swipes: Local<SwipeTracker>,
+) -> BoxInput {
+    if let SessionKind::Spectator { .. } = *session_kind {
+        return BoxInput { inp: 0 };
+    }
+    let keys = pressed_keys(&mut key_events);
+    let pad_dir = local_gamepad.and_then(|gamepad| {
+        gamepad_direction(gamepad, &gamepad_buttons, &gamepad_axes, &mut last_stick)
+    });
+    let swipe_dir = swipe_direction(&touches, &mut swipes);
+    let pressed = keys
+        .iter()
+        .filter_map(|key| bindings.direction(*key))
+        .chain(pad_dir)
+        .chain(swipe_dir);
+    let turns = pressed_turns(pressed, handle.0, &snake_query, &segment_query);
+    BoxInput {
+        inp: encode_turns(&turns),
+    }
+}
+
+// Local multiplayer: player 0 (the left snake) steers with WASD, player 1 with the arrow keys.
+pub fn local_key_direction(handle: PlayerHandle) -> fn(KeyCode) -> Option<Direction> {
+    if handle == 0 {
+        Direction::from_wasd
+    } else {
+        Direction::from_key
+    }
+}
+
+// Stands in for GGRS in local multiplayer, filling `PlayerInputs` from the keyboard each frame.
+pub fn local_input(
+    mut key_events: EventReader<KeyboardInput>,
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    mut inputs: ResMut<PlayerInputs<GGRSConfig>>,
+) {
+    let keys = pressed_keys(&mut key_events);
+    for (handle, (input, status)) in inputs.iter_mut().enumerate() {
+        let pressed = keys.iter().copied().filter_map(local_key_direction(handle));
+        let turns = pressed_turns(pressed, handle, &snake_query, &segment_query);
+        *input = BoxInput {
+            inp: encode_turns(&turns),
+        };
+        *status = InputStatus::Confirmed;
+    }
+}
+
+// Set once the matchbox socket's message loop has stopped. While still in the lobby, that means
+// the signaling server couldn't be reached, whether the connection was refused or timed out, or
+// that it went away before the match started.
+#[derive(Clone, Default)]
+pub struct SignalingStatus(Arc<AtomicBool>);
+
+impl SignalingStatus {
+    pub fn stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Held by the message loop's task, so the status is set however the loop ends, panics included.
+struct StopOnDrop(SignalingStatus);
+
+impl Drop for StopOnDrop {
+    fn drop(&mut self) {
+        (self.0).0.store(true, Ordering::Relaxed);
+    }
+}
+
+// Shown in the lobby in place of the player count, along with a button to play offline instead.
+#[derive(Default, Deref, DerefMut)]
+pub struct LobbyError(pub Option<String>);
+
+// Set once the player gave up on the lobby to play on their own, without a GGRS session.
+#[derive(Default, Deref, DerefMut)]
+pub struct PlayingOffline(pub bool);
+
+// Switches to a single-player game on this machine. The offline stage takes over from GGRS,
+// reading the one player's input the same way the session would have. With no peers to agree
+// with, the food is seeded from `thread_rng`.
+pub fn start_offline(
+    commands: &mut Commands,
+    max_players: &mut MaxPlayers,
+    offline: &mut PlayingOffline,
+    app_state: &mut State<AppState>,
+) {
+    info!("Playing offline");
+    *max_players = MaxPlayers(1);
+    **offline = true;
+    let inputs: PlayerInputs<GGRSConfig> = vec![(BoxInput { inp: 0 }, InputStatus::Confirmed)];
+    commands.insert_resource(inputs);
+    commands.insert_resource(GameRng::from_entropy());
+    set_state(app_state, AppState::InGame);
+}
+
+// The handle `input` is read for when playing offline.
+pub fn offline_handle() -> PlayerHandle {
+    0
+}
+
+pub fn store_offline_input(inp: In<BoxInput>, mut inputs: ResMut<PlayerInputs<GGRSConfig>>) {
+    for (input, status) in inputs.iter_mut() {
+        *input = inp.0;
+        *status = InputStatus::Confirmed;
+    }
+}
+
+#[derive(Default)]
+pub struct OfflineTimestep {
+    accumulator: f64,
+    looping: bool,
+}
+
+// Steps the offline stage at the `SimConfig` rate like `FixedTimestep`, except that no time builds
+// up before `PlayingOffline` is set, so the first offline frame doesn't have to catch up on the
+// whole time spent in the menu and lobby.
+pub fn offline_step(
+    offline: Res<PlayingOffline>,
+    sim: Res<SimConfig>,
+    time: Res<Time>,
+    mut timestep: Local<OfflineTimestep>,
+) -> ShouldRun {
+    if !**offline {
+        return ShouldRun::No;
+    }
+    let step = sim.tick_secs();
+    if !timestep.looping {
+        timestep.accumulator += time.delta_seconds_f64();
+    }
+    timestep.looping = timestep.accumulator >= step;
+    if timestep.looping {
+        timestep.accumulator -= step;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        ShouldRun::No
+    }
+}
+
+pub fn start_matchbox_socket(
+    mut commands: Commands,
+    task_pool: Res<IoTaskPool>,
+    config: Res<MatchboxConfig>,
+    mut signaling: ResMut<SignalingStatus>,
+    mut lobby_error: ResMut<LobbyError>,
+) {
+    // A socket from an earlier visit to the lobby has long stopped, so it gets a fresh status.
+    *signaling = SignalingStatus::default();
+    **lobby_error = None;
+    let room_url = &config.room_url;
+    if !room_url.starts_with("ws://") && !room_url.starts_with("wss://") {
+        error!(
+            "Invalid room url {:?}: it must start with ws:// or wss://",
+            room_url
+        );
+        commands.insert_resource(None::<WebRtcSocket>);
+        return;
+    }
+    info!("Connecting to matchbox to server: {}", room_url);
+    let (socket, message_loop) = WebRtcSocket::new(room_url);
+    let stop_on_drop = StopOnDrop(signaling.clone());
+    task_pool
+        .spawn(async move {
+            let _stop_on_drop = stop_on_drop;
+            message_loop.await
+        })
+        .detach();
+    commands.insert_resource(Some(socket));
+}
+
+pub fn wait_for_players(
+    mut commands: Commands,
+    mut socket: ResMut<Option<WebRtcSocket>>,
+    mut app_state: ResMut<State<AppState>>,
+    session_kind: Res<SessionKind>,
+    max_players: Res<MaxPlayers>,
+    mut difficulty: ResMut<Difficulty>,
+    mut grid: ResMut<GridConfig>,
+    mut lobby_settings: Local<LobbySettings>,
+    signaling: Res<SignalingStatus>,
+    mut lobby_error: ResMut<LobbyError>,
+    net_config: Res<NetConfig>,
+    time: Res<Time>,
+) {
+    // Without a signaling server no peer can ever join, so the lobby stops waiting for one.
+    if lobby_error.is_some() {
+        return;
+    }
+    if socket.is_none() || signaling.stopped() {
+        warn!("{}", SIGNALING_ERROR);
+        **lobby_error = Some(SIGNALING_ERROR.to_string());
+        return;
+    }
+    if let SessionKind::Spectator { host } = &*session_kind {
+        start_spectating(
+            &mut commands,
+            &mut socket,
+            &mut app_state,
+            host,
+            *max_players,
+            &mut difficulty,
+            &mut grid,
+            &mut lobby_settings,
+            &mut lobby_error,
+            time.seconds_since_startup(),
+        );
+        return;
+    }
+    let own_settings = MatchSettings::new(*difficulty, &grid);
+    let (players, mut peer_ids, own_id) = match socket.as_mut() {
+        Some(socket) => {
+            socket.accept_new_connections();
+            lobby_settings.exchange(socket, Some(own_settings), time.seconds_since_startup());
+            let mut peer_ids = socket.connected_peers();
+            peer_ids.push(socket.id().clone());
+            (socket.players(), peer_ids, socket.id().clone())
+        }
+        None => return,
+    };
+    // Spectators connect like players but aren't waited on for settings, only for their acks, so
+    // they've heard the settings before the socket goes to GGRS.
+    let (spectators, players): (Vec<_>, Vec<_>) = players
+        .into_iter()
+        .partition(|player| remote_id(player).map_or(false, |id| lobby_settings.is_spectator(id)));
+    peer_ids.retain(|id| !lobby_settings.is_spectator(id));
+    let remote_ids = players.iter().filter_map(remote_id).collect::<Vec<_>>();
+    let spectator_ids = spectators.iter().filter_map(remote_id).collect::<Vec<_>>();
+    let connected = lobby_full(players.len(), *max_players);
+    if !connected
+        || !lobby_settings.has_heard_from(remote_ids.iter().copied())
+        || !lobby_settings.is_acked_by(remote_ids.iter().chain(&spectator_ids).copied())
+    {
+        if lobby_settings.timed_out(connected) {
+            warn!("{}", SETTINGS_ERROR);
+            **lobby_error = Some(SETTINGS_ERROR.to_string());
+        }
+        return;
+    }
+    commands.insert_resource(GameRng::new(session_seed(peer_ids)));
+    let own_choice = (&own_id, own_settings);
+    let choices = lobby_settings.received.iter().map(|(id, s)| (id, *s));
+    agreed_settings(choices.chain(std::iter::once(own_choice)), own_settings)
+        .apply(&mut difficulty, &mut grid);
+
+    info!("All peers have joined, starting the session");
+    let mut session_builder = net_config.apply(
+        SessionBuilder::<GGRSConfig>::new()
+            .with_num_players(max_players.count())
+            .with_disconnect_timeout(Duration::from_secs(DISCONNECT_TIMEOUT_SECS)),
+    );
+    for (handle, player) in players.into_iter().enumerate() {
+        session_builder = session_builder
+            .add_player(player, handle)
+            .expect("failed to add player");
+    }
+    // GGRS numbers spectators after the players.
+    for (handle, id) in spectator_ids.into_iter().enumerate() {
+        session_builder = session_builder
+            .add_player(
+                PlayerType::Spectator(id.clone()),
+                max_players.count() + handle,
+            )
+            .expect("failed to add spectator");
+    }
+    let session = session_builder
+        .start_p2p_session(SessionSocket(socket.take().unwrap()))
+        .expect("failed to start session");
+    commands.insert_resource(session);
+    commands.insert_resource(SessionType::P2PSession);
+    set_state(&mut app_state, AppState::InGame);
+}
+
+// The spectator seeds its `GameRng` from the players' peer ids, just like they do, so it has to
+// wait until it's connected to all of them, not only to the host, and has heard every player's
+// settings. It sends a hello instead of settings of its own, and waits for the players to ack it
+// so none of them is still waiting on it once the socket goes to GGRS.
+fn start_spectating(
+    commands: &mut Commands,
+    socket: &mut Option<WebRtcSocket>,
+    app_state: &mut State<AppState>,
+    host: &str,
+    max_players: MaxPlayers,
+    difficulty: &mut Difficulty,
+    grid: &mut GridConfig,
+    lobby_settings: &mut LobbySettings,
+    lobby_error: &mut LobbyError,
+    now: f64,
+) {
+    let mut peer_ids = match socket.as_mut() {
+        Some(socket) => {
+            socket.accept_new_connections();
+            lobby_settings.exchange(socket, None, now);
+            socket.connected_peers()
+        }
+        None => return,
+    };
+    peer_ids.retain(|id| !lobby_settings.is_spectator(id));
+    let connected = lobby_full(peer_ids.len(), max_players) && peer_ids.iter().any(|id| id == host);
+    if !connected
+        || !lobby_settings.has_heard_from(peer_ids.iter())
+        || !lobby_settings.is_acked_by(peer_ids.iter())
+    {
+        if lobby_settings.timed_out(connected) {
+            warn!("{}", SETTINGS_ERROR);
+            **lobby_error = Some(SETTINGS_ERROR.to_string());
+        }
+        return;
+    }
+    commands.insert_resource(GameRng::new(session_seed(peer_ids)));
+    let choices = lobby_settings.received.iter().map(|(id, s)| (id, *s));
+    agreed_settings(choices, MatchSettings::new(*difficulty, grid)).apply(difficulty, grid);
+
+    info!("Connected to host {}, starting to spectate", host);
+    let session = SessionBuilder::<GGRSConfig>::new()
+        .with_num_players(max_players.count())
+        .start_spectator_session(host.to_string(), SessionSocket(socket.take().unwrap()));
+    commands.insert_resource(session);
+    commands.insert_resource(SessionType::SpectatorSession);
+    set_state(app_state, AppState::InGame);
+}
+
+// Set while GGRS hears nothing from a peer, to the seconds left before it gives up on them.
+#[derive(Default, Deref, DerefMut)]
+pub struct WaitingForPeer(pub Option<f32>);
+
+impl WaitingForPeer {
+    // Follows one session event, returning whether the match has to end because a peer is gone.
+    pub fn follow(&mut self, event: &GGRSEvent<GGRSConfig>) -> bool {
+        match event {
+            GGRSEvent::NetworkInterrupted {
+                disconnect_timeout, ..
+            } => {
+                warn!("A peer stopped responding, waiting for them to come back");
+                self.0 = Some(*disconnect_timeout as f32 / 1000.);
+                false
+            }
+            GGRSEvent::NetworkResumed { .. } => {
+                info!("The peer is back, resuming the match");
+                self.0 = None;
+                false
+            }
+            GGRSEvent::Disconnected { .. } => {
+                warn!("A peer disconnected, ending the match");
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn tick(&mut self, secs: f32) {
+        if let Some(remaining) = &mut self.0 {
+            *remaining = (*remaining - secs).max(0.);
+        }
+    }
+
+    // Whether the peer didn't come back before the timeout ran out.
+    pub fn timed_out(&self) -> bool {
+        self.0 == Some(0.)
+    }
+}
+
+// GGRS stalls the session on its own when a peer goes quiet and picks up where it left off once
+// their packets come through again, so this only has to tell the player what's going on. A peer
+// that stays away for the whole timeout can't be added back to a running session, so the match
+// ends and everyone goes back to the menu. That doesn't wait on GGRS's `Disconnected` alone: the
+// wait is counted down here too, and once it runs out the match ends whether or not GGRS noticed.
+pub fn handle_session_events(
+    mut commands: Commands,
+    session: Option<ResMut<P2PSession<GGRSConfig>>>,
+    time: Res<Time>,
+    mut waiting: ResMut<WaitingForPeer>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    let mut session = match session {
+        Some(session) => session,
+        None => return,
+    };
+    waiting.tick(time.delta_seconds());
+    for event in session.events() {
+        if waiting.follow(&event) {
+            end_session(&mut commands, &mut waiting, &mut app_state);
+            return;
+        }
+    }
+    if waiting.timed_out() {
+        warn!("A peer didn't come back in time, ending the match");
+        end_session(&mut commands, &mut waiting, &mut app_state);
+    }
+}
+
+fn end_session(
+    commands: &mut Commands,
+    waiting: &mut WaitingForPeer,
+    app_state: &mut State<AppState>,
+) {
+    **waiting = None;
+    commands.remove_resource::<P2PSession<GGRSConfig>>();
+    commands.remove_resource::<SessionType>();
+    set_state(app_state, AppState::MainMenu);
+}
+
+// Queues a state change, which only fails if another one was queued this frame already. The first
+// one wins, so this one is only logged.
+pub(crate) fn set_state(app_state: &mut State<AppState>, state: AppState) {
+    if let Err(err) = app_state.set(state.clone()) {
+        warn!("Could not switch to {:?}: {:?}", state, err);
+    }
+}
+
+// Queues leaving and entering the current state again, like `set_state` would if it could.
+pub(crate) fn restart_state(app_state: &mut State<AppState>) {
+    if let Err(err) = app_state.restart() {
+        warn!("Could not restart {:?}: {:?}", app_state.current(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions_round_trip_through_the_input_byte() {
+        for dir in Direction::ALL {
+            assert_eq!(decode_input(encode_input(Some(dir))), Some(dir));
+        }
+        assert_eq!(decode_input(encode_input(None)), None);
+    }
+
+    #[test]
+    fn the_lobby_waits_for_every_player() {
+        let max_players = MaxPlayers(3);
+        for connected in 0..3 {
+            assert!(!lobby_full(connected, max_players));
+        }
+        assert!(lobby_full(3, max_players));
+    }
+
+    #[test]
+    fn the_settings_swap_only_times_out_while_everyone_is_connected() {
+        let mut lobby = LobbySettings::default();
+        lobby.now = 0.;
+        assert!(!lobby.timed_out(true));
+        lobby.now = SETTINGS_TIMEOUT_SECS + 1.;
+        assert!(lobby.timed_out(true));
+        // Someone dropping out restarts the clock.
+        assert!(!lobby.timed_out(false));
+        assert!(!lobby.timed_out(true));
+    }
+
+    #[test]
+    fn a_spectator_is_acked_but_never_waited_on_for_settings() {
+        let (player, spectator) = ("player".to_string(), "spectator".to_string());
+        let settings = MatchSettings::new(Difficulty::default(), &GridConfig::default());
+        let mut lobby = LobbySettings::default();
+        assert!(lobby.read(&player, &settings.to_bytes()));
+        assert!(lobby.read(&spectator, &[SPECTATOR_HELLO]));
+        assert!(!lobby.read(&player, &[SETTINGS_ACK]));
+        assert!(lobby.is_spectator(&spectator));
+        assert!(!lobby.is_spectator(&player));
+        assert!(lobby.has_heard_from([&player].iter().copied()));
+        assert!(lobby.is_acked_by([&player].iter().copied()));
+        assert!(!lobby.is_acked_by([&player, &spectator].iter().copied()));
+    }
+
+    #[test]
+    fn a_peer_that_comes_back_in_time_resumes_the_match() {
+        let peer = "peer".to_string();
+        let mut waiting = WaitingForPeer::default();
+        let interrupted = GGRSEvent::NetworkInterrupted {
+            addr: peer.clone(),
+            disconnect_timeout: 2000,
+        };
+        assert!(!waiting.follow(&interrupted));
+        waiting.tick(1.5);
+        assert_eq!(*waiting, Some(0.5));
+        assert!(!waiting.follow(&GGRSEvent::NetworkResumed { addr: peer.clone() }));
+        assert_eq!(*waiting, None);
+
+        waiting.follow(&interrupted);
+        waiting.tick(1.);
+        waiting.tick(1.);
+        assert!(waiting.timed_out());
+        assert!(waiting.follow(&GGRSEvent::Disconnected { addr: peer }));
+    }
+
+    #[test]
+    fn a_room_code_round_trips_through_its_room_path() {
+        let mut rng = rand_pcg::Pcg64Mcg::new(7);
+        for _ in 0..10 {
+            let code = RoomCode::generate(&mut rng);
+            assert_eq!(code.as_str().len(), ROOM_CODE_LEN);
+            assert_eq!(
+                RoomCode::from_room_path(&code.room_path()),
+                Some(code.clone())
+            );
+            assert_eq!(RoomCode::parse(&code.as_str().to_lowercase()), Some(code));
+        }
+        assert_eq!(RoomCode::parse("ABC"), None);
+    }
+
+    #[test]
+    fn the_net_flags_reach_the_session() {
+        let args = ["--max-prediction", "12", "--input-delay", "3"].map(String::from);
+        let config = NetConfig::from_args(&args).unwrap();
+        assert_eq!(
+            config,
+            NetConfig {
+                max_prediction_frames: 12,
+                input_delay: 3,
+            }
+        );
+        assert_eq!(NetConfig::from_args(&[]), Ok(NetConfig::default()));
+        let args = ["--input-delay", "soon"].map(String::from);
+        assert!(NetConfig::from_args(&args).is_err());
+
+        let session = config
+            .apply(SessionBuilder::<GGRSConfig>::new().with_num_players(1))
+            .add_player(PlayerType::Local, 0)
+            .unwrap()
+            .start_synctest_session()
+            .unwrap();
+        assert_eq!(session.max_prediction(), 12);
+    }
+}
@@ -0,0 +1,115 @@
+use bevy::prelude::*;
+use ggrs::InputStatus;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use crate::config::arg_value;
+use crate::net::{BoxInput, GGRSConfig, PlayerInputs};
+use crate::resources::{FrameCount, GameRng, Paused};
+
+// Everything needed to re-run a match: where the RNG was when it started, as its seed and how many
+// draws it had made, and every frame's input bits, indexed by player handle. Replaying also needs
+// the same board flags as the recorded run.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplayRecorder {
+    pub seed: u64,
+    pub draws: u64,
+    pub frames: Vec<Vec<u8>>,
+}
+
+impl ReplayRecorder {
+    pub fn new(rng: &GameRng) -> ReplayRecorder {
+        ReplayRecorder {
+            seed: rng.seed(),
+            draws: rng.draws(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn save_replay(&self, path: impl AsRef<Path>) -> bincode::Result<()> {
+        let file = File::create(path)?;
+        bincode::serialize_into(BufWriter::new(file), self)
+    }
+
+    pub fn load_replay(path: impl AsRef<Path>) -> bincode::Result<ReplayRecorder> {
+        let file = File::open(path)?;
+        bincode::deserialize_from(BufReader::new(file))
+    }
+}
+
+// Where to write the recording when the game ends, set with `--record <file>`.
+#[derive(Clone, Debug, Default)]
+pub struct RecordPath(pub Option<String>);
+
+impl RecordPath {
+    pub fn from_args(args: &[String]) -> RecordPath {
+        RecordPath(arg_value(args, "--record"))
+    }
+}
+
+// A recording being fed back through the simulation in place of live input.
+pub struct ReplayPlayback {
+    pub replay: ReplayRecorder,
+    pub frame: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: ReplayRecorder) -> ReplayPlayback {
+        ReplayPlayback { replay, frame: 0 }
+    }
+}
+
+pub fn start_recording(mut recorder: ResMut<ReplayRecorder>, rng: Res<GameRng>) {
+    *recorder = ReplayRecorder::new(&rng);
+}
+
+// Frames are stored by `FrameCount` rather than appended, so a frame GGRS re-simulates after a
+// rollback overwrites its predicted inputs instead of being logged twice. `tick_frame` has already
+// counted this frame, so it goes at the index before the count. Paused frames aren't counted, so
+// they aren't recorded either.
+pub fn record_inputs(
+    mut recorder: ResMut<ReplayRecorder>,
+    frame_count: Res<FrameCount>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    paused: Res<Paused>,
+) {
+    if **paused {
+        return;
+    }
+    let frame = frame_count.saturating_sub(1) as usize;
+    recorder.frames.truncate(frame);
+    recorder
+        .frames
+        .push(inputs.iter().map(|(input, _)| input.inp).collect());
+}
+
+pub fn replay_input(
+    mut playback: ResMut<ReplayPlayback>,
+    mut inputs: ResMut<PlayerInputs<GGRSConfig>>,
+    paused: Res<Paused>,
+) {
+    if **paused {
+        return;
+    }
+    let frame = playback.replay.frames.get(playback.frame).cloned();
+    for (handle, (input, status)) in inputs.iter_mut().enumerate() {
+        let inp = frame
+            .as_ref()
+            .and_then(|frame| frame.get(handle).copied())
+            .unwrap_or(0);
+        *input = BoxInput { inp };
+        *status = InputStatus::Confirmed;
+    }
+    playback.frame += 1;
+}
+
+pub fn save_on_game_over(recorder: Res<ReplayRecorder>, path: Res<RecordPath>) {
+    if let Some(path) = &path.0 {
+        match recorder.save_replay(path) {
+            Ok(()) => info!("Saved replay to {}", path),
+            Err(err) => error!("Failed to save replay to {}: {}", path, err),
+        }
+    }
+}
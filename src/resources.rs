@@ -0,0 +1,480 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use ggrs::PlayerHandle;
+use rand::{rand_core::impls, seq::SliceRandom, Rng, RngCore, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+use std::collections::HashMap;
+
+use crate::components::{SegmentShape, ShapeIcon};
+use crate::config::{GridConfig, GridPos, SimConfig};
+use crate::FPS;
+
+pub const BASE_MOVE_INTERVAL: f32 = 0.10;
+pub const MIN_MOVE_INTERVAL: f32 = 0.04;
+pub const MOVE_INTERVAL_DECAY: f32 = 0.98;
+pub const COUNTDOWN_SECS: f32 = 3.;
+pub const COUNTDOWN_FRAMES: u32 = (COUNTDOWN_SECS * FPS as f32) as u32;
+// A death shakes the camera by up to half a cell, settling over 0.4 seconds.
+pub const SCREEN_SHAKE_SECS: f32 = 0.4;
+pub const SCREEN_SHAKE_CELLS: f32 = 0.5;
+pub const SCREEN_SHAKE_SEED: u64 = 0x5ba4e;
+pub const SEGMENT_IMAGE_SIZE: u32 = 32;
+pub const SHAPE_IMAGE_SIZE: u32 = 32;
+// How far in from the corners the head and tail are rounded, as a share of the cell.
+pub const SEGMENT_END_ROUNDING: f32 = 0.4;
+
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct Score(pub u32);
+
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct FoodTimer(pub u32);
+
+#[derive(Component, Reflect)]
+#[reflect(Resource)]
+pub struct GameSpeed {
+    pub interval: f32,
+}
+
+impl Default for GameSpeed {
+    fn default() -> GameSpeed {
+        GameSpeed {
+            interval: BASE_MOVE_INTERVAL,
+        }
+    }
+}
+
+impl GameSpeed {
+    // Speeds up from `base_interval` with every point scored.
+    pub fn for_score(base_interval: f32, score: u32) -> GameSpeed {
+        GameSpeed {
+            interval: (base_interval * MOVE_INTERVAL_DECAY.powi(score as i32))
+                .max(MIN_MOVE_INTERVAL),
+        }
+    }
+}
+
+// Pausing is local-only: a networked peer would keep simulating, so `toggle_pause` only flips
+// this while there's a single snake in play.
+#[derive(Default, Deref, DerefMut)]
+pub struct Paused(pub bool);
+
+// How many rings of the `ShrinkingArena` have closed so far.
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct ClosedRings(pub u32);
+
+// Set on the frame the match is decided and rolled back with it, so a death that was only
+// predicted is taken back along with the end of the match. The game-over screen waits for that
+// frame to be confirmed.
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct MatchOver(pub bool);
+
+// How the last match ended, for the game-over screen. The last snake alive wins, and if the last
+// ones die on the same frame it's a draw.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MatchOutcome {
+    Undecided,
+    Winner(PlayerHandle),
+    Draw,
+}
+
+impl Default for MatchOutcome {
+    fn default() -> MatchOutcome {
+        MatchOutcome::Undecided
+    }
+}
+
+// Set by the pause menu's restart, so `cleanup_game` clears the board on the way out of InGame
+// instead of leaving it up behind a game-over screen.
+#[derive(Default, Deref, DerefMut)]
+pub struct RestartRequested(pub bool);
+
+// Simulation frames since the game started.
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct FrameCount(pub u32);
+
+// Frames the time trial has run for. It's counted in the simulation and rolled back with it, so a
+// predicted frame's time always matches its snakes. `finished` is set on the frame a snake reaches
+// the target length, and the count stops there.
+#[derive(Component, Default, Reflect)]
+#[reflect(Resource)]
+pub struct TrialTimer {
+    pub frames: u32,
+    pub finished: bool,
+}
+
+// Seconds accumulated towards the next move, advanced by a fixed `SimConfig::tick_secs` every
+// frame.
+#[derive(Component, Default, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct MoveTimer(pub f32);
+
+// Holds the snakes still at the start of a game. It's ticked by a fixed `SimConfig::tick_secs` per
+// simulation frame rather than by wall-clock time, so it ends on the same frame for every peer.
+#[derive(Component, Deref, DerefMut, Reflect)]
+#[reflect(Resource)]
+pub struct StartCountdown(pub Timer);
+
+impl Default for StartCountdown {
+    fn default() -> StartCountdown {
+        StartCountdown(Timer::from_seconds(COUNTDOWN_SECS, false))
+    }
+}
+
+// The frame, by `FrameCount`, until which leaving the board in Wall mode isn't deadly: the start
+// countdown and a few moments after it, so a new player gets a moment to orient. Every peer
+// advances `FrameCount` identically, and it stands still while paused, so only unpaused frames use
+// the grace up. Zero turns it off.
+#[derive(Copy, Clone, Debug, Default, Deref, DerefMut)]
+pub struct GracePeriod(pub u32);
+
+impl GracePeriod {
+    // Counted in ticks at `sim`'s rate, from `grace_frames` frames at `FPS` a second.
+    pub fn new(grace_frames: u32, sim: &SimConfig) -> GracePeriod {
+        if grace_frames == 0 {
+            return GracePeriod(0);
+        }
+        GracePeriod(sim.ticks(COUNTDOWN_FRAMES + grace_frames))
+    }
+
+    pub fn active(&self, frame_count: &FrameCount) -> bool {
+        **frame_count < self.0
+    }
+}
+
+// The cells covered by segments, food and obstacles, updated as they move, spawn and despawn so
+// `spawn_food` doesn't have to look at every entity. Cells are counted rather than just marked,
+// since two things can share a cell for a frame, e.g. a head and the food it's eating. Rolled back
+// as a whole value.
+#[derive(Component, Clone, Debug, Default, Reflect)]
+#[reflect_value(Resource)]
+pub struct Occupancy(pub HashMap<GridPos, u32>);
+
+impl Occupancy {
+    pub fn insert(&mut self, cell: GridPos) {
+        *self.0.entry(cell).or_insert(0) += 1;
+    }
+
+    pub fn remove(&mut self, cell: GridPos) {
+        if let Some(count) = self.0.get_mut(&cell) {
+            *count -= 1;
+            if *count == 0 {
+                self.0.remove(&cell);
+            }
+        }
+    }
+
+    pub fn contains(&self, cell: GridPos) -> bool {
+        self.0.contains_key(&cell)
+    }
+
+    // Picks one of the board's free cells with `rng`, or `None` once the board is full. The free
+    // cells are listed in `GridConfig::cells` order, so every peer draws from the same list.
+    pub fn free_cell(&self, grid: &GridConfig, rng: &mut GameRng) -> Option<GridPos> {
+        let free_cells = grid
+            .cells()
+            .filter(|cell| !self.contains(*cell))
+            .collect::<Vec<_>>();
+        rng.choose(&free_cells).copied()
+    }
+}
+
+// Segment entities of snakes that are gone, hidden and stripped of their `Segment` and `Rollback`
+// so the next game's snakes can take them over instead of spawning new ones. A pooled entity is
+// invisible to the simulation and the rollback, so the pool itself isn't rolled back.
+#[derive(Default, Deref, DerefMut)]
+pub struct SegmentPool(pub Vec<Entity>);
+
+// A seeded PCG generator, so every peer draws the same numbers as long as they start from the same
+// seed. Everything random in the simulation draws from it; `thread_rng` is only for seeds and room
+// codes. The generator can't be reflected field by field, so the whole of it is rolled back as one
+// value. It counts its draws, which with the seed is enough to checksum it or start it again from
+// the same place.
+#[derive(Clone, Component, Reflect)]
+#[reflect_value(Resource)]
+pub struct GameRng {
+    seed: u64,
+    draws: u64,
+    rng: Pcg64Mcg,
+}
+
+impl Default for GameRng {
+    fn default() -> GameRng {
+        GameRng::new(0)
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> GameRng {
+        GameRng {
+            seed,
+            draws: 0,
+            rng: Pcg64Mcg::seed_from_u64(seed),
+        }
+    }
+
+    // A generator started from `seed` that has already made `draws` draws.
+    pub fn resume(seed: u64, draws: u64) -> GameRng {
+        let mut rng = GameRng::new(seed);
+        rng.rng.advance(draws as u128);
+        rng.draws = draws;
+        rng
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn draws(&self) -> u64 {
+        self.draws
+    }
+
+    // A `u32` rather than a `usize` range, so a wasm peer draws the same index as a native one.
+    pub fn gen_index(&mut self, len: usize) -> usize {
+        self.gen_range(0..len as u32) as usize
+    }
+
+    // True with probability `p`, from 0 to 1.
+    pub fn chance(&mut self, p: f64) -> bool {
+        self.gen_bool(p.clamp(0., 1.))
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        items.choose(self)
+    }
+
+    // Any cell of the board, taken or not.
+    pub fn next_cell(&mut self, grid: &GridConfig) -> GridPos {
+        GridPos::new(
+            self.gen_index(grid.width as usize) as i32,
+            self.gen_index(grid.height as usize) as i32,
+        )
+    }
+
+    // A fresh seed for games with no peers or replay to agree with.
+    pub fn from_entropy() -> GameRng {
+        GameRng::new(rand::random())
+    }
+}
+
+// Every output of `Pcg64Mcg` is one step of it, whatever its width, so counting the calls here
+// counts the steps `resume` has to skip.
+impl RngCore for GameRng {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// How hard the camera is shaking, from 1 right after a death down to 0. It isn't rolled back:
+// only confirmed deaths start it, and the offsets are drawn from its own `GameRng`, so shaking
+// never touches the game's.
+pub struct ScreenShake {
+    pub magnitude: f32,
+    pub rng: GameRng,
+}
+
+impl Default for ScreenShake {
+    fn default() -> ScreenShake {
+        ScreenShake {
+            magnitude: 0.,
+            rng: GameRng::new(SCREEN_SHAKE_SEED),
+        }
+    }
+}
+
+impl ScreenShake {
+    pub fn start(&mut self) {
+        self.magnitude = 1.;
+    }
+
+    pub fn decay(&mut self, secs: f32) {
+        self.magnitude = (self.magnitude - secs / SCREEN_SHAKE_SECS).max(0.);
+    }
+
+    // A random offset of up to `max_offset` on each axis, scaled down as the shake settles.
+    pub fn offset(&mut self, max_offset: f32) -> Vec2 {
+        if self.magnitude <= 0. {
+            return Vec2::ZERO;
+        }
+        let (x, y) = (self.rng.gen_range(-1. ..1.), self.rng.gen_range(-1. ..1.));
+        Vec2::new(x, y) * max_offset * self.magnitude * self.magnitude
+    }
+}
+
+// The mask images the snake's segments are drawn with, white so each sprite's own color still
+// tints them. They're generated at startup, `SEGMENT_IMAGE_SIZE` pixels square.
+pub struct SegmentImages {
+    pub straight: Handle<Image>,
+    pub corner: Handle<Image>,
+    pub head: Handle<Image>,
+    pub tail: Handle<Image>,
+}
+
+impl SegmentImages {
+    pub fn get(&self, shape: SegmentShape) -> &Handle<Image> {
+        match shape {
+            SegmentShape::Straight => &self.straight,
+            SegmentShape::Corner => &self.corner,
+            SegmentShape::Head => &self.head,
+            SegmentShape::Tail => &self.tail,
+        }
+    }
+
+    // Whether pixel (`x`, `y`) of `shape`'s image is filled, counted from the bottom left. A corner
+    // comes in at the bottom and leaves on the right, so it's a quarter disc around the bottom
+    // right corner. The head and tail round off the top and bottom corners.
+    pub fn covers(shape: SegmentShape, x: u32, y: u32) -> bool {
+        let size = SEGMENT_IMAGE_SIZE as f32;
+        let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+        let radius = size * SEGMENT_END_ROUNDING;
+        let rounded = |y: f32| {
+            let dx = (radius - x).max(x - (size - radius)).max(0.);
+            let dy = (y - (size - radius)).max(0.);
+            dx * dx + dy * dy <= radius * radius
+        };
+        match shape {
+            SegmentShape::Straight => true,
+            SegmentShape::Corner => (size - x).powi(2) + y.powi(2) <= size * size,
+            SegmentShape::Head => rounded(y),
+            SegmentShape::Tail => rounded(size - y),
+        }
+    }
+
+    pub fn image(shape: SegmentShape) -> Image {
+        mask_image(SEGMENT_IMAGE_SIZE, |x, y| {
+            SegmentImages::covers(shape, x, y)
+        })
+    }
+}
+
+// A white square image of `size` pixels, opaque where `covers` is true for the pixel counted from
+// the bottom left.
+pub fn mask_image(size: u32, covers: impl Fn(u32, u32) -> bool) -> Image {
+    // Image rows run top to bottom.
+    let pixels = (0..size)
+        .rev()
+        .flat_map(|y| (0..size).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            let alpha = if covers(x, y) { 255 } else { 0 };
+            [255, 255, 255, alpha]
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+// The mask images of the colorblind icons, generated at startup like `SegmentImages`.
+pub struct ShapeImages(pub HashMap<ShapeIcon, Handle<Image>>);
+
+impl ShapeImages {
+    pub fn get(&self, icon: ShapeIcon) -> Option<&Handle<Image>> {
+        self.0.get(&icon)
+    }
+
+    // Whether pixel (`x`, `y`) of `icon`'s image is filled, counted from the bottom left. The star
+    // points up.
+    pub fn covers(icon: ShapeIcon, x: u32, y: u32) -> bool {
+        let half = SHAPE_IMAGE_SIZE as f32 / 2.;
+        // From -1 to 1 across the image.
+        let u = (x as f32 + 0.5 - half) / half;
+        let v = (y as f32 + 0.5 - half) / half;
+        let r = (u * u + v * v).sqrt();
+        match icon {
+            ShapeIcon::Circle => r <= 0.6,
+            ShapeIcon::Ring => (0.4..=0.75).contains(&r),
+            ShapeIcon::Square => u.abs() <= 0.6 && v.abs() <= 0.6,
+            ShapeIcon::Cross => {
+                let bar = 0.2 * std::f32::consts::SQRT_2;
+                ((u - v).abs() <= bar || (u + v).abs() <= bar) && u.abs() <= 0.7 && v.abs() <= 0.7
+            }
+            ShapeIcon::Star => {
+                // Folded into the half of a point between its tip and the next inner corner, the
+                // star's edge is the line between the two.
+                let sector = std::f32::consts::TAU / 5.;
+                let turn = u.atan2(v).rem_euclid(sector);
+                let angle = turn.min(sector - turn);
+                let (outer, inner) = (0.95, 0.4);
+                let corner = Vec2::new(inner * (sector / 2.).cos(), inner * (sector / 2.).sin());
+                let tip = Vec2::new(outer, 0.);
+                let point = Vec2::new(r * angle.cos(), r * angle.sin());
+                (corner - tip).perp_dot(point - tip) >= 0.
+            }
+        }
+    }
+
+    pub fn image(icon: ShapeIcon) -> Image {
+        mask_image(SHAPE_IMAGE_SIZE, |x, y| ShapeImages::covers(icon, x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_cell_only_picks_an_empty_cell() {
+        let grid = GridConfig {
+            width: 6,
+            height: 6,
+            ..GridConfig::default()
+        };
+        let free = GridPos::new(2, 3);
+        let mut occupancy = Occupancy::default();
+        for cell in grid.cells().filter(|cell| *cell != free) {
+            occupancy.insert(cell);
+        }
+        for seed in 0..20 {
+            let mut rng = GameRng::new(seed);
+            assert_eq!(occupancy.free_cell(&grid, &mut rng), Some(free));
+        }
+    }
+
+    #[test]
+    fn the_same_seed_draws_the_same_sequence() {
+        let grid = GridConfig::default();
+        let draw = |rng: &mut GameRng| {
+            (0..50)
+                .map(|_| {
+                    let cell = rng.next_cell(&grid);
+                    (cell, rng.chance(0.5), *rng.choose(&[1, 2, 3, 4]).unwrap())
+                })
+                .collect::<Vec<_>>()
+        };
+        let (mut a, mut b) = (GameRng::new(42), GameRng::new(42));
+        assert_eq!(draw(&mut a), draw(&mut b));
+        assert_ne!(draw(&mut GameRng::new(42)), draw(&mut GameRng::new(43)));
+
+        let mut resumed = GameRng::resume(a.seed(), a.draws());
+        assert_eq!(draw(&mut resumed), draw(&mut a));
+        assert_eq!(resumed.draws(), a.draws());
+    }
+}
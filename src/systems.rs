@@ -0,0 +1,1705 @@
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    render::{
+        render_resource::TextureFormat,
+        texture::{Image, ImageType},
+    },
+    window::WindowResized,
+    winit::WinitWindows,
+};
+use bevy_ggrs::*;
+use ggrs::{InputStatus, PlayerHandle};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use winit::window::Icon;
+
+use crate::components::{
+    BotController, Direction, Food, FoodKind, FoodValue, GridLine, Head, InputBuffer, Invincible,
+    MainCamera, Obstacle, Player, PlayerScore, Portal, PowerUp, Segment, SegmentShape,
+    SegmentSprite, ShapeIcon, ShapeOverlay, ShieldGlow, Snake, SpeedBoost, Trail, Wall,
+};
+use crate::config::{
+    AccessibilityConfig, AssistMode, BotHandles, BoundaryMode, DailyChallenge, Difficulty,
+    DifficultySettings, FoodSettings, GridConfig, GridPos, KeyBindings, MaxPlayers, ObstacleLayout,
+    Palette, PortalLayout, ShrinkingArena, SimConfig, StartLength, TimeTrial, WindowSettings,
+};
+use crate::level::Level;
+use crate::net::{decode_turns, GGRSConfig, PlayerInputs};
+use crate::replay::ReplayPlayback;
+use crate::resources::{
+    ClosedRings, FoodTimer, FrameCount, GameRng, GameSpeed, GracePeriod, MatchOutcome, MatchOver,
+    MoveTimer, Occupancy, Paused, RestartRequested, Score, ScreenShake, SegmentImages, SegmentPool,
+    ShapeImages, StartCountdown, TrialTimer, COUNTDOWN_FRAMES, SCREEN_SHAKE_CELLS,
+};
+use crate::{AppState, FPS};
+
+// One in this many spawned food is golden.
+pub const GOLDEN_FOOD_ODDS: usize = 10;
+// One in this many of the rest is poison, and one in this many of what's left a magnet.
+pub const POISON_FOOD_ODDS: usize = 8;
+pub const MAGNET_FOOD_ODDS: usize = 6;
+// Magnet food moves a cell every this many frames, half the snakes' starting speed.
+pub const MAGNET_STEP_FRAMES: u32 = 12;
+// A power-up turns up every 15 seconds of play while none is on the board. A speed boost lasts 5
+// seconds and a shield 3.
+pub const POWER_UP_SPAWN_FRAMES: u32 = 15 * FPS as u32;
+pub const SPEED_BOOST_FRAMES: u32 = 5 * FPS as u32;
+pub const SHIELD_FRAMES: u32 = 3 * FPS as u32;
+// Frames per flash of a boosted snake.
+pub const BOOST_PULSE_FRAMES: u32 = 20;
+// A trail fades out over 0.3 seconds, and no more than this many are on screen at once.
+pub const TRAIL_SECS: f32 = 0.3;
+pub const MAX_TRAIL_SPRITES: usize = 64;
+
+// `Safe` is sent before the eaten food is despawned, so systems later in the same stage can still
+// look the food entity up.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CollisionEvent {
+    Safe { handle: PlayerHandle, food: Entity },
+    Deadly { handle: PlayerHandle },
+}
+
+impl CollisionEvent {
+    // Events are consumed by ascending handle and, for the same handle, deaths before food. Several
+    // systems send them, so this order doesn't depend on which of them ran first.
+    pub fn order(&self) -> (PlayerHandle, u8) {
+        match self {
+            CollisionEvent::Deadly { handle } => (*handle, 0),
+            CollisionEvent::Safe { handle, .. } => (*handle, 1),
+        }
+    }
+}
+
+// Reads this frame's collisions in `CollisionEvent::order`. The sort is stable, so events that
+// tie keep the order they were sent in.
+pub fn sorted_collisions(
+    collision_events: &mut EventReader<CollisionEvent>,
+) -> Vec<CollisionEvent> {
+    let mut collisions = collision_events.iter().copied().collect::<Vec<_>>();
+    collisions.sort_by_key(CollisionEvent::order);
+    collisions
+}
+
+// What happened in the game, for observers like UI, audio or replays that shouldn't have to
+// re-derive it from the world. `CollisionEvent` stays the internal signal between systems.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GameEvent {
+    FoodEaten { cell: GridPos, value: FoodValue },
+    SnakeDied { handle: PlayerHandle },
+    SnakeGrew { handle: PlayerHandle, len: usize },
+    SnakeShrank { handle: PlayerHandle, len: usize },
+    PowerUpCollected { handle: PlayerHandle, kind: PowerUp },
+    FoodSpawned { cell: GridPos },
+}
+
+// Margin kept around the walls when framing the board, in cells.
+pub const CAMERA_MARGIN_CELLS: f32 = 0.5;
+
+// The board is centred on the origin, so the camera only has to zoom: this is the orthographic
+// scale that fits the board, its walls and a small margin into a window of the given size.
+pub fn camera_scale(grid: &GridConfig, window_width: f32, window_height: f32) -> f32 {
+    let framed_cells = 2. + 2. * CAMERA_MARGIN_CELLS;
+    let board_width = (grid.width as f32 + framed_cells) * grid.box_size;
+    let board_height = (grid.height as f32 + framed_cells) * grid.box_size;
+    (board_width / window_width.max(1.)).max(board_height / window_height.max(1.))
+}
+
+pub fn setup_camera(
+    mut commands: Commands,
+    palette: Res<Palette>,
+    grid: Res<GridConfig>,
+    windows: Res<Windows>,
+) {
+    commands.insert_resource(ClearColor(palette.background));
+    let mut camera = OrthographicCameraBundle::new_2d();
+    if let Some(window) = windows.get_primary() {
+        camera.orthographic_projection.scale = camera_scale(&grid, window.width(), window.height());
+    }
+    commands.spawn_bundle(camera).insert(MainCamera);
+}
+
+// Decodes the PNG at `path` into a window icon. Grayscale images decode to formats winit can't use,
+// so only RGB and RGBA ones are accepted.
+pub fn load_window_icon(path: &str) -> Result<Icon, String> {
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    let image =
+        Image::from_buffer(&bytes, ImageType::Extension("png")).map_err(|err| err.to_string())?;
+    if image.texture_descriptor.format != TextureFormat::Rgba8UnormSrgb {
+        return Err("the icon has to be an RGB or RGBA image".to_string());
+    }
+    let size = image.texture_descriptor.size;
+    Icon::from_rgba(image.data, size.width, size.height).map_err(|err| err.to_string())
+}
+
+// winit only creates the window once the app is running, so this waits until it's there. An icon
+// that can't be loaded is warned about once and the window keeps the default.
+pub fn set_window_icon(
+    settings: Res<WindowSettings>,
+    windows: Res<Windows>,
+    winit_windows: Option<NonSend<WinitWindows>>,
+    mut done: Local<bool>,
+) {
+    if *done {
+        return;
+    }
+    let path = match &settings.icon {
+        Some(path) => path,
+        None => {
+            *done = true;
+            return;
+        }
+    };
+    let winit_windows = match winit_windows {
+        Some(winit_windows) => winit_windows,
+        None => return,
+    };
+    let window = windows
+        .get_primary()
+        .and_then(|window| winit_windows.get_window(window.id()));
+    let window = match window {
+        Some(window) => window,
+        None => return,
+    };
+    *done = true;
+    match load_window_icon(path) {
+        Ok(icon) => window.set_window_icon(Some(icon)),
+        Err(err) => warn!("Failed to load the window icon {}: {}", path, err),
+    }
+}
+
+// Like sounds, only a death on a confirmed frame shakes the camera, so a death that a rollback
+// takes back never does.
+pub fn queue_screen_shake(
+    mut collision_events: EventReader<CollisionEvent>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    mut shake: ResMut<ScreenShake>,
+) {
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    let deaths = collision_events
+        .iter()
+        .filter(|collision| matches!(collision, CollisionEvent::Deadly { .. }))
+        .count();
+    if confirmed && deaths > 0 {
+        shake.start();
+    }
+}
+
+// Only the camera moves, so the board and snakes stay where the simulation put them. With the
+// shake settled the camera is back on the origin, where the board is centred.
+pub fn screen_shake(
+    time: Res<Time>,
+    grid: Res<GridConfig>,
+    mut shake: ResMut<ScreenShake>,
+    mut camera_query: Query<&mut Transform, With<MainCamera>>,
+) {
+    shake.decay(time.delta_seconds());
+    let offset = shake.offset(grid.box_size * SCREEN_SHAKE_CELLS);
+    for mut trans in camera_query.iter_mut() {
+        if trans.translation.truncate() != offset {
+            trans.translation.x = offset.x;
+            trans.translation.y = offset.y;
+        }
+    }
+}
+
+// Re-fits the board when the window is resized, or when the board itself changes size, like when the
+// lobby settles on the host's settings. The scale covers the tighter of the two axes, so the board
+// keeps its aspect and the clear colour fills the rest as letterbox bars.
+pub fn fit_camera_on_resize(
+    mut resize_events: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    grid: Res<GridConfig>,
+    mut camera_query: Query<&mut OrthographicProjection, With<MainCamera>>,
+) {
+    let resized = resize_events
+        .iter()
+        .filter(|event| event.id.is_primary())
+        .last()
+        .map(|event| (event.width, event.height));
+    let size = match (resized, windows.get_primary()) {
+        (Some(size), _) => size,
+        (None, Some(primary)) if grid.is_changed() => (primary.width(), primary.height()),
+        _ => return,
+    };
+    for mut projection in camera_query.iter_mut() {
+        projection.scale = camera_scale(&grid, size.0, size.1);
+    }
+}
+
+// Where each snake's head starts and the way it faces. Snakes take the corners in turn, bottom
+// left then top right, bottom right and top left, so two players are point-symmetric about the
+// centre. Each sits a quarter of the way in and faces the centre vertically, trailing its body
+// back towards the edge. On a board too small for that, a clash moves to the first free cell.
+pub fn spawn_positions(player_count: usize, grid: &GridConfig) -> Vec<(GridPos, Direction)> {
+    let (width, height) = (grid.width as i32, grid.height as i32);
+    let x = ((width + 2) / 4).min(width - 1);
+    let y = (height / 4).min(height - 1);
+    let corners = [
+        (GridPos::new(x, y), Direction::Up),
+        (GridPos::new(width - 1 - x, height - 1 - y), Direction::Down),
+        (GridPos::new(width - 1 - x, y), Direction::Up),
+        (GridPos::new(x, height - 1 - y), Direction::Down),
+    ];
+    let taken = |cell: GridPos, positions: &[(GridPos, Direction)]| {
+        positions.iter().any(|(other, _)| *other == cell)
+    };
+    let mut positions = Vec::with_capacity(player_count);
+    for i in 0..player_count {
+        let (mut cell, dir) = corners[i % corners.len()];
+        if taken(cell, &positions) {
+            match grid.cells().find(|cell| !taken(*cell, &positions)) {
+                Some(free) => cell = free,
+                None => break,
+            }
+        }
+        positions.push((cell, dir));
+    }
+    positions
+}
+
+// Runs every frame but only redraws when `GridConfig` changed, which includes the first frame.
+pub fn draw_grid(
+    mut commands: Commands,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    line_query: Query<Entity, With<GridLine>>,
+) {
+    if !grid.is_changed() {
+        return;
+    }
+    for entity in line_query.iter() {
+        commands.entity(entity).despawn();
+    }
+    for sprite in GridLine::grid_lines(&grid, palette.grid) {
+        commands.spawn_bundle(sprite).insert(GridLine);
+    }
+}
+
+pub fn apply_difficulty(
+    difficulty: Res<Difficulty>,
+    mut settings: ResMut<DifficultySettings>,
+    mut food: ResMut<FoodSettings>,
+    mut grace: ResMut<GracePeriod>,
+    sim: Res<SimConfig>,
+) {
+    *settings = DifficultySettings::for_difficulty(*difficulty);
+    *food = settings.food;
+    *grace = GracePeriod::new(settings.grace_frames, &sim);
+}
+
+// Starts every game of the daily challenge from the same seed, so a restart gets the same food
+// again. A GGRS session or a replay brings its own seed, which is left alone.
+pub fn reset_daily_seed(
+    daily: Res<DailyChallenge>,
+    session: Option<Res<SessionType>>,
+    replay: Option<Res<ReplayPlayback>>,
+    mut rng: ResMut<GameRng>,
+) {
+    if let Some(date) = **daily {
+        if session.is_none() && replay.is_none() {
+            *rng = GameRng::new(date.seed());
+        }
+    }
+}
+
+pub fn setup(
+    mut commands: Commands,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    boundary_mode: Res<BoundaryMode>,
+    settings: Res<DifficultySettings>,
+    mut score: ResMut<Score>,
+    mut food_timer: ResMut<FoodTimer>,
+    mut speed: ResMut<GameSpeed>,
+    mut move_timer: ResMut<MoveTimer>,
+    mut frame_count: ResMut<FrameCount>,
+    mut countdown: ResMut<StartCountdown>,
+    mut outcome: ResMut<MatchOutcome>,
+    mut match_over: ResMut<MatchOver>,
+    mut closed: ResMut<ClosedRings>,
+    mut trial_timer: ResMut<TrialTimer>,
+) {
+    *score = Score::default();
+    *closed = ClosedRings::default();
+    *outcome = MatchOutcome::default();
+    *match_over = MatchOver::default();
+    *countdown = StartCountdown::default();
+    *frame_count = FrameCount::default();
+    *trial_timer = TrialTimer::default();
+    *food_timer = FoodTimer::default();
+    *speed = GameSpeed::for_score(settings.move_interval, 0);
+    *move_timer = MoveTimer::default();
+    if *boundary_mode == BoundaryMode::Wall {
+        for (wall, sprite) in Wall::boundary_walls(&grid, palette.wall) {
+            commands.spawn_bundle(sprite).insert(wall);
+        }
+    }
+}
+
+// A level's obstacles are all placed whatever the difficulty, and its walls are obstacles drawn in
+// the wall colour.
+pub fn spawn_obstacles(
+    mut commands: Commands,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    layout: Res<ObstacleLayout>,
+    level: Option<Res<Level>>,
+    settings: Res<DifficultySettings>,
+    mut occupancy: ResMut<Occupancy>,
+) {
+    let cells = match &level {
+        Some(level) => level
+            .walls
+            .iter()
+            .map(|cell| (*cell, palette.wall))
+            .chain(level.obstacles.iter().map(|cell| (*cell, palette.obstacle)))
+            .collect::<Vec<_>>(),
+        None => layout
+            .iter()
+            .take(settings.obstacles)
+            .map(|cell| (*cell, palette.obstacle))
+            .collect(),
+    };
+    for (cell, color) in cells.into_iter().filter(|(cell, _)| grid.in_bounds(*cell)) {
+        occupancy.insert(cell);
+        let pos = cell.to_translation(&grid);
+        commands
+            .spawn_bundle(Obstacle::new_sprite_bundle(
+                pos.x,
+                pos.y,
+                grid.box_size,
+                color,
+            ))
+            .insert(Obstacle);
+    }
+}
+
+// Both ends of a pair count as occupied, so nothing spawns on a portal. A pair with an end off the
+// board is skipped.
+pub fn spawn_portals(
+    mut commands: Commands,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    layout: Res<PortalLayout>,
+    mut occupancy: ResMut<Occupancy>,
+) {
+    for &(a, b) in layout.iter() {
+        if !grid.in_bounds(a) || !grid.in_bounds(b) || a == b {
+            warn!("Skipping a portal pair from {:?} to {:?}", a, b);
+            continue;
+        }
+        for (cell, exit) in [(a, b), (b, a)] {
+            occupancy.insert(cell);
+            let pos = cell.to_translation(&grid);
+            commands
+                .spawn_bundle(Portal::new_sprite_bundle(
+                    pos.x,
+                    pos.y,
+                    grid.box_size,
+                    palette.portal,
+                ))
+                .insert(Portal { exit });
+        }
+    }
+}
+
+pub fn spawn_players(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    bot_handles: Res<BotHandles>,
+    start_length: Res<StartLength>,
+    max_players: Res<MaxPlayers>,
+    level: Option<Res<Level>>,
+    mut occupancy: ResMut<Occupancy>,
+    mut pool: ResMut<SegmentPool>,
+) {
+    let box_size = grid.box_size;
+    // A body trails straight back from its head, so any longer than the room behind the most
+    // cramped snake is cut short.
+    let positions = match &level {
+        Some(level) => level.spawn_positions(max_players.count(), &grid),
+        None => spawn_positions(max_players.count(), &grid),
+    };
+    let max_length = positions
+        .iter()
+        .map(|(cell, dir)| match dir {
+            Direction::Up => cell.y + 1,
+            Direction::Down => grid.height as i32 - cell.y,
+            Direction::Left => grid.width as i32 - cell.x,
+            Direction::Right => cell.x + 1,
+        })
+        .min()
+        .unwrap_or(1) as u32;
+    if **start_length > max_length {
+        warn!(
+            "A start length of {} doesn't fit on the board, using {}",
+            **start_length, max_length
+        );
+    }
+    let length = (**start_length).clamp(1, max_length);
+    for (handle, (start, dir)) in positions.into_iter().enumerate() {
+        let cells = std::iter::once(start)
+            .chain(trailing_cells(start, dir, length as usize - 1))
+            .collect::<Vec<_>>();
+        let mut snake = Vec::with_capacity(cells.len());
+        for (i, cell) in cells.into_iter().enumerate() {
+            occupancy.insert(cell);
+            let pos = cell.to_translation(&grid);
+            let color = if i == 0 {
+                palette.head(handle)
+            } else {
+                palette.body
+            };
+            // The sprite bundle is inserted whole, so a pooled segment comes back visible and
+            // with nothing left over from its last snake.
+            let mut segment = match pool.pop() {
+                Some(entity) => commands.entity(entity),
+                None => commands.spawn(),
+            };
+            segment
+                .insert_bundle(Segment::new_sprite_bundle(pos.x, pos.y, box_size, color))
+                .insert(Segment {
+                    pos: cell,
+                    curr_dir: dir,
+                    next_dir: dir,
+                })
+                .insert(Rollback::new(rip.next_id()));
+            if i == 0 {
+                segment.insert(Head);
+            }
+            snake.push(segment.id());
+        }
+        let mut player = commands.spawn();
+        player
+            .insert(Player { handle })
+            .insert(Snake(snake))
+            .insert(PlayerScore::default())
+            .insert(InputBuffer::default())
+            .insert(SpeedBoost::default())
+            .insert(Invincible::default())
+            .insert(Rollback::new(rip.next_id()));
+        if bot_handles.contains(&handle) {
+            player.insert(BotController);
+        }
+    }
+}
+
+// Turns pressed while paused are dropped, like the paused frames themselves, so a replay never
+// needs them.
+pub fn update_dir(
+    mut snake_query: Query<(&Player, &Snake, &mut InputBuffer), Without<BotController>>,
+    segment_query: Query<&Segment>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    paused: Res<Paused>,
+) {
+    if **paused {
+        return;
+    }
+    for (player, snake, mut buffer) in snake_query.iter_mut() {
+        let (input, status) = match inputs.get(player.handle) {
+            Some(input) => *input,
+            None => continue,
+        };
+        if let InputStatus::Disconnected = status {
+            continue;
+        }
+        let head_seg = match snake.first().and_then(|head| segment_query.get(*head).ok()) {
+            Some(head_seg) => head_seg,
+            None => continue,
+        };
+        for dir in decode_turns(input.inp) {
+            buffer.push(dir, head_seg.curr_dir);
+        }
+    }
+}
+
+// Counts the frame before anything else in the simulation runs, so every system reading
+// `FrameCount` this frame sees the same value on every peer. Paused frames aren't counted: nothing
+// moves in them, and a replay, which never pauses, has to reach the same frame numbers.
+pub fn tick_frame(mut frame_count: ResMut<FrameCount>, paused: Res<Paused>) {
+    if !**paused {
+        **frame_count += 1;
+    }
+}
+
+pub fn tick_countdown(
+    mut countdown: ResMut<StartCountdown>,
+    paused: Res<Paused>,
+    sim: Res<SimConfig>,
+) {
+    if !**paused {
+        countdown.tick(Duration::from_secs_f64(sim.tick_secs()));
+    }
+}
+
+pub fn move_snake(
+    app_state: Res<State<AppState>>,
+    mut snake_query: Query<(
+        &Snake,
+        Option<&mut InputBuffer>,
+        Option<&mut SpeedBoost>,
+        Option<&Invincible>,
+    )>,
+    mut segment_query: Query<&mut Segment>,
+    portal_query: Query<(&Transform, &Portal)>,
+    grid: Res<GridConfig>,
+    boundary_mode: Res<BoundaryMode>,
+    speed: Res<GameSpeed>,
+    mut move_timer: ResMut<MoveTimer>,
+    mut occupancy: ResMut<Occupancy>,
+    countdown: Res<StartCountdown>,
+    grace: Res<GracePeriod>,
+    assist: Res<AssistMode>,
+    max_players: Res<MaxPlayers>,
+    (frame_count, sim): (Res<FrameCount>, Res<SimConfig>),
+    paused: Res<Paused>,
+    match_over: Res<MatchOver>,
+) {
+    // The board stays up, frozen, behind the game-over screen, and already from the frame the match
+    // is decided while that frame waits to be confirmed.
+    let frozen = **paused || !countdown.finished() || **match_over;
+    if app_state.current() != &AppState::InGame || frozen {
+        return;
+    }
+    let tick_secs = sim.tick_secs() as f32;
+    **move_timer += tick_secs;
+    let step = **move_timer >= speed.interval;
+    if step {
+        **move_timer -= speed.interval;
+    }
+    let in_grace = *boundary_mode == BoundaryMode::Wall && grace.active(&frame_count);
+    let assisted = assist.active(*max_players);
+    let portals = portal_query
+        .iter()
+        .map(|(trans, portal)| (grid.cell_at(trans.translation), portal.exit))
+        .collect::<HashMap<_, _>>();
+
+    for (snake, buffer, boost, invincible) in snake_query.iter_mut() {
+        // A boosted snake goes by its own timer instead of the shared one until the boost runs out.
+        let step = match boost {
+            Some(mut boost) if boost.active() => {
+                let interval = boost.interval(speed.interval);
+                boost.frames -= 1;
+                boost.move_timer += tick_secs;
+                let step = boost.move_timer >= interval;
+                if step {
+                    boost.move_timer -= interval;
+                }
+                step
+            }
+            _ => step,
+        };
+        if !step {
+            continue;
+        }
+        let head = match snake.first() {
+            Some(head) => *head,
+            None => continue,
+        };
+        if let Some(err) = snake.iter().find_map(|seg| segment_query.get(*seg).err()) {
+            warn!(
+                "Skipping the move of a snake with a missing segment: {}",
+                err
+            );
+            continue;
+        }
+
+        let cell = {
+            let mut head_seg = match segment_query.get_mut(head) {
+                Ok(head_seg) => head_seg,
+                Err(_) => continue,
+            };
+            if let Some(dir) = buffer.and_then(|mut buffer| buffer.pop()) {
+                head_seg.next_dir = dir;
+            }
+            let cell = head_seg.pos.step(head_seg.next_dir);
+            let cell = if *boundary_mode == BoundaryMode::Wrap {
+                grid.wrap(cell)
+            } else {
+                cell
+            };
+            // Stepping into a portal puts the head on its exit, still facing the same way. Only the
+            // cell moved into is checked, so the next move leaves the exit instead of going back.
+            portals.get(&cell).copied().unwrap_or(cell)
+        };
+        // During the grace period a snake heading off the board waits against the wall for a
+        // turn instead, so it's still on the board when the period ends. A turn into the wall is
+        // dropped, leaving the player free to pick any other way out. A shielded snake waits the
+        // same way until its shield runs out, and a snake with the wall assist always does.
+        let shielded = invincible.map_or(false, |invincible| invincible.active());
+        if (in_grace || shielded || assisted) && !grid.in_bounds(cell) {
+            if let Ok(mut head_seg) = segment_query.get_mut(head) {
+                head_seg.next_dir = head_seg.curr_dir;
+            }
+            continue;
+        }
+
+        let mut ahead = {
+            let mut head_seg = match segment_query.get_mut(head) {
+                Ok(head_seg) => head_seg,
+                Err(_) => continue,
+            };
+            let old = (head_seg.curr_dir, head_seg.pos);
+            head_seg.pos = cell;
+            head_seg.curr_dir = head_seg.next_dir;
+            old
+        };
+        // Every body segment steps into the cell the segment ahead of it held before this move, so
+        // walking from the head down, each one takes over the old place of the one before and
+        // hands its own on. The direction it moves in is the one the segment ahead took to reach
+        // that cell, i.e. the ahead segment's old `curr_dir`. `next_dir` only matters for the head,
+        // so the body doesn't pick up the head's queued turn.
+        for seg in snake.iter().skip(1) {
+            let mut seg = match segment_query.get_mut(*seg) {
+                Ok(seg) => seg,
+                Err(_) => continue,
+            };
+            let old = (seg.curr_dir, seg.pos);
+            seg.curr_dir = ahead.0;
+            seg.next_dir = ahead.0;
+            seg.pos = ahead.1;
+            ahead = old;
+        }
+
+        // Only the ends change cells: the head moves into a new one and the tail leaves its old one.
+        let (_, old_tail) = ahead;
+        occupancy.remove(old_tail);
+        occupancy.insert(cell);
+    }
+}
+
+// Closes the next ring of the `ShrinkingArena` when it's due, by `FrameCount` so every peer closes
+// it on the same frame. The ring's cells become obstacles, which are rolled back like snakes, food
+// and power-ups on them are lost, and a snake with any segment there dies. Nothing closes while
+// paused.
+pub fn shrink_arena(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    app_state: Res<State<AppState>>,
+    arena: Res<ShrinkingArena>,
+    mut closed: ResMut<ClosedRings>,
+    frame_count: Res<FrameCount>,
+    sim: Res<SimConfig>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    snake_query: Query<(&Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    food_query: Query<(Entity, &Transform), Or<(With<Food>, With<PowerUp>)>>,
+    mut occupancy: ResMut<Occupancy>,
+    mut collision_events: EventWriter<CollisionEvent>,
+    paused: Res<Paused>,
+) {
+    let in_game = app_state.current() == &AppState::InGame && !**paused;
+    let interval = match **arena {
+        Some(interval) if interval > 0 && in_game => interval,
+        _ => return,
+    };
+    let due = frame_count.saturating_sub(sim.ticks(COUNTDOWN_FRAMES)) / sim.ticks(interval);
+    if **closed >= due.min(grid.closable_rings()) {
+        return;
+    }
+    let ring = **closed as i32;
+    **closed += 1;
+
+    for cell in grid.cells().filter(|cell| grid.ring(*cell) == ring) {
+        occupancy.insert(cell);
+        let pos = cell.to_translation(&grid);
+        commands
+            .spawn_bundle(Obstacle::new_sprite_bundle(
+                pos.x,
+                pos.y,
+                grid.box_size,
+                palette.obstacle,
+            ))
+            .insert(Obstacle)
+            .insert(Rollback::new(rip.next_id()));
+    }
+    for (food, trans) in food_query.iter() {
+        let cell = grid.cell_at(trans.translation);
+        if grid.ring(cell) == ring {
+            occupancy.remove(cell);
+            commands.entity(food).despawn_recursive();
+        }
+    }
+    let mut snakes = snake_query.iter().collect::<Vec<_>>();
+    snakes.sort_by_key(|(player, _)| player.handle);
+    for (player, snake) in snakes {
+        let caught = snake.iter().any(|seg| {
+            segment_query
+                .get(*seg)
+                .map_or(false, |seg| grid.ring(seg.pos) <= ring)
+        });
+        if caught {
+            collision_events.send(CollisionEvent::Deadly {
+                handle: player.handle,
+            });
+        }
+    }
+}
+
+// Everything is compared by grid cell. The walls sit just outside the board, so in Wall mode
+// leaving the board is what kills, once the grace period is over.
+pub fn check_collisions(
+    mut commands: Commands,
+    snake_query: Query<(&Player, &Snake, Option<&Invincible>)>,
+    segment_query: Query<&Segment>,
+    food_query: Query<(Entity, &Transform, Option<&FoodValue>), With<Food>>,
+    obstacle_query: Query<&Transform, With<Obstacle>>,
+    mut collision_events: EventWriter<CollisionEvent>,
+    mut game_events: EventWriter<GameEvent>,
+    mut occupancy: ResMut<Occupancy>,
+    grid: Res<GridConfig>,
+    boundary_mode: Res<BoundaryMode>,
+    grace: Res<GracePeriod>,
+    frame_count: Res<FrameCount>,
+    match_over: Res<MatchOver>,
+) {
+    // The snakes that ended the match are left where they died, so they'd keep colliding.
+    if **match_over {
+        return;
+    }
+    let boundary_deadly = *boundary_mode == BoundaryMode::Wall && !grace.active(&frame_count);
+    let cell_of = |seg: &Entity| segment_query.get(*seg).ok().map(|seg| seg.pos);
+    let obstacles = obstacle_query
+        .iter()
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<HashSet<_>>();
+    // Query order isn't guaranteed to match between peers, so snakes are handled by ascending
+    // handle. That decides, for instance, who gets a food two heads reach on the same frame.
+    let mut snakes = snake_query.iter().collect::<Vec<_>>();
+    snakes.sort_by_key(|(player, _, _)| player.handle);
+    // Every snake's cells are looked up once, so each head is checked against all the bodies in
+    // constant time however long they get. Heads are counted, since a head is always on its own.
+    let mut body_cells = HashSet::new();
+    let mut head_cells = HashMap::new();
+    for (_, snake, _) in snakes.iter() {
+        if let Some(head) = snake.first().and_then(cell_of) {
+            *head_cells.entry(head).or_insert(0) += 1;
+        }
+        body_cells.extend(snake.iter().skip(1).filter_map(cell_of));
+    }
+    let mut eaten = HashSet::new();
+    for &(player, snake, invincible) in snakes.iter() {
+        let handle = player.handle;
+        let head = match snake.first().and_then(cell_of) {
+            Some(head) => head,
+            None => continue,
+        };
+        let mut deadly = false;
+        if boundary_deadly && !grid.in_bounds(head) {
+            deadly = true;
+        }
+
+        if obstacles.contains(&head) {
+            deadly = true;
+        }
+
+        // Running into a body kills, its own or another snake's. Another snake's head counts too,
+        // so when two heads meet in the same cell both snakes see the collision and both die.
+        if body_cells.contains(&head) || head_cells.get(&head).copied().unwrap_or(0) > 1 {
+            deadly = true;
+        }
+
+        for (food_entity, food_transform, value) in food_query.iter() {
+            let cell = grid.cell_at(food_transform.translation);
+            if cell == head && eaten.insert(food_entity) {
+                let value = value.copied().unwrap_or_default();
+                // Poison that would take the last segment kills; `add_segment` never sees it.
+                if value.kills(snake.len()) {
+                    deadly = true;
+                } else {
+                    collision_events.send(CollisionEvent::Safe {
+                        handle,
+                        food: food_entity,
+                    });
+                }
+                game_events.send(GameEvent::FoodEaten { cell, value });
+                commands.entity(food_entity).despawn_recursive();
+                occupancy.remove(cell);
+            }
+        }
+
+        // A shield saves the snake from all of it, though an arena ring closing on it still kills.
+        if deadly && !invincible.map_or(false, |invincible| invincible.active()) {
+            collision_events.send(CollisionEvent::Deadly { handle });
+        }
+    }
+}
+
+// The `count` cells trailing straight back from `from` when heading `dir`, nearest first.
+pub fn trailing_cells(from: GridPos, dir: Direction, count: usize) -> Vec<GridPos> {
+    let behind = dir.opposite();
+    std::iter::successors(Some(from), |cell| Some(cell.step(behind)))
+        .skip(1)
+        .take(count)
+        .collect()
+}
+
+// Spawns `count` body segments behind `tail_seg`, all moving like it, and returns them in the
+// order they belong on the end of the `Snake`, each with its cell. They line up straight back from
+// the tail until that leaves the board or runs into something, and the rest stack on the last cell
+// reached, to unfold from it as the snake moves on.
+pub fn spawn_segments(
+    commands: &mut Commands,
+    rip: &mut RollbackIdProvider,
+    grid: &GridConfig,
+    occupancy: &Occupancy,
+    color: Color,
+    count: usize,
+    tail_seg: Segment,
+) -> Vec<(Entity, GridPos)> {
+    let mut behind = trailing_cells(tail_seg.pos, tail_seg.curr_dir, count)
+        .into_iter()
+        .take_while(|cell| grid.in_bounds(*cell) && !occupancy.contains(*cell));
+    let mut last = tail_seg.pos;
+    (0..count)
+        .map(|_| {
+            let cell = behind.next().unwrap_or(last);
+            last = cell;
+            let pos = cell.to_translation(grid);
+            let entity = commands
+                .spawn_bundle(Segment::new_sprite_bundle(
+                    pos.x,
+                    pos.y,
+                    grid.box_size,
+                    color,
+                ))
+                .insert(Segment {
+                    pos: cell,
+                    ..tail_seg
+                })
+                .insert(Rollback::new(rip.next_id()))
+                .id();
+            (entity, cell)
+        })
+        .collect()
+}
+
+pub fn add_segment(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    mut snake_query: Query<(&Player, &mut Snake, &mut PlayerScore)>,
+    segment_query: Query<&Segment>,
+    food_query: Query<&FoodValue>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut game_events: EventWriter<GameEvent>,
+    mut score: ResMut<Score>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    mut occupancy: ResMut<Occupancy>,
+    paused: Res<Paused>,
+) {
+    if **paused {
+        return;
+    }
+    for event in sorted_collisions(&mut collision_events).iter() {
+        if let CollisionEvent::Safe { handle, food } = event {
+            let (mut snake, mut player_score) = match snake_query
+                .iter_mut()
+                .find(|(player, _, _)| player.handle == *handle)
+            {
+                Some((_, snake, player_score)) => (snake, player_score),
+                None => continue,
+            };
+            let value = food_query.get(*food).copied().unwrap_or_default();
+            **score += value.score;
+            **player_score += value.score;
+            if value.kind == FoodKind::Poison {
+                let keep = snake.len() - value.segments as usize;
+                for seg in snake.split_off(keep) {
+                    if let Ok(seg) = segment_query.get(seg) {
+                        occupancy.remove(seg.pos);
+                    }
+                    commands.entity(seg).despawn_recursive();
+                }
+                game_events.send(GameEvent::SnakeShrank {
+                    handle: *handle,
+                    len: snake.len(),
+                });
+                continue;
+            }
+            let tail_seg = match snake.last().and_then(|tail| segment_query.get(*tail).ok()) {
+                Some(tail_seg) => *tail_seg,
+                None => continue,
+            };
+            let segments = spawn_segments(
+                &mut commands,
+                &mut rip,
+                &grid,
+                &occupancy,
+                palette.body,
+                value.segments as usize,
+                tail_seg,
+            );
+            for (entity, cell) in segments {
+                occupancy.insert(cell);
+                snake.push(entity);
+            }
+            game_events.send(GameEvent::SnakeGrew {
+                handle: *handle,
+                len: snake.len(),
+            });
+        }
+    }
+}
+
+pub fn spawn_food(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    app_state: Res<State<AppState>>,
+    mut occupancy: ResMut<Occupancy>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    mut food_timer: ResMut<FoodTimer>,
+    settings: Res<FoodSettings>,
+    food_query: Query<Entity, With<Food>>,
+    mut collisions: EventReader<CollisionEvent>,
+    mut rng: ResMut<GameRng>,
+    mut game_events: EventWriter<GameEvent>,
+    paused: Res<Paused>,
+    sim: Res<SimConfig>,
+) {
+    if app_state.current() != &AppState::InGame || **paused {
+        return;
+    }
+    // Food eaten this frame is despawned only once the stage's commands are applied, so it's left
+    // out of the count rather than holding a place that's already free. Food entities are rolled
+    // back, so every peer counts the same ones.
+    let eaten = collisions
+        .iter()
+        .filter_map(|event| match event {
+            CollisionEvent::Safe { food, .. } => Some(*food),
+            CollisionEvent::Deadly { .. } => None,
+        })
+        .collect::<HashSet<_>>();
+    let active = food_query.iter().filter(|food| !eaten.contains(food));
+    if active.count() >= settings.max_active as usize {
+        return;
+    }
+    **food_timer += 1;
+    if **food_timer < sim.ticks(settings.spawn_frames()) {
+        return;
+    }
+    **food_timer = 0;
+    let cell = match occupancy.free_cell(&grid, &mut rng) {
+        Some(cell) => cell,
+        None => return,
+    };
+    let value = if rng.chance(1. / GOLDEN_FOOD_ODDS as f64) {
+        FoodValue::GOLDEN
+    } else if rng.chance(1. / POISON_FOOD_ODDS as f64) {
+        FoodValue::POISON
+    } else if rng.chance(1. / MAGNET_FOOD_ODDS as f64) {
+        FoodValue::MAGNET
+    } else {
+        FoodValue::NORMAL
+    };
+    occupancy.insert(cell);
+    let pos = cell.to_translation(&grid);
+    commands
+        .spawn_bundle(Food::new_sprite_bundle(
+            pos.x,
+            pos.y,
+            grid.box_size,
+            value.color(&palette),
+        ))
+        .insert(Food)
+        .insert(value)
+        .insert(Rollback::new(rip.next_id()));
+    game_events.send(GameEvent::FoodSpawned { cell });
+}
+
+// The cell next to `from` that's closest to `target`, if any free one is closer than `from`. Ties
+// go to the first direction in `Direction::ALL`.
+pub fn magnet_step(
+    from: GridPos,
+    target: GridPos,
+    grid: &GridConfig,
+    occupancy: &Occupancy,
+) -> Option<GridPos> {
+    Direction::ALL
+        .iter()
+        .map(|dir| from.step(*dir))
+        .filter(|cell| grid.in_bounds(*cell) && !occupancy.contains(*cell))
+        .filter(|cell| cell.manhattan(target) < from.manhattan(target))
+        .min_by_key(|cell| cell.manhattan(target))
+}
+
+// Every `MAGNET_STEP_FRAMES` by `FrameCount`, magnet food moves a cell towards the nearest head,
+// the lowest cell on a tie. It waits rather than move onto anything, so it's eaten like any other
+// food once a head reaches it. Food is moved in cell order, so where two magnets want the same
+// cell every peer lets the same one have it.
+pub fn move_food(
+    app_state: Res<State<AppState>>,
+    countdown: Res<StartCountdown>,
+    frame_count: Res<FrameCount>,
+    sim: Res<SimConfig>,
+    paused: Res<Paused>,
+    snake_query: Query<&Snake>,
+    segment_query: Query<&Segment>,
+    mut food_query: Query<(&FoodValue, &mut Transform), With<Food>>,
+    grid: Res<GridConfig>,
+    mut occupancy: ResMut<Occupancy>,
+) {
+    if app_state.current() != &AppState::InGame || **paused || !countdown.finished() {
+        return;
+    }
+    if **frame_count % sim.ticks(MAGNET_STEP_FRAMES) != 0 {
+        return;
+    }
+    let heads = snake_query
+        .iter()
+        .filter_map(|snake| segment_query.get(*snake.first()?).ok())
+        .map(|seg| seg.pos)
+        .collect::<Vec<_>>();
+    let mut magnets = food_query
+        .iter_mut()
+        .filter(|(value, _)| value.kind == FoodKind::Magnet)
+        .map(|(_, trans)| (grid.cell_at(trans.translation), trans))
+        .collect::<Vec<_>>();
+    magnets.sort_by_key(|(cell, _)| *cell);
+    for (cell, mut trans) in magnets {
+        let target = heads
+            .iter()
+            .copied()
+            .min_by_key(|head| (head.manhattan(cell), *head));
+        let next = match target.and_then(|target| magnet_step(cell, target, &grid, &occupancy)) {
+            Some(next) => next,
+            None => continue,
+        };
+        occupancy.remove(cell);
+        occupancy.insert(next);
+        let pos = next.to_translation(&grid);
+        trans.translation.x = pos.x;
+        trans.translation.y = pos.y;
+    }
+}
+
+// Spawned every `POWER_UP_SPAWN_FRAMES` by `FrameCount`, so every peer spawns it on the same frame
+// and draws its cell from `GameRng` in the same order.
+pub fn spawn_power_up(
+    mut commands: Commands,
+    mut rip: ResMut<RollbackIdProvider>,
+    app_state: Res<State<AppState>>,
+    frame_count: Res<FrameCount>,
+    mut occupancy: ResMut<Occupancy>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    power_up_query: Query<(), With<PowerUp>>,
+    mut rng: ResMut<GameRng>,
+    paused: Res<Paused>,
+    sim: Res<SimConfig>,
+) {
+    if app_state.current() != &AppState::InGame || **paused {
+        return;
+    }
+    let frame = frame_count.saturating_sub(sim.ticks(COUNTDOWN_FRAMES));
+    if frame == 0 || frame % sim.ticks(POWER_UP_SPAWN_FRAMES) != 0 || !power_up_query.is_empty() {
+        return;
+    }
+    let cell = match occupancy.free_cell(&grid, &mut rng) {
+        Some(cell) => cell,
+        None => return,
+    };
+    let power_up = match rng.choose(&PowerUp::ALL) {
+        Some(power_up) => *power_up,
+        None => return,
+    };
+    occupancy.insert(cell);
+    let pos = cell.to_translation(&grid);
+    commands
+        .spawn_bundle(PowerUp::new_sprite_bundle(
+            pos.x,
+            pos.y,
+            grid.box_size,
+            power_up.color(&palette),
+        ))
+        .insert(power_up)
+        .insert(Rollback::new(rip.next_id()));
+}
+
+// Like food, a power-up two heads reach on the same frame goes to the lower handle.
+pub fn collect_power_ups(
+    mut commands: Commands,
+    mut snake_query: Query<(&Player, &Snake, &mut SpeedBoost, &mut Invincible)>,
+    segment_query: Query<&Segment>,
+    power_up_query: Query<(Entity, &Transform, &PowerUp)>,
+    mut game_events: EventWriter<GameEvent>,
+    mut occupancy: ResMut<Occupancy>,
+    grid: Res<GridConfig>,
+    sim: Res<SimConfig>,
+) {
+    let mut snakes = snake_query.iter_mut().collect::<Vec<_>>();
+    snakes.sort_by_key(|(player, _, _, _)| player.handle);
+    let mut collected = HashSet::new();
+    for (player, snake, boost, invincible) in snakes.iter_mut() {
+        let head = match snake.first().and_then(|head| segment_query.get(*head).ok()) {
+            Some(head_seg) => head_seg.pos,
+            None => continue,
+        };
+        for (entity, trans, power_up) in power_up_query.iter() {
+            let cell = grid.cell_at(trans.translation);
+            if cell != head || !collected.insert(entity) {
+                continue;
+            }
+            match power_up {
+                PowerUp::Speed => {
+                    **boost = SpeedBoost {
+                        frames: sim.ticks(SPEED_BOOST_FRAMES),
+                        move_timer: 0.,
+                    }
+                }
+                PowerUp::Shield => **invincible = Invincible(sim.ticks(SHIELD_FRAMES)),
+            }
+            game_events.send(GameEvent::PowerUpCollected {
+                handle: player.handle,
+                kind: *power_up,
+            });
+            commands.entity(entity).despawn();
+            occupancy.remove(cell);
+        }
+    }
+}
+
+pub fn tick_invincibility(
+    app_state: Res<State<AppState>>,
+    mut query: Query<&mut Invincible>,
+    paused: Res<Paused>,
+) {
+    if app_state.current() != &AppState::InGame || **paused {
+        return;
+    }
+    for mut invincible in query.iter_mut() {
+        if invincible.active() {
+            **invincible -= 1;
+        }
+    }
+}
+
+// Moves every segment's sprite onto its cell, after any rollback or step this frame. Only the
+// `Segment` decides where a snake is, so this is just drawing.
+pub fn sync_segment_transforms(
+    grid: Res<GridConfig>,
+    mut segment_query: Query<(&Segment, &mut Transform)>,
+) {
+    for (seg, mut trans) in segment_query.iter_mut() {
+        let pos = seg.pos.to_translation(&grid);
+        trans.translation.x = pos.x;
+        trans.translation.y = pos.y;
+    }
+}
+
+// Where a segment moving from `from` to `to` is drawn `t` of the way through the move. A jump of
+// more than one cell, through a wrap or a portal, isn't drawn in between.
+pub fn interpolated_translation(from: GridPos, to: GridPos, t: f32, grid: &GridConfig) -> Vec3 {
+    let end = to.to_translation(grid);
+    if from.manhattan(to) > 1 {
+        return end;
+    }
+    from.to_translation(grid).lerp(end, t.clamp(0., 1.))
+}
+
+// Slides every segment from its last cell to its new one over the course of a move, by how far the
+// snake's move timer has come since it stepped. The two cells are tracked here, from what the
+// `Segment`s say, so the simulation still only ever steps whole cells and rollback is unaffected.
+// Outside a game the board is drawn on its cells. Runs after `sync_segment_transforms`, which it
+// overrides.
+pub fn interpolate_movement(
+    app_state: Res<State<AppState>>,
+    grid: Res<GridConfig>,
+    speed: Res<GameSpeed>,
+    move_timer: Res<MoveTimer>,
+    snake_query: Query<(&Snake, Option<&SpeedBoost>)>,
+    mut segment_query: Query<(&Segment, &mut Transform)>,
+    mut moves: Local<HashMap<Entity, (GridPos, GridPos)>>,
+) {
+    let in_game = app_state.current() == &AppState::InGame;
+    let mut seen = HashSet::new();
+    for (snake, boost) in snake_query.iter() {
+        let t = match boost {
+            _ if !in_game => 1.,
+            Some(boost) if boost.active() => boost.move_timer / boost.interval(speed.interval),
+            _ => **move_timer / speed.interval,
+        };
+        for entity in snake.iter() {
+            if let Ok((seg, mut trans)) = segment_query.get_mut(*entity) {
+                let (from, to) = moves.entry(*entity).or_insert((seg.pos, seg.pos));
+                if *to != seg.pos {
+                    *from = *to;
+                    *to = seg.pos;
+                }
+                let pos = interpolated_translation(*from, *to, t, &grid);
+                trans.translation.x = pos.x;
+                trans.translation.y = pos.y;
+                seen.insert(*entity);
+            }
+        }
+    }
+    moves.retain(|entity, _| seen.contains(entity));
+}
+
+// Gives every segment a glow of its own, hidden until its snake is shielded. Pooled segments keep
+// theirs, and a segment a rollback brings back gets a new one. Like the power-up flash the glow is
+// only drawn, so it isn't part of the simulation.
+pub fn spawn_shield_glow(
+    mut commands: Commands,
+    segment_query: Query<(Entity, Option<&Children>), Added<Segment>>,
+    glow_query: Query<(), With<ShieldGlow>>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+) {
+    let size = grid.box_size * ShieldGlow::SCALE;
+    for (entity, children) in segment_query.iter() {
+        let has_glow = children.map_or(false, |children| {
+            children.iter().any(|child| glow_query.get(*child).is_ok())
+        });
+        if has_glow {
+            continue;
+        }
+        let glow = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: palette.shield,
+                    custom_size: Some(Vec2::new(size, size)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(0., 0., ShieldGlow::Z),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(ShieldGlow)
+            .id();
+        commands.entity(entity).add_child(glow);
+    }
+}
+
+// Shows the glow of every segment of a shielded snake and hides the rest. A segment's visibility
+// isn't passed on to its children, so this hides the glow of a pooled segment too.
+pub fn draw_shield_glow(
+    snake_query: Query<(&Snake, &Invincible)>,
+    mut glow_query: Query<(&Parent, &mut Visibility), With<ShieldGlow>>,
+) {
+    let shielded = snake_query
+        .iter()
+        .filter(|(_, invincible)| invincible.active())
+        .flat_map(|(snake, _)| snake.iter().copied())
+        .collect::<HashSet<_>>();
+    for (parent, mut visibility) in glow_query.iter_mut() {
+        let visible = shielded.contains(&parent.0);
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    }
+}
+
+// Puts a colorblind icon on every food and obstacle as it spawns. It's a child of the food, so it
+// follows it when it moves, and like the shield glow it's left out of the simulation.
+pub fn spawn_shape_overlays(
+    mut commands: Commands,
+    food_query: Query<(Entity, Option<&FoodValue>), Added<Food>>,
+    obstacle_query: Query<Entity, Added<Obstacle>>,
+    accessibility: Res<AccessibilityConfig>,
+    shape_images: Option<Res<ShapeImages>>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+) {
+    let shape_images = match shape_images {
+        Some(shape_images) if accessibility.colorblind_shapes => shape_images,
+        _ => return,
+    };
+    let food = food_query.iter().map(|(entity, value)| {
+        let kind = value.copied().unwrap_or_default().kind;
+        (entity, ShapeIcon::for_food(kind))
+    });
+    let obstacles = obstacle_query
+        .iter()
+        .map(|entity| (entity, ShapeIcon::Square));
+    let size = grid.box_size * ShapeOverlay::SCALE;
+    for (entity, icon) in food.chain(obstacles) {
+        let image = match shape_images.get(icon) {
+            Some(image) => image.clone(),
+            None => continue,
+        };
+        let overlay = commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color: palette.shape_overlay,
+                    custom_size: Some(Vec2::new(size, size)),
+                    ..default()
+                },
+                texture: image,
+                transform: Transform::from_xyz(0., 0., ShapeOverlay::Z),
+                ..default()
+            })
+            .insert(ShapeOverlay(icon))
+            .id();
+        commands.entity(entity).add_child(overlay);
+    }
+}
+
+// bevy_ggrs despawns the entities a rollback takes back without their children, so the glows and
+// icons they leave behind are cleaned up here.
+pub fn despawn_orphaned_overlays(
+    mut commands: Commands,
+    overlay_query: Query<(Entity, &Parent), Or<(With<ShieldGlow>, With<ShapeOverlay>)>>,
+    parent_query: Query<()>,
+) {
+    for (entity, parent) in overlay_query.iter() {
+        if parent_query.get(parent.0).is_err() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn setup_shape_images(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let shape_images = ShapeIcon::ALL
+        .iter()
+        .map(|icon| (*icon, images.add(ShapeImages::image(*icon))))
+        .collect();
+    commands.insert_resource(ShapeImages(shape_images));
+}
+
+pub fn setup_segment_images(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let mut add = |shape| images.add(SegmentImages::image(shape));
+    commands.insert_resource(SegmentImages {
+        straight: add(SegmentShape::Straight),
+        corner: add(SegmentShape::Corner),
+        head: add(SegmentShape::Head),
+        tail: add(SegmentShape::Tail),
+    });
+}
+
+// Picks every segment's image from the way it came in and the way the segment ahead left, so the
+// body joins up round its turns. Only the image and rotation change, from `Segment`s the
+// simulation already settled, so this never feeds back into the game.
+pub fn shape_segments(
+    images: Option<Res<SegmentImages>>,
+    snake_query: Query<&Snake>,
+    mut segment_query: Query<(&Segment, &mut Sprite, &mut Handle<Image>, &mut Transform)>,
+) {
+    let images = match images {
+        Some(images) => images,
+        None => return,
+    };
+    for snake in snake_query.iter() {
+        let dirs = snake
+            .iter()
+            .map(|seg| segment_query.get(*seg).ok().map(|(seg, ..)| seg.curr_dir))
+            .collect::<Option<Vec<_>>>();
+        let dirs = match dirs {
+            Some(dirs) => dirs,
+            None => continue,
+        };
+        for (i, seg) in snake.iter().enumerate() {
+            let is_tail = i > 0 && i == dirs.len() - 1;
+            let in_dir = Some(dirs[i]).filter(|_| !is_tail);
+            let out_dir = i.checked_sub(1).map(|ahead| dirs[ahead]);
+            let shape = SegmentSprite::for_dirs(in_dir, out_dir);
+            if let Ok((_, mut sprite, mut image, mut trans)) = segment_query.get_mut(*seg) {
+                let handle = images.get(shape.shape);
+                if *image != *handle {
+                    *image = handle.clone();
+                }
+                if sprite.flip_x != shape.flip_x {
+                    sprite.flip_x = shape.flip_x;
+                }
+                trans.rotation = Quat::from_rotation_z(shape.facing.angle());
+            }
+        }
+    }
+}
+
+// Flashes a boosted snake between its own colors and the power-up's. It's only drawn, so it can
+// run outside the simulation and just follows whatever `SpeedBoost` the rollback left.
+pub fn pulse_boosted_snakes(
+    snake_query: Query<(&Player, &Snake, &SpeedBoost)>,
+    mut sprite_query: Query<&mut Sprite, With<Segment>>,
+    palette: Res<Palette>,
+    sim: Res<SimConfig>,
+) {
+    let pulse_frames = sim.ticks(BOOST_PULSE_FRAMES);
+    let mix = |from: Color, to: Color, t: f32| {
+        let (from, to) = (from.as_rgba_f32(), to.as_rgba_f32());
+        let channel = |i: usize| from[i] + (to[i] - from[i]) * t;
+        Color::rgba(channel(0), channel(1), channel(2), channel(3))
+    };
+    for (player, snake, boost) in snake_query.iter() {
+        let pulse = if boost.active() {
+            let phase = (boost.frames % pulse_frames) as f32 / pulse_frames as f32;
+            0.5 - 0.5 * (phase * std::f32::consts::TAU).cos()
+        } else {
+            0.
+        };
+        for (i, seg) in snake.iter().enumerate() {
+            if let Ok(mut sprite) = sprite_query.get_mut(*seg) {
+                let color = if i == 0 {
+                    palette.head(player.handle)
+                } else {
+                    palette.body
+                };
+                sprite.color = mix(color, palette.power_up, pulse);
+            }
+        }
+    }
+}
+
+// Leaves a `Trail` on every cell a segment moved off since the last frame without another moving
+// in. It only compares where segments are now with where they were when it last ran, so whatever
+// the simulation or a rollback did in between, it never feeds back into the game.
+pub fn spawn_trails(
+    mut commands: Commands,
+    segment_query: Query<(Entity, &Segment)>,
+    trail_query: Query<(), With<Trail>>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    mut last_cells: Local<HashMap<Entity, GridPos>>,
+) {
+    let cells = segment_query
+        .iter()
+        .map(|(entity, seg)| (entity, seg.pos))
+        .collect::<HashMap<_, _>>();
+    let covered = cells.values().copied().collect::<HashSet<_>>();
+    let vacated = cells
+        .iter()
+        .filter_map(|(entity, cell)| last_cells.get(entity).filter(|old| *old != cell))
+        .filter(|old| !covered.contains(*old))
+        .copied()
+        .collect::<HashSet<_>>();
+    let room = MAX_TRAIL_SPRITES.saturating_sub(trail_query.iter().count());
+    let mut color = palette.body;
+    color.set_a(Trail::ALPHA);
+    for cell in vacated.into_iter().take(room) {
+        let pos = cell.to_translation(&grid);
+        commands
+            .spawn_bundle(SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(Vec2::new(grid.box_size, grid.box_size)),
+                    ..default()
+                },
+                transform: Transform::from_xyz(pos.x, pos.y, Trail::Z),
+                ..default()
+            })
+            .insert(Trail {
+                timer: Timer::from_seconds(TRAIL_SECS, false),
+            });
+    }
+    *last_cells = cells;
+}
+
+pub fn fade_trails(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut trail_query: Query<(Entity, &mut Trail, &mut Sprite)>,
+) {
+    for (entity, mut trail, mut sprite) in trail_query.iter_mut() {
+        if trail.timer.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        } else {
+            let alpha = Trail::ALPHA * trail.timer.percent_left();
+            sprite.color.set_a(alpha);
+        }
+    }
+}
+
+pub fn update_game_speed(
+    score: Res<Score>,
+    settings: Res<DifficultySettings>,
+    mut speed: ResMut<GameSpeed>,
+) {
+    *speed = GameSpeed::for_score(settings.move_interval, **score);
+}
+
+// Hides `entity` and puts it in the pool for `spawn_players` to reuse. Only for systems outside the
+// rollback: the pool isn't rolled back, and GGRS would respawn an entity whose `Rollback` was taken
+// away in a frame it re-simulates.
+pub fn pool_segment(commands: &mut Commands, pool: &mut SegmentPool, entity: Entity) {
+    commands
+        .entity(entity)
+        .remove::<Segment>()
+        .remove::<Head>()
+        .remove::<Rollback>()
+        .insert(Visibility { is_visible: false });
+    pool.push(entity);
+}
+
+// Counts the time trial's frames once the snakes can move, until one of them has grown to the
+// target. Growth is applied by `add_segment`, so the frame a snake reaches it is still counted.
+pub fn tick_trial_timer(
+    app_state: Res<State<AppState>>,
+    trial: Option<Res<TimeTrial>>,
+    mut timer: ResMut<TrialTimer>,
+    snake_query: Query<&Snake>,
+    countdown: Res<StartCountdown>,
+    paused: Res<Paused>,
+) {
+    let trial = match trial {
+        Some(trial) => trial,
+        None => return,
+    };
+    if app_state.current() != &AppState::InGame || **paused || !countdown.finished() {
+        return;
+    }
+    if timer.finished {
+        return;
+    }
+    timer.frames += 1;
+    let target_len = trial.target_len as usize;
+    if snake_query.iter().any(|snake| snake.len() >= target_len) {
+        timer.finished = true;
+    }
+}
+
+// A dead snake is taken off the board while the others play on. The match is decided once at most
+// one is left, or straight away if there was only one to begin with, but like the checksum the
+// game only ends on a confirmed frame. GGRS doesn't re-simulate a frame it predicted right, so
+// every frame after the decision checks again until one is confirmed, which confirms the decision
+// too.
+pub fn game_over(
+    mut commands: Commands,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut game_events: EventWriter<GameEvent>,
+    snake_query: Query<(Entity, &Player, &Snake)>,
+    segment_query: Query<&Segment>,
+    inputs: Res<PlayerInputs<GGRSConfig>>,
+    mut occupancy: ResMut<Occupancy>,
+    mut outcome: ResMut<MatchOutcome>,
+    mut match_over: ResMut<MatchOver>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if app_state.current() != &AppState::InGame {
+        return;
+    }
+    if !**match_over {
+        // A snake can hit several things at once, but it only dies once.
+        let mut dead = sorted_collisions(&mut collision_events)
+            .into_iter()
+            .filter_map(|collision| match collision {
+                CollisionEvent::Deadly { handle } => Some(handle),
+                CollisionEvent::Safe { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        dead.dedup();
+        if dead.is_empty() {
+            return;
+        }
+        for &handle in dead.iter() {
+            game_events.send(GameEvent::SnakeDied { handle });
+        }
+
+        let mut snakes = snake_query.iter().collect::<Vec<_>>();
+        snakes.sort_by_key(|(_, player, _)| player.handle);
+        let total = snakes.len();
+        let (dying, alive): (Vec<_>, Vec<_>) = snakes
+            .into_iter()
+            .partition(|(_, player, _)| dead.contains(&player.handle));
+        let decided = match alive.as_slice() {
+            // A lone snake dying just ends the game, there's no one to beat.
+            [] if total == 1 => Some(MatchOutcome::Undecided),
+            [] => Some(MatchOutcome::Draw),
+            [(_, winner, _)] if total > 1 => Some(MatchOutcome::Winner(winner.handle)),
+            _ => None,
+        };
+        match decided {
+            // The snakes that ended the match stay on the frozen board. `cleanup_game` puts their
+            // segments in the pool after the game-over screen, outside the rollback.
+            Some(decided) => {
+                *outcome = decided;
+                **match_over = true;
+            }
+            None => {
+                for (entity, _, snake) in dying {
+                    for seg in snake.iter() {
+                        if let Ok(seg) = segment_query.get(*seg) {
+                            occupancy.remove(seg.pos);
+                        }
+                        commands.entity(*seg).despawn_recursive();
+                    }
+                    commands.entity(entity).despawn();
+                }
+                return;
+            }
+        }
+    }
+    let confirmed = inputs
+        .iter()
+        .all(|(_, status)| !matches!(status, InputStatus::Predicted));
+    if confirmed {
+        // Fails if GGRS ran another frame this update after the first one queued it, which is fine.
+        let _ = app_state.set(AppState::GameOver);
+    }
+}
+
+pub fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    player_query: Query<&Player>,
+    mut paused: ResMut<Paused>,
+) {
+    if keys.just_pressed(bindings.pause) && player_query.iter().count() == 1 {
+        **paused = !**paused;
+    }
+}
+
+// Segments go back to the pool, everything else is despawned. On the way out of InGame that's only
+// for a restart from the pause menu: otherwise the board stays up behind the game-over screen.
+pub fn cleanup_game(
+    mut commands: Commands,
+    app_state: Res<State<AppState>>,
+    mut restart: ResMut<RestartRequested>,
+    segment_query: Query<Entity, With<Segment>>,
+    mut pool: ResMut<SegmentPool>,
+    query: Query<
+        Entity,
+        Or<(
+            With<Food>,
+            With<Player>,
+            With<Wall>,
+            With<Obstacle>,
+            With<PowerUp>,
+            With<Portal>,
+            With<Trail>,
+        )>,
+    >,
+) {
+    if app_state.current() == &AppState::InGame {
+        if !**restart {
+            return;
+        }
+        **restart = false;
+    }
+    commands.insert_resource(Paused::default());
+    commands.insert_resource(Occupancy::default());
+    for entity in segment_query.iter() {
+        pool_segment(&mut commands, &mut pool, entity);
+    }
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn restart_on_key(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    if keys.just_pressed(bindings.restart) {
+        // Fails if a state change is already queued this frame, which is fine.
+        let _ = app_state.set(AppState::InGame);
+    }
+}
+
+pub fn quit_on_key(
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<KeyBindings>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if keys.just_pressed(bindings.quit) {
+        exit.send(AppExit);
+    }
+}
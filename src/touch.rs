@@ -0,0 +1,46 @@
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+use crate::components::Direction;
+
+// How far, in logical pixels, a finger has to travel before the touch counts as a swipe.
+pub const SWIPE_THRESHOLD: f32 = 30.;
+
+// Touch ids that already produced a turn, so one swipe only turns once however far it goes.
+#[derive(Default)]
+pub struct SwipeTracker {
+    swiped: HashSet<u64>,
+}
+
+fn swipe_of(delta: Vec2) -> Option<Direction> {
+    if delta.length() < SWIPE_THRESHOLD {
+        None
+    } else if delta.x.abs() > delta.y.abs() && delta.x > 0. {
+        Some(Direction::Right)
+    } else if delta.x.abs() > delta.y.abs() {
+        Some(Direction::Left)
+    } else if delta.y > 0. {
+        Some(Direction::Up)
+    } else {
+        Some(Direction::Down)
+    }
+}
+
+// The direction of a touch that just travelled far enough from where it started. Positions are
+// taken with y pointing up, like the rest of bevy's window coordinates.
+pub fn swipe_direction(touches: &Touches, tracker: &mut SwipeTracker) -> Option<Direction> {
+    for touch in touches.iter_just_released() {
+        tracker.swiped.remove(&touch.id());
+    }
+    let mut dir = None;
+    for touch in touches.iter() {
+        if tracker.swiped.contains(&touch.id()) {
+            continue;
+        }
+        if let Some(swipe) = swipe_of(touch.position() - touch.start_position()) {
+            tracker.swiped.insert(touch.id());
+            dir = Some(swipe);
+        }
+    }
+    dir
+}
@@ -0,0 +1,1509 @@
+use bevy::{
+    app::AppExit,
+    asset::LoadState,
+    diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+    prelude::*,
+};
+use ggrs::{NetworkStats, P2PSession, PlayerHandle};
+use matchbox_socket::WebRtcSocket;
+use std::collections::HashMap;
+
+use crate::components::{Food, Obstacle, Player, PlayerScore, Portal, PowerUp, Segment, Snake};
+use crate::config::{
+    AssistMode, DailyChallenge, Difficulty, GridConfig, GridPos, KeyBindings, MaxPlayers, Palette,
+    SimConfig, TimeTrial, UtcDate, MAX_GRID_CELLS, MAX_PLAYERS, MIN_GRID_CELLS, MIN_PLAYERS,
+};
+use crate::highscore::{BestTimes, HighScore};
+use crate::net::{
+    default_room_url, restart_state, set_state, start_offline, GGRSConfig, LobbyError,
+    MatchboxConfig, PlayingOffline, WaitingForPeer, SIGNALING_ERROR,
+};
+use crate::resources::{
+    FrameCount, MatchOutcome, Occupancy, Paused, RestartRequested, Score, StartCountdown,
+    TrialTimer,
+};
+use crate::AppState;
+
+pub const FONT_PATH: &str = "fonts/DejaVuSans.ttf";
+pub const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+pub const SELECTED_BUTTON_COLOR: Color = Color::rgb(0.35, 0.55, 0.35);
+pub const PAUSE_DIM_COLOR: Color = Color::rgba(0., 0., 0., 0.6);
+pub const NETWORK_STATS_KEY: KeyCode = KeyCode::F3;
+pub const PERF_OVERLAY_KEY: KeyCode = KeyCode::F1;
+// The longer side of the board takes this many pixels on the minimap, whatever its size.
+pub const MINIMAP_SIZE_PX: f32 = 120.;
+pub const MINIMAP_BORDER_PX: f32 = 2.;
+// Boards with a side at least this many cells long get a minimap.
+pub const MINIMAP_MIN_CELLS: u32 = 30;
+
+#[derive(Component)]
+pub struct Scoreboard;
+
+// Every snake's own score, in its head color, under the main scoreboard.
+#[derive(Component)]
+pub struct PlayerScoreboard;
+
+#[derive(Component)]
+pub struct LobbyText;
+
+// Offered under the lobby's error when the signaling server can't be reached.
+#[derive(Component)]
+pub struct PlayOfflineButton;
+
+#[derive(Component)]
+pub struct CountdownText;
+
+#[derive(Component)]
+pub struct PeerWaitText;
+
+// Ping and rollback figures for the P2P session, in the top right corner.
+#[derive(Component)]
+pub struct NetworkStatsText;
+
+#[derive(Component)]
+pub struct DailyText;
+
+// FPS, frame time and the current frame, in the bottom left corner.
+#[derive(Component)]
+pub struct PerfOverlayText;
+
+// The time trial's clock, centred at the top.
+#[derive(Component)]
+pub struct TrialTimerText;
+
+// Whether the perf overlay is shown, toggled with `PERF_OVERLAY_KEY`. Nothing resets it, so it stays
+// the way it was left across restarts.
+#[derive(Default, Deref, DerefMut)]
+pub struct ShowPerfOverlay(pub bool);
+
+// Whether the network stats are shown, toggled with `NETWORK_STATS_KEY`.
+#[derive(Default, Deref, DerefMut)]
+pub struct ShowNetworkStats(pub bool);
+
+// A scaled-down copy of a large board in the bottom right corner, framed in the wall colour.
+#[derive(Component)]
+pub struct Minimap;
+
+// One cell's square on the minimap. It's kept once spawned, hidden while the cell is empty, so a
+// change to the board only recolours squares instead of spawning them all again.
+#[derive(Component)]
+pub struct MinimapCell(pub GridPos);
+
+// The root node of the main menu, despawned with its buttons when the menu is left.
+#[derive(Component)]
+pub struct MainMenu;
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MenuButton {
+    Play,
+    SinglePlayer,
+    Difficulty(Difficulty),
+    Quit,
+}
+
+impl MenuButton {
+    fn label(self) -> &'static str {
+        match self {
+            MenuButton::Play => "Play",
+            MenuButton::SinglePlayer => "Single player",
+            MenuButton::Difficulty(difficulty) => difficulty.label(),
+            MenuButton::Quit => "Quit",
+        }
+    }
+
+    // The difficulty buttons act as a radio group, with the picked one highlighted.
+    fn color(self, selected: Difficulty) -> UiColor {
+        match self {
+            MenuButton::Difficulty(difficulty) if difficulty == selected => {
+                SELECTED_BUTTON_COLOR.into()
+            }
+            _ => BUTTON_COLOR.into(),
+        }
+    }
+}
+
+// The match settings form between the main menu and the lobby. Online, every peer fills it in but
+// only the host's settings are played with, see `LobbySettings`.
+#[derive(Component)]
+pub struct SettingsForm;
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SettingsField {
+    Width,
+    Height,
+    Players,
+    Difficulty,
+    Assist,
+}
+
+impl SettingsField {
+    pub const ALL: [SettingsField; 5] = [
+        SettingsField::Width,
+        SettingsField::Height,
+        SettingsField::Players,
+        SettingsField::Difficulty,
+        SettingsField::Assist,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            SettingsField::Width => "Width",
+            SettingsField::Height => "Height",
+            SettingsField::Players => "Players",
+            SettingsField::Difficulty => "Difficulty",
+            SettingsField::Assist => "Assist",
+        }
+    }
+
+    fn value(
+        self,
+        grid: &GridConfig,
+        max_players: MaxPlayers,
+        difficulty: Difficulty,
+        assist: AssistMode,
+    ) -> String {
+        match self {
+            SettingsField::Width => grid.width.to_string(),
+            SettingsField::Height => grid.height.to_string(),
+            SettingsField::Players => max_players.0.to_string(),
+            SettingsField::Difficulty => difficulty.label().to_string(),
+            SettingsField::Assist if *assist => "On".to_string(),
+            SettingsField::Assist => "Off".to_string(),
+        }
+    }
+
+    // Moves the field `delta` steps within its bounds. The difficulty wraps around instead, and
+    // either button flips the assist.
+    fn step(
+        self,
+        delta: i32,
+        grid: &mut GridConfig,
+        max_players: &mut MaxPlayers,
+        difficulty: &mut Difficulty,
+        assist: &mut AssistMode,
+    ) {
+        let cells = |cells: u32| {
+            (cells as i32 + delta).clamp(MIN_GRID_CELLS as i32, MAX_GRID_CELLS as i32) as u32
+        };
+        match self {
+            SettingsField::Width => grid.width = cells(grid.width),
+            SettingsField::Height => grid.height = cells(grid.height),
+            SettingsField::Players => {
+                let players =
+                    (max_players.0 as i32 + delta).clamp(MIN_PLAYERS as i32, MAX_PLAYERS as i32);
+                max_players.0 = players as u8;
+            }
+            SettingsField::Difficulty => {
+                let count = Difficulty::ALL.len() as i32;
+                let current = Difficulty::ALL
+                    .iter()
+                    .position(|d| *d == *difficulty)
+                    .unwrap_or_default() as i32;
+                *difficulty = Difficulty::ALL[(current + delta).rem_euclid(count) as usize];
+            }
+            SettingsField::Assist => **assist = !**assist,
+        }
+    }
+}
+
+// The "-" and "+" buttons either side of a field's value.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SettingsStepper {
+    pub field: SettingsField,
+    pub delta: i32,
+}
+
+// The text showing a field's current value.
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SettingsValue(pub SettingsField);
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SettingsButton {
+    Start,
+    Back,
+}
+
+impl SettingsButton {
+    fn label(self) -> &'static str {
+        match self {
+            SettingsButton::Start => "Start",
+            SettingsButton::Back => "Back",
+        }
+    }
+}
+
+// The final score, shown over the frozen board until the next game starts.
+#[derive(Component)]
+pub struct GameOverScreen;
+
+// The dimmed overlay shown over the board while the game is paused.
+#[derive(Component)]
+pub struct PauseMenu;
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PauseButton {
+    Resume,
+    Restart,
+    Quit,
+}
+
+impl PauseButton {
+    fn label(self) -> &'static str {
+        match self {
+            PauseButton::Resume => "Resume",
+            PauseButton::Restart => "Restart",
+            PauseButton::Quit => "Quit",
+        }
+    }
+}
+
+pub fn setup_scoreboard(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "Score: 0  Best: 0",
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: 20.,
+                    color: Color::WHITE,
+                },
+                default(),
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(Scoreboard);
+}
+
+pub fn update_scoreboard(
+    score: Res<Score>,
+    high_score: Res<HighScore>,
+    asset_server: Res<AssetServer>,
+    mut font_failed: Local<bool>,
+    mut scoreboard_query: Query<&mut Text, With<Scoreboard>>,
+) {
+    for mut text in scoreboard_query.iter_mut() {
+        let section = &mut text.sections[0];
+        if !*font_failed && asset_server.get_load_state(&section.style.font) == LoadState::Failed {
+            warn!("Could not load {}, logging the score instead", FONT_PATH);
+            *font_failed = true;
+        }
+        section.value = format!("Score: {}  Best: {}", **score, **high_score);
+    }
+    if *font_failed && score.is_changed() {
+        info!("Score: {}", **score);
+    }
+}
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    button: impl Component,
+    label: &str,
+    width: f32,
+    color: UiColor,
+    font: &Handle<Font>,
+) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                size: Size::new(Val::Px(width), Val::Px(50.)),
+                margin: Rect::all(Val::Px(10.)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color,
+            ..default()
+        })
+        .insert(button)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    label,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 30.,
+                        color: Color::WHITE,
+                    },
+                    default(),
+                ),
+                ..default()
+            });
+        });
+}
+
+fn spawn_menu_button(
+    parent: &mut ChildBuilder,
+    button: MenuButton,
+    width: f32,
+    font: &Handle<Font>,
+    difficulty: Difficulty,
+) {
+    let color = button.color(difficulty);
+    spawn_button(parent, button, button.label(), width, color, font);
+}
+
+pub fn setup_menu(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
+) {
+    let font = asset_server.load(FONT_PATH);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(MainMenu)
+        .with_children(|parent| {
+            spawn_menu_button(parent, MenuButton::Play, 150., &font, *difficulty);
+            spawn_menu_button(parent, MenuButton::SinglePlayer, 250., &font, *difficulty);
+            parent
+                .spawn_bundle(NodeBundle {
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for option in Difficulty::ALL {
+                        let button = MenuButton::Difficulty(option);
+                        spawn_menu_button(parent, button, 120., &font, *difficulty);
+                    }
+                });
+            spawn_menu_button(parent, MenuButton::Quit, 150., &font, *difficulty);
+        });
+}
+
+pub fn menu_interaction(
+    mut commands: Commands,
+    button_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut color_query: Query<(&MenuButton, &mut UiColor)>,
+    mut app_state: ResMut<State<AppState>>,
+    mut difficulty: ResMut<Difficulty>,
+    mut max_players: ResMut<MaxPlayers>,
+    mut offline: ResMut<PlayingOffline>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            MenuButton::Play => set_state(&mut app_state, AppState::Settings),
+            MenuButton::SinglePlayer => start_offline(
+                &mut commands,
+                &mut max_players,
+                &mut offline,
+                &mut app_state,
+            ),
+            MenuButton::Difficulty(picked) => *difficulty = *picked,
+            MenuButton::Quit => exit.send(AppExit),
+        }
+    }
+    if difficulty.is_changed() {
+        for (button, mut color) in color_query.iter_mut() {
+            *color = button.color(*difficulty);
+        }
+    }
+}
+
+pub fn cleanup_menu(mut commands: Commands, menu_query: Query<Entity, With<MainMenu>>) {
+    for entity in menu_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn settings_text(value: String, width: f32, font: &Handle<Font>) -> TextBundle {
+    TextBundle {
+        style: Style {
+            size: Size::new(Val::Px(width), Val::Auto),
+            margin: Rect::all(Val::Px(10.)),
+            ..default()
+        },
+        text: Text::with_section(
+            value,
+            TextStyle {
+                font: font.clone(),
+                font_size: 30.,
+                color: Color::WHITE,
+            },
+            TextAlignment {
+                horizontal: HorizontalAlign::Center,
+                ..default()
+            },
+        ),
+        ..default()
+    }
+}
+
+// A game played offline leaves `MaxPlayers` at 1, so the form starts from a count a lobby can fill.
+pub fn setup_settings(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    grid: Res<GridConfig>,
+    mut max_players: ResMut<MaxPlayers>,
+    difficulty: Res<Difficulty>,
+    assist: Res<AssistMode>,
+) {
+    max_players.0 = max_players.0.clamp(MIN_PLAYERS, MAX_PLAYERS);
+    let font = asset_server.load(FONT_PATH);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(SettingsForm)
+        .with_children(|parent| {
+            for field in SettingsField::ALL {
+                let value = field.value(&grid, *max_players, *difficulty, *assist);
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        color: Color::NONE.into(),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        let color = UiColor::from(BUTTON_COLOR);
+                        let stepper = |delta| SettingsStepper { field, delta };
+                        parent.spawn_bundle(settings_text(field.label().to_string(), 160., &font));
+                        spawn_button(parent, stepper(-1), "-", 50., color, &font);
+                        parent
+                            .spawn_bundle(settings_text(value, 160., &font))
+                            .insert(SettingsValue(field));
+                        spawn_button(parent, stepper(1), "+", 50., color, &font);
+                    });
+            }
+            parent
+                .spawn_bundle(NodeBundle {
+                    color: Color::NONE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for button in [SettingsButton::Back, SettingsButton::Start] {
+                        let color = BUTTON_COLOR.into();
+                        spawn_button(parent, button, button.label(), 150., color, &font);
+                    }
+                });
+        });
+}
+
+// A default room is matched by player count, so one that wasn't picked on the command line follows
+// the form's count. A private room or a `--room-url` is left alone.
+pub fn settings_interaction(
+    stepper_query: Query<(&Interaction, &SettingsStepper), Changed<Interaction>>,
+    button_query: Query<(&Interaction, &SettingsButton), Changed<Interaction>>,
+    mut value_query: Query<(&SettingsValue, &mut Text)>,
+    mut grid: ResMut<GridConfig>,
+    mut max_players: ResMut<MaxPlayers>,
+    mut difficulty: ResMut<Difficulty>,
+    mut assist: ResMut<AssistMode>,
+    mut config: ResMut<MatchboxConfig>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    for (interaction, stepper) in stepper_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        let players = *max_players;
+        stepper.field.step(
+            stepper.delta,
+            &mut grid,
+            &mut max_players,
+            &mut difficulty,
+            &mut assist,
+        );
+        if config.room_code.is_none() && config.room_url == default_room_url(players) {
+            config.room_url = default_room_url(*max_players);
+        }
+    }
+    let changed = grid.is_changed() || max_players.is_changed() || difficulty.is_changed();
+    if changed || assist.is_changed() {
+        for (field, mut text) in value_query.iter_mut() {
+            let value = field.0.value(&grid, *max_players, *difficulty, *assist);
+            if text.sections[0].value != value {
+                text.sections[0].value = value;
+            }
+        }
+    }
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            SettingsButton::Start => set_state(&mut app_state, AppState::Lobby),
+            SettingsButton::Back => set_state(&mut app_state, AppState::MainMenu),
+        }
+    }
+}
+
+pub fn cleanup_settings(mut commands: Commands, form_query: Query<Entity, With<SettingsForm>>) {
+    for entity in form_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn lobby_status(connected: usize, players: MaxPlayers, config: &MatchboxConfig) -> String {
+    let status = format!("Waiting for players ({}/{})...", connected, players.count());
+    match &config.room_code {
+        Some(code) => format!("Room code: {}\n{}", code.as_str(), status),
+        None => status,
+    }
+}
+
+pub fn setup_lobby_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    config: Res<MatchboxConfig>,
+    max_players: Res<MaxPlayers>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .insert(LobbyText)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    lobby_status(1, *max_players, &config),
+                    TextStyle {
+                        font: asset_server.load(FONT_PATH),
+                        font_size: 24.,
+                        color: Color::WHITE,
+                    },
+                    default(),
+                ),
+                ..default()
+            });
+        });
+}
+
+// Counts this peer plus every peer the socket is connected to.
+pub fn update_lobby_text(
+    socket: Res<Option<WebRtcSocket>>,
+    config: Res<MatchboxConfig>,
+    max_players: Res<MaxPlayers>,
+    lobby_error: Res<LobbyError>,
+    lobby_query: Query<&Children, With<LobbyText>>,
+    mut text_query: Query<&mut Text>,
+) {
+    let status = match (&**lobby_error, socket.as_ref()) {
+        (Some(err), _) => err.clone(),
+        (None, Some(socket)) => {
+            lobby_status(socket.connected_peers().len() + 1, *max_players, &config)
+        }
+        (None, None) => SIGNALING_ERROR.to_string(),
+    };
+    for children in lobby_query.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(*child) {
+                if text.sections[0].value != status {
+                    text.sections[0].value = status.clone();
+                }
+            }
+        }
+    }
+}
+
+pub fn show_play_offline_button(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    lobby_error: Res<LobbyError>,
+    lobby_query: Query<Entity, With<LobbyText>>,
+    button_query: Query<(), With<PlayOfflineButton>>,
+) {
+    if lobby_error.is_none() || !button_query.is_empty() {
+        return;
+    }
+    let font = asset_server.load(FONT_PATH);
+    for lobby in lobby_query.iter() {
+        commands.entity(lobby).with_children(|parent| {
+            spawn_button(
+                parent,
+                PlayOfflineButton,
+                "Play offline",
+                200.,
+                BUTTON_COLOR.into(),
+                &font,
+            );
+        });
+    }
+}
+
+pub fn play_offline_interaction(
+    mut commands: Commands,
+    button_query: Query<&Interaction, (Changed<Interaction>, With<PlayOfflineButton>)>,
+    mut socket: ResMut<Option<WebRtcSocket>>,
+    mut max_players: ResMut<MaxPlayers>,
+    mut offline: ResMut<PlayingOffline>,
+    mut app_state: ResMut<State<AppState>>,
+) {
+    let clicked = button_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Clicked);
+    if clicked {
+        *socket = None;
+        start_offline(
+            &mut commands,
+            &mut max_players,
+            &mut offline,
+            &mut app_state,
+        );
+    }
+}
+
+pub fn cleanup_lobby_text(mut commands: Commands, lobby_query: Query<Entity, With<LobbyText>>) {
+    for entity in lobby_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn setup_countdown_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load(FONT_PATH),
+                            font_size: 80.,
+                            color: Color::WHITE,
+                        },
+                        default(),
+                    ),
+                    ..default()
+                })
+                .insert(CountdownText);
+        });
+}
+
+// Shows the whole seconds left, 3-2-1, and nothing once the snakes are moving or outside a game.
+pub fn update_countdown_text(
+    countdown: Res<StartCountdown>,
+    app_state: Res<State<AppState>>,
+    mut text_query: Query<&mut Text, With<CountdownText>>,
+) {
+    let value = if app_state.current() == &AppState::InGame && !countdown.finished() {
+        let remaining = countdown.duration().as_secs_f32() - countdown.elapsed_secs();
+        format!("{}", remaining.ceil() as u32)
+    } else {
+        String::new()
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// Shows the pause menu when the game is paused and takes it down again when it's resumed, however
+// that happens.
+pub fn update_pause_menu(
+    mut commands: Commands,
+    paused: Res<Paused>,
+    asset_server: Res<AssetServer>,
+    menu_query: Query<Entity, With<PauseMenu>>,
+) {
+    if !paused.is_changed() {
+        return;
+    }
+    if !**paused {
+        for entity in menu_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+    if !menu_query.is_empty() {
+        return;
+    }
+    let font = asset_server.load(FONT_PATH);
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: PAUSE_DIM_COLOR.into(),
+            ..default()
+        })
+        .insert(PauseMenu)
+        .with_children(|parent| {
+            for button in [PauseButton::Resume, PauseButton::Restart, PauseButton::Quit] {
+                spawn_button(
+                    parent,
+                    button,
+                    button.label(),
+                    150.,
+                    BUTTON_COLOR.into(),
+                    &font,
+                );
+            }
+        });
+}
+
+pub fn pause_menu_interaction(
+    button_query: Query<(&Interaction, &PauseButton), Changed<Interaction>>,
+    mut paused: ResMut<Paused>,
+    mut restart: ResMut<RestartRequested>,
+    mut app_state: ResMut<State<AppState>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+        match button {
+            PauseButton::Resume => **paused = false,
+            // Leaves the game and enters the next one straight away, without the game-over screen
+            // or the replay, score and time saved on it.
+            PauseButton::Restart => {
+                **paused = false;
+                if app_state.current() == &AppState::InGame {
+                    **restart = true;
+                    restart_state(&mut app_state);
+                }
+            }
+            PauseButton::Quit => exit.send(AppExit),
+        }
+    }
+}
+
+// Players are numbered from 1 on screen.
+pub fn game_over_text(
+    outcome: MatchOutcome,
+    score: u32,
+    high_score: u32,
+    bindings: &KeyBindings,
+) -> String {
+    let title = match outcome {
+        MatchOutcome::Winner(handle) => format!("Player {} wins!", handle + 1),
+        MatchOutcome::Draw => "Draw!".to_string(),
+        MatchOutcome::Undecided => "Game over".to_string(),
+    };
+    format!(
+        "{}\nScore: {}  Best: {}\nPress {:?} to play again / {:?} to quit",
+        title, score, high_score, bindings.restart, bindings.quit
+    )
+}
+
+pub fn setup_game_over_screen(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    score: Res<Score>,
+    high_score: Res<HighScore>,
+    bindings: Res<KeyBindings>,
+    outcome: Res<MatchOutcome>,
+) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: PAUSE_DIM_COLOR.into(),
+            ..default()
+        })
+        .insert(GameOverScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(
+                    game_over_text(*outcome, **score, **high_score, &bindings),
+                    TextStyle {
+                        font: asset_server.load(FONT_PATH),
+                        font_size: 24.,
+                        color: Color::WHITE,
+                    },
+                    TextAlignment {
+                        horizontal: HorizontalAlign::Center,
+                        ..default()
+                    },
+                ),
+                ..default()
+            });
+        });
+}
+
+pub fn cleanup_game_over_screen(
+    mut commands: Commands,
+    screen_query: Query<Entity, With<GameOverScreen>>,
+) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// One line per remote player, then how many frames this peer is running ahead of the last one
+// confirmed by everyone, i.e. how far it may have to roll back.
+pub fn format_network_stats(
+    stats: &[(PlayerHandle, NetworkStats)],
+    current_frame: i32,
+    confirmed_frame: i32,
+) -> String {
+    let mut lines = stats
+        .iter()
+        .map(|(handle, stats)| {
+            format!(
+                "P{}: {} ms  queue {}  {} kbps",
+                handle, stats.ping, stats.send_queue_len, stats.kbps_sent
+            )
+        })
+        .collect::<Vec<_>>();
+    lines.push(format!(
+        "Predicted frames: {}",
+        (current_frame - confirmed_frame).max(0)
+    ));
+    lines.join("\n")
+}
+
+pub fn setup_network_stats_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: 16.,
+                    color: Color::WHITE,
+                },
+                default(),
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    right: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(NetworkStatsText);
+}
+
+// The pixels per cell that fit the longer side of the board into `MINIMAP_SIZE_PX`.
+pub fn minimap_scale(grid: &GridConfig) -> f32 {
+    MINIMAP_SIZE_PX / grid.width.max(grid.height).max(1) as f32
+}
+
+// Where a cell's square sits on the minimap, from the bottom left corner like the board's cells.
+pub fn minimap_position(cell: GridPos, scale: f32) -> Vec2 {
+    Vec2::new(cell.x as f32, cell.y as f32) * scale
+}
+
+// Every occupied cell of the board and the colour of the sprite on it, in `Occupancy`'s cells
+// sorted so the squares are spawned in the same order every time.
+pub fn minimap_cells(
+    grid: &GridConfig,
+    occupancy: &Occupancy,
+    sprites: impl Iterator<Item = (Vec3, Color)>,
+) -> Vec<(GridPos, Color)> {
+    let colors = sprites
+        .map(|(translation, color)| (grid.cell_at(translation), color))
+        .collect::<HashMap<_, _>>();
+    let mut cells = occupancy
+        .0
+        .keys()
+        .filter(|cell| grid.in_bounds(**cell))
+        .filter_map(|cell| colors.get(cell).map(|color| (*cell, *color)))
+        .collect::<Vec<_>>();
+    cells.sort_by_key(|(cell, _)| *cell);
+    cells
+}
+
+// Updated whenever the board changes, and only shown during a game on a board big enough to need
+// it. It only ever reads the board, so it stays out of the simulation.
+pub fn update_minimap(
+    mut commands: Commands,
+    app_state: Res<State<AppState>>,
+    grid: Res<GridConfig>,
+    palette: Res<Palette>,
+    occupancy: Res<Occupancy>,
+    minimap_query: Query<Entity, With<Minimap>>,
+    mut cell_query: Query<(&Parent, &MinimapCell, &mut UiColor, &mut Visibility)>,
+    sprite_query: Query<
+        (&Transform, &Sprite, Option<&Segment>),
+        Or<(
+            With<Segment>,
+            With<Food>,
+            With<Obstacle>,
+            With<PowerUp>,
+            With<Portal>,
+        )>,
+    >,
+) {
+    let in_game = matches!(app_state.current(), AppState::InGame | AppState::GameOver);
+    let shown = in_game && grid.width.max(grid.height) >= MINIMAP_MIN_CELLS;
+    // A resized board needs a new frame, so its minimap starts over.
+    if !shown || grid.is_changed() {
+        for entity in minimap_query.iter() {
+            commands.entity(entity).despawn_recursive();
+        }
+        if !shown {
+            return;
+        }
+    }
+    let scale = minimap_scale(&grid);
+    let square = |pos: Vec2, size: Size<Val>, color: Color| NodeBundle {
+        style: Style {
+            size,
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(MINIMAP_BORDER_PX + pos.x),
+                bottom: Val::Px(MINIMAP_BORDER_PX + pos.y),
+                ..default()
+            },
+            ..default()
+        },
+        color: color.into(),
+        ..default()
+    };
+    let minimap = match minimap_query.iter().next() {
+        Some(_) if !occupancy.is_changed() && !grid.is_changed() => return,
+        Some(minimap) if !grid.is_changed() => minimap,
+        _ => {
+            let board = Size::new(
+                Val::Px(grid.width as f32 * scale),
+                Val::Px(grid.height as f32 * scale),
+            );
+            let frame = Size::new(
+                Val::Px(grid.width as f32 * scale + 2. * MINIMAP_BORDER_PX),
+                Val::Px(grid.height as f32 * scale + 2. * MINIMAP_BORDER_PX),
+            );
+            commands
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: frame,
+                        position_type: PositionType::Absolute,
+                        position: Rect {
+                            bottom: Val::Px(5.),
+                            right: Val::Px(5.),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    color: palette.wall.into(),
+                    ..default()
+                })
+                .insert(Minimap)
+                .with_children(|parent| {
+                    parent.spawn_bundle(square(Vec2::ZERO, board, palette.background));
+                })
+                .id()
+        }
+    };
+    // Snakes go last, so a head is drawn over anything sharing its cell.
+    let mut sprites = sprite_query.iter().collect::<Vec<_>>();
+    sprites.sort_by_key(|(_, _, segment)| segment.is_some());
+    let sprites = sprites
+        .into_iter()
+        .map(|(trans, sprite, _)| (trans.translation, sprite.color));
+    let mut cells = minimap_cells(&grid, &occupancy, sprites)
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    for (parent, cell, mut color, mut visibility) in cell_query.iter_mut() {
+        if parent.0 != minimap {
+            continue;
+        }
+        match cells.remove(&cell.0) {
+            Some(cell_color) => {
+                color.0 = cell_color;
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+    // Only cells taken for the first time are left, in the same order every time.
+    let mut new_cells = cells.into_iter().collect::<Vec<_>>();
+    new_cells.sort_by_key(|(cell, _)| *cell);
+    commands.entity(minimap).with_children(|parent| {
+        for (cell, color) in new_cells {
+            let size = Size::new(Val::Px(scale), Val::Px(scale));
+            parent
+                .spawn_bundle(square(minimap_position(cell, scale), size, color))
+                .insert(MinimapCell(cell));
+        }
+    });
+}
+
+// The day and seed of the daily challenge, in the bottom left corner, so players can check they're
+// on the same one.
+pub fn daily_challenge_text(date: UtcDate) -> String {
+    format!("Daily {}  Seed: {:016x}", date, date.seed())
+}
+
+pub fn setup_daily_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    daily: Res<DailyChallenge>,
+) {
+    let date = match **daily {
+        Some(date) => date,
+        None => return,
+    };
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                daily_challenge_text(date),
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: 16.,
+                    color: Color::WHITE,
+                },
+                default(),
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(5.),
+                    left: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(DailyText);
+}
+
+// Only a P2P session has stats to show; otherwise, or while hidden, the text stays empty.
+pub fn update_network_stats(
+    keys: Res<Input<KeyCode>>,
+    mut show: ResMut<ShowNetworkStats>,
+    session: Option<Res<P2PSession<GGRSConfig>>>,
+    mut text_query: Query<&mut Text, With<NetworkStatsText>>,
+) {
+    if keys.just_pressed(NETWORK_STATS_KEY) {
+        **show = !**show;
+    }
+    let value = match session {
+        Some(session) if **show => {
+            let stats = (0..session.num_players())
+                .filter_map(|handle| Some((handle, session.network_stats(handle).ok()?)))
+                .collect::<Vec<_>>();
+            format_network_stats(&stats, session.current_frame(), session.confirmed_frame())
+        }
+        _ => String::new(),
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// FPS and frame time come from `FrameTimeDiagnosticsPlugin`, and read `-` until it has measured
+// a few frames.
+pub fn format_perf_overlay(fps: Option<f64>, frame_time_ms: Option<f64>, frame: i32) -> String {
+    let or_dash = |value: Option<f64>, decimals: usize| {
+        value.map_or_else(
+            || "-".to_string(),
+            |value| format!("{:.*}", decimals, value),
+        )
+    };
+    format!(
+        "FPS: {}\nFrame time: {} ms\nFrame: {}",
+        or_dash(fps, 0),
+        or_dash(frame_time_ms, 1),
+        frame
+    )
+}
+
+pub fn setup_perf_overlay_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT_PATH),
+                    font_size: 16.,
+                    color: Color::WHITE,
+                },
+                default(),
+            ),
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(25.),
+                    left: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(PerfOverlayText);
+}
+
+// The frame is the P2P session's own while there is one, otherwise the simulation's `FrameCount`.
+// Like every UI system this runs once per rendered frame, never inside a rollback.
+pub fn update_perf_overlay(
+    keys: Res<Input<KeyCode>>,
+    mut show: ResMut<ShowPerfOverlay>,
+    diagnostics: Res<Diagnostics>,
+    session: Option<Res<P2PSession<GGRSConfig>>>,
+    frame_count: Res<FrameCount>,
+    mut text_query: Query<&mut Text, With<PerfOverlayText>>,
+) {
+    if keys.just_pressed(PERF_OVERLAY_KEY) {
+        **show = !**show;
+    }
+    let value = if **show {
+        let average = |id| {
+            diagnostics
+                .get(id)
+                .and_then(|diagnostic| diagnostic.average())
+        };
+        let frame = session.map_or(**frame_count as i32, |session| session.current_frame());
+        format_perf_overlay(
+            average(FrameTimeDiagnosticsPlugin::FPS),
+            average(FrameTimeDiagnosticsPlugin::FRAME_TIME).map(|secs| secs * 1000.),
+            frame,
+        )
+    } else {
+        String::new()
+    };
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+// Minutes, seconds and hundredths of a second.
+pub fn format_trial_time(hundredths: u32) -> String {
+    format!(
+        "{}:{:02}.{:02}",
+        hundredths / 6000,
+        hundredths / 100 % 60,
+        hundredths % 100
+    )
+}
+
+pub fn trial_timer_text(
+    timer: &TrialTimer,
+    sim: &SimConfig,
+    best: Option<u32>,
+    len: usize,
+    target_len: u32,
+) -> String {
+    let best = best.map_or_else(|| "-".to_string(), format_trial_time);
+    let len = if timer.finished {
+        "Done!".to_string()
+    } else {
+        format!("Length {}/{}", len, target_len)
+    };
+    format!(
+        "Time {}  Best {}  {}",
+        format_trial_time(sim.hundredths(timer.frames)),
+        best,
+        len
+    )
+}
+
+pub fn setup_trial_timer_text(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    trial: Option<Res<TimeTrial>>,
+) {
+    if trial.is_none() {
+        return;
+    }
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Auto),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.),
+                    ..default()
+                },
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load(FONT_PATH),
+                            font_size: 20.,
+                            color: Color::WHITE,
+                        },
+                        default(),
+                    ),
+                    ..default()
+                })
+                .insert(TrialTimerText);
+        });
+}
+
+// Shows the longest snake's length, so with several players it's the one closest to the target.
+pub fn update_trial_timer_text(
+    trial: Option<Res<TimeTrial>>,
+    timer: Res<TrialTimer>,
+    sim: Res<SimConfig>,
+    best_times: Res<BestTimes>,
+    snake_query: Query<&Snake>,
+    mut text_query: Query<&mut Text, With<TrialTimerText>>,
+) {
+    let trial = match trial {
+        Some(trial) => trial,
+        None => return,
+    };
+    let len = snake_query
+        .iter()
+        .map(|snake| snake.len())
+        .max()
+        .unwrap_or(0);
+    let best = best_times.best(trial.target_len);
+    let value = trial_timer_text(&timer, &sim, best, len, trial.target_len);
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+pub fn peer_wait_status(remaining: f32) -> String {
+    format!("Paused - waiting for peer ({}s)", remaining.ceil() as u32)
+}
+
+pub fn setup_peer_wait_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            ..default()
+        })
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(TextBundle {
+                    text: Text::with_section(
+                        "",
+                        TextStyle {
+                            font: asset_server.load(FONT_PATH),
+                            font_size: 24.,
+                            color: Color::WHITE,
+                        },
+                        default(),
+                    ),
+                    ..default()
+                })
+                .insert(PeerWaitText);
+        });
+}
+
+pub fn update_peer_wait_text(
+    waiting: Res<WaitingForPeer>,
+    mut text_query: Query<&mut Text, With<PeerWaitText>>,
+) {
+    let value = waiting.map(peer_wait_status).unwrap_or_default();
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != value {
+            text.sections[0].value = value.clone();
+        }
+    }
+}
+
+pub fn setup_multiplayer_scoreboard(mut commands: Commands) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(30.),
+                    left: Val::Px(5.),
+                    ..default()
+                },
+                ..default()
+            },
+            ..default()
+        })
+        .insert(PlayerScoreboard);
+}
+
+// A crashed snake is despawned along with its score, so the last score seen for each handle is
+// kept until that handle is spawned again in the next game.
+pub fn multiplayer_scoreboard(
+    player_query: Query<(&Player, &PlayerScore)>,
+    palette: Res<Palette>,
+    asset_server: Res<AssetServer>,
+    mut scores: Local<Vec<u32>>,
+    mut text_query: Query<&mut Text, With<PlayerScoreboard>>,
+) {
+    for (player, score) in player_query.iter() {
+        if scores.len() <= player.handle {
+            scores.resize(player.handle + 1, 0);
+        }
+        scores[player.handle] = **score;
+    }
+    let font = asset_server.load(FONT_PATH);
+    let sections = scores
+        .iter()
+        .enumerate()
+        .map(|(handle, score)| TextSection {
+            value: format!("P{}: {}   ", handle + 1, score),
+            style: TextStyle {
+                font: font.clone(),
+                font_size: 18.,
+                color: palette.head(handle),
+            },
+        })
+        .collect::<Vec<_>>();
+    for mut text in text_query.iter_mut() {
+        let changed = text.sections.len() != sections.len()
+            || text
+                .sections
+                .iter()
+                .zip(sections.iter())
+                .any(|(old, new)| old.value != new.value);
+        if changed {
+            text.sections = sections.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_lobby_counts_up_as_peers_connect() {
+        let config = MatchboxConfig::default();
+        let players = MaxPlayers(3);
+        assert_eq!(
+            lobby_status(1, players, &config),
+            "Waiting for players (1/3)..."
+        );
+        assert_eq!(
+            lobby_status(2, players, &config),
+            "Waiting for players (2/3)..."
+        );
+    }
+
+    #[test]
+    fn the_network_stats_list_each_peer_then_the_predicted_frames() {
+        let stats = NetworkStats {
+            send_queue_len: 3,
+            ping: 42,
+            kbps_sent: 12,
+            local_frames_behind: 0,
+            remote_frames_behind: 1,
+        };
+        assert_eq!(
+            format_network_stats(&[(1, stats)], 120, 117),
+            "P1: 42 ms  queue 3  12 kbps\nPredicted frames: 3"
+        );
+        assert_eq!(format_network_stats(&[], 5, 7), "Predicted frames: 0");
+    }
+
+    #[test]
+    fn the_minimap_shows_a_snake_on_its_scaled_cells() {
+        let grid = GridConfig {
+            width: 40,
+            height: 60,
+            ..GridConfig::default()
+        };
+        let scale = minimap_scale(&grid);
+        assert_eq!(scale, 2.);
+        let snake = [GridPos::new(3, 5), GridPos::new(3, 6), GridPos::new(4, 6)];
+        let mut occupancy = Occupancy::default();
+        for cell in snake {
+            occupancy.insert(cell);
+        }
+        let sprites = snake
+            .iter()
+            .map(|cell| (cell.to_translation(&grid), Color::GREEN));
+        let cells = minimap_cells(&grid, &occupancy, sprites);
+        assert_eq!(
+            cells,
+            vec![
+                (GridPos::new(3, 5), Color::GREEN),
+                (GridPos::new(3, 6), Color::GREEN),
+                (GridPos::new(4, 6), Color::GREEN),
+            ]
+        );
+        let positions = cells
+            .iter()
+            .map(|(cell, _)| minimap_position(*cell, scale))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            vec![Vec2::new(6., 10.), Vec2::new(6., 12.), Vec2::new(8., 12.)]
+        );
+    }
+
+    #[test]
+    fn the_perf_overlay_labels_fps_frame_time_and_frame() {
+        assert_eq!(
+            format_perf_overlay(Some(59.7), Some(16.74), 1234),
+            "FPS: 60\nFrame time: 16.7 ms\nFrame: 1234"
+        );
+        assert_eq!(
+            format_perf_overlay(None, None, 0),
+            "FPS: -\nFrame time: - ms\nFrame: 0"
+        );
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::agent::{agent_input, send_agent_state, start_agent_listener, AgentPort};
+use game2d::build_headless_app;
+use game2d::components::Direction;
+use game2d::systems::update_dir;
+use game2d::FixedUpdateStage;
+
+// Connects as soon as the game is listening, answers the first state with `reply` and hangs up.
+fn mock_agent(port: u16, reply: &'static str) {
+    thread::spawn(move || {
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(5)),
+            }
+        };
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut state = String::new();
+        reader.read_line(&mut state).unwrap();
+        writeln!(stream, "{}", reply).unwrap();
+    });
+}
+
+#[test]
+fn the_agent_steers_its_snake() {
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut app = build_headless_app();
+    app.insert_resource(AgentPort(Some(port)))
+        .add_startup_system(start_agent_listener)
+        .add_system_to_stage(FixedUpdateStage, agent_input.before(update_dir))
+        .add_system_to_stage(CoreStage::Last, send_agent_state);
+    start(&mut app);
+    mock_agent(port, "Right");
+
+    // The grace period holds the snake at the top wall if the answer takes a while.
+    let turned = (0..200).any(|_| {
+        step(&mut app);
+        thread::sleep(Duration::from_millis(5));
+        head(&mut app, 0).next_dir == Direction::Right
+    });
+    assert!(turned);
+}
@@ -0,0 +1,164 @@
+use bevy::ecs::event::Events;
+use bevy::prelude::*;
+use bevy::window::{WindowId, WindowResized};
+
+use game2d::build_headless_app;
+use game2d::components::{Direction, GridLine, MainCamera, Segment};
+use game2d::config::{GridConfig, GridPos, Palette};
+use game2d::resources::Occupancy;
+use game2d::systems::{camera_scale, draw_grid, fit_camera_on_resize, setup_camera};
+use game2d::ui::{update_minimap, MinimapCell};
+use game2d::AppState;
+
+fn grid_lines(app: &mut App) -> usize {
+    app.world
+        .query_filtered::<(), With<GridLine>>()
+        .iter(&app.world)
+        .count()
+}
+
+#[test]
+fn a_line_is_drawn_on_every_cell_boundary() {
+    let mut app = build_headless_app();
+    app.add_system(draw_grid);
+    app.update();
+    assert_eq!(grid_lines(&mut app), 11 + 21);
+
+    app.insert_resource(GridConfig {
+        width: 6,
+        height: 8,
+        ..GridConfig::default()
+    });
+    app.update();
+    assert_eq!(grid_lines(&mut app), 7 + 9);
+}
+
+#[test]
+fn the_camera_frames_the_board_around_its_centre() {
+    let mut app = build_headless_app();
+    app.insert_resource(Windows::default())
+        .add_startup_system(setup_camera);
+    app.update();
+    let translation = app
+        .world
+        .query_filtered::<&Transform, With<MainCamera>>()
+        .single(&app.world)
+        .translation;
+    assert_eq!(translation.truncate(), Vec2::ZERO);
+
+    // The 10x20 board with its walls and a half-cell margin on each side, at 26 pixels a cell.
+    let grid = GridConfig::default();
+    assert_eq!(camera_scale(&grid, 13. * 26., 23. * 26.), 1.);
+    assert_eq!(camera_scale(&grid, 26. * 26., 46. * 26.), 0.5);
+
+    // The four middle cells sit evenly round the origin.
+    assert_eq!(grid.cell_center(5, 10), Vec2::new(13., 13.));
+    assert_eq!(grid.cell_center(4, 9), -grid.cell_center(5, 10));
+}
+
+fn camera_scale_after_resize(app: &mut App, width: f32, height: f32) -> f32 {
+    app.world
+        .get_resource_mut::<Events<WindowResized>>()
+        .unwrap()
+        .send(WindowResized {
+            id: WindowId::primary(),
+            width,
+            height,
+        });
+    app.update();
+    app.world
+        .query_filtered::<&OrthographicProjection, With<MainCamera>>()
+        .single(&app.world)
+        .scale
+}
+
+#[test]
+fn a_resized_window_letterboxes_the_board() {
+    let mut app = build_headless_app();
+    app.insert_resource(Windows::default())
+        .add_event::<WindowResized>()
+        .add_startup_system(setup_camera)
+        .add_system(fit_camera_on_resize);
+    app.update();
+
+    // Twice as wide only adds bars at the sides, and twice as tall bars at the top and bottom.
+    assert_eq!(
+        camera_scale_after_resize(&mut app, 26. * 26., 23. * 26.),
+        1.
+    );
+    assert_eq!(
+        camera_scale_after_resize(&mut app, 13. * 26., 46. * 26.),
+        1.
+    );
+    assert_eq!(
+        camera_scale_after_resize(&mut app, 26. * 26., 46. * 26.),
+        0.5
+    );
+}
+
+fn minimap_squares(app: &mut App) -> Vec<(GridPos, bool)> {
+    let mut squares = app
+        .world
+        .query::<(&MinimapCell, &Visibility)>()
+        .iter(&app.world)
+        .map(|(cell, visibility)| (cell.0, visibility.is_visible))
+        .collect::<Vec<_>>();
+    squares.sort();
+    squares
+}
+
+// Moves the lone segment, and its cell in `Occupancy`, from `from` to `to`.
+fn move_segment(app: &mut App, from: GridPos, to: GridPos) {
+    let grid = *app.world.get_resource::<GridConfig>().unwrap();
+    let mut segments = app.world.query::<(&mut Segment, &mut Transform)>();
+    for (mut seg, mut trans) in segments.iter_mut(&mut app.world) {
+        seg.pos = to;
+        trans.translation = to.to_translation(&grid);
+    }
+    let mut occupancy = app.world.get_resource_mut::<Occupancy>().unwrap();
+    occupancy.remove(from);
+    occupancy.insert(to);
+}
+
+// A cell left empty keeps its square, hidden, for when something comes back to it.
+#[test]
+fn the_minimap_reuses_one_square_per_cell() {
+    let grid = GridConfig {
+        width: 40,
+        height: 40,
+        ..GridConfig::default()
+    };
+    let (a, b) = (GridPos::new(3, 5), GridPos::new(4, 5));
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(grid)
+        .insert_resource(Palette::default())
+        .init_resource::<Occupancy>()
+        .add_state(AppState::InGame)
+        .add_system(update_minimap);
+    let pos = a.to_translation(&grid);
+    app.world
+        .spawn()
+        .insert_bundle(Segment::new_sprite_bundle(
+            pos.x,
+            pos.y,
+            grid.box_size,
+            Color::GREEN,
+        ))
+        .insert(Segment {
+            pos: a,
+            curr_dir: Direction::Right,
+            next_dir: Direction::Right,
+        });
+    app.world.get_resource_mut::<Occupancy>().unwrap().insert(a);
+    app.update();
+    assert_eq!(minimap_squares(&mut app), vec![(a, true)]);
+
+    move_segment(&mut app, a, b);
+    app.update();
+    assert_eq!(minimap_squares(&mut app), vec![(a, false), (b, true)]);
+
+    move_segment(&mut app, b, a);
+    app.update();
+    assert_eq!(minimap_squares(&mut app), vec![(a, true), (b, false)]);
+}
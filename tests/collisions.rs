@@ -0,0 +1,219 @@
+mod common;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::Direction;
+use game2d::config::{
+    AssistMode, BoundaryMode, GridConfig, GridPos, MaxPlayers, ObstacleLayout, ShrinkingArena,
+    StartLength,
+};
+use game2d::resources::{
+    ClosedRings, FrameCount, GracePeriod, Occupancy, Paused, COUNTDOWN_FRAMES,
+};
+use game2d::systems::CollisionEvent;
+
+const DEATH: CollisionEvent = CollisionEvent::Deadly { handle: 0 };
+
+#[test]
+fn running_into_any_wall_kills() {
+    let walls = [
+        (GridPos::new(9, 10), Direction::Right),
+        (GridPos::new(0, 10), Direction::Left),
+        (GridPos::new(5, 19), Direction::Up),
+        (GridPos::new(5, 0), Direction::Down),
+    ];
+    for (cell, dir) in walls {
+        let mut app = started_app();
+        no_grace(&mut app);
+        place_snake(&mut app, 0, cell, dir);
+        let collisions = events_during(&mut app, step);
+        assert!(collisions.contains(&DEATH), "{:?} {:?}", cell, dir);
+    }
+}
+
+// Runs the head off the right edge in `mode`, returning whether it died and where it ended up.
+fn off_the_right_edge(mode: BoundaryMode) -> (bool, GridPos) {
+    let mut app = build_headless_app();
+    app.insert_resource(mode);
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let collisions = events_during(&mut app, step);
+    (collisions.contains(&DEATH), head(&mut app, 0).pos)
+}
+
+#[test]
+fn the_right_edge_kills_in_wall_mode() {
+    assert_eq!(
+        off_the_right_edge(BoundaryMode::Wall),
+        (true, GridPos::new(10, 10))
+    );
+}
+
+#[test]
+fn the_right_edge_leads_back_in_from_the_left_in_wrap_mode() {
+    assert_eq!(
+        off_the_right_edge(BoundaryMode::Wrap),
+        (false, GridPos::new(0, 10))
+    );
+}
+
+// Each edge cell is still on the board, and only the step past it kills.
+#[test]
+fn only_the_cell_past_an_edge_kills() {
+    let edges = [
+        (GridPos::new(8, 10), Direction::Right),
+        (GridPos::new(1, 10), Direction::Left),
+        (GridPos::new(5, 18), Direction::Up),
+        (GridPos::new(5, 1), Direction::Down),
+    ];
+    for (cell, dir) in edges {
+        let mut app = started_app();
+        no_grace(&mut app);
+        place_snake(&mut app, 0, cell, dir);
+        let collisions = events_during(&mut app, step);
+        assert!(!collisions.contains(&DEATH), "{:?} {:?}", cell, dir);
+        let collisions = events_during(&mut app, step);
+        assert!(collisions.contains(&DEATH), "{:?} {:?}", cell, dir);
+    }
+}
+
+#[test]
+fn an_obstacle_ahead_kills() {
+    let mut app = build_headless_app();
+    app.insert_resource(ObstacleLayout(vec![GridPos::new(3, 6)]));
+    start(&mut app);
+    let collisions = events_during(&mut app, step);
+    assert!(collisions.contains(&DEATH));
+}
+
+// Snake 1 starts at (6, 14) heading down, so its body trails up through (6, 15).
+#[test]
+fn a_head_running_into_another_body_only_kills_its_own_snake() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(3));
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(5, 15), Direction::Right);
+    let collisions = events_during(&mut app, step);
+    assert_eq!(collisions, vec![DEATH]);
+}
+
+#[test]
+fn the_wall_only_kills_once_the_grace_period_is_over() {
+    let mut app = started_app();
+    let frame = **resource::<FrameCount>(&app);
+    app.insert_resource(GracePeriod(frame + 10));
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    assert!(!collisions.contains(&DEATH));
+
+    let frame = **resource::<FrameCount>(&app);
+    app.insert_resource(GracePeriod(frame));
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let collisions = events_during(&mut app, step);
+    assert!(collisions.contains(&DEATH));
+}
+
+#[test]
+fn a_pause_doesnt_use_up_the_grace_period() {
+    let mut app = started_app();
+    let frame = **resource::<FrameCount>(&app);
+    app.insert_resource(GracePeriod(frame + 2))
+        .insert_resource(Paused(true));
+    steps(&mut app, 10);
+    assert!(resource::<GracePeriod>(&app).active(resource::<FrameCount>(&app)));
+
+    app.insert_resource(Paused(false));
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    assert!(!collisions.contains(&DEATH));
+}
+
+#[test]
+fn a_snake_on_the_outer_ring_dies_when_it_closes() {
+    let mut app = build_headless_app();
+    app.insert_resource(ShrinkingArena(Some(10)));
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(0, 5), Direction::Up);
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    assert!(!collisions.contains(&DEATH));
+
+    app.insert_resource(FrameCount(COUNTDOWN_FRAMES + 10));
+    let collisions = events_during(&mut app, step);
+    assert!(collisions.contains(&DEATH));
+    assert_eq!(**resource::<ClosedRings>(&app), 1);
+    assert!(resource::<Occupancy>(&app).contains(GridPos::new(0, 0)));
+}
+
+#[test]
+fn the_arena_waits_out_a_pause_before_closing() {
+    let mut app = build_headless_app();
+    app.insert_resource(ShrinkingArena(Some(10)));
+    start(&mut app);
+    app.insert_resource(FrameCount(COUNTDOWN_FRAMES + 10))
+        .insert_resource(Paused(true));
+    step(&mut app);
+    assert_eq!(**resource::<ClosedRings>(&app), 0);
+    assert!(!resource::<Occupancy>(&app).contains(GridPos::new(0, 0)));
+
+    app.insert_resource(Paused(false));
+    step(&mut app);
+    assert_eq!(**resource::<ClosedRings>(&app), 1);
+}
+
+// 500 segments snaking back and forth along 20 rows of a 25 wide board, the head in the bottom left
+// corner turning up into the second row.
+#[test]
+fn a_500_long_snake_still_notices_running_into_itself() {
+    let mut app = build_headless_app();
+    app.insert_resource(GridConfig {
+        width: 25,
+        height: 25,
+        ..GridConfig::default()
+    })
+    .insert_resource(ObstacleLayout(Vec::new()))
+    .insert_resource(MaxPlayers(1))
+    .insert_resource(StartLength(500));
+    start(&mut app);
+    let cells = (0..20)
+        .flat_map(|y| {
+            let row = (0..25).map(move |x| GridPos::new(x, y));
+            if y % 2 == 0 {
+                row.collect::<Vec<_>>()
+            } else {
+                row.rev().collect()
+            }
+        })
+        .collect::<Vec<_>>();
+    lay_snake(&mut app, 0, &cells, Direction::Up);
+    assert_eq!(snake_cells(&mut app, 0), cells);
+
+    let collisions = events_during(&mut app, step);
+    assert!(collisions.contains(&DEATH));
+}
+
+#[test]
+fn with_the_assist_a_snake_waits_at_the_wall_instead_of_dying() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(AssistMode(true));
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    for _ in 0..5 {
+        let collisions = events_during(&mut app, step);
+        assert!(!collisions.contains(&DEATH));
+        assert_eq!(head(&mut app, 0).pos, GridPos::new(9, 10));
+    }
+    press(&mut app, 0, &[Direction::Up]);
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).pos, GridPos::new(9, 11));
+
+    // It's off with anyone else to play against.
+    let mut app = build_headless_app();
+    app.insert_resource(AssistMode(true));
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    assert!(events_during(&mut app, step).contains(&DEATH));
+}
@@ -0,0 +1,198 @@
+// Helpers shared by the simulation tests, which step `build_headless_app` one frame per
+// `app.update()`. Not every test file uses all of them.
+#![allow(dead_code)]
+
+use bevy::ecs::event::Events;
+use bevy::input::{keyboard::KeyboardInput, ElementState};
+use bevy::prelude::*;
+use std::time::Duration;
+
+use game2d::build_headless_app;
+use game2d::components::{Direction, Food, FoodValue, Player, PowerUp, Segment, Snake};
+use game2d::config::{GridConfig, GridPos};
+use game2d::net::{encode_turns, BoxInput, GGRSConfig, PlayerInputs};
+use game2d::resources::{GameSpeed, GracePeriod, MoveTimer, Occupancy, StartCountdown};
+use game2d::AppState;
+
+// A headless app with the first frame run, so the board and snakes are spawned, and the countdown
+// already over.
+pub fn started_app() -> App {
+    let mut app = build_headless_app();
+    start(&mut app);
+    app
+}
+
+// Runs the first frame of an app that may have had resources inserted, then ends the countdown.
+pub fn start(app: &mut App) {
+    app.update();
+    finish_countdown(app);
+}
+
+pub fn finish_countdown(app: &mut App) {
+    let mut countdown = app.world.get_resource_mut::<StartCountdown>().unwrap();
+    let duration = countdown.duration();
+    countdown.tick(duration + Duration::from_secs(1));
+}
+
+pub fn no_grace(app: &mut App) {
+    app.insert_resource(GracePeriod(0));
+}
+
+// Runs one frame with the move timer due, so every snake takes exactly one step.
+pub fn step(app: &mut App) {
+    let interval = app.world.get_resource::<GameSpeed>().unwrap().interval;
+    app.insert_resource(MoveTimer(interval));
+    app.update();
+}
+
+pub fn steps(app: &mut App, count: usize) {
+    for _ in 0..count {
+        step(app);
+    }
+}
+
+// Sets the turns `handle` presses from the next frame on, until they're changed again.
+pub fn press(app: &mut App, handle: usize, turns: &[Direction]) {
+    let mut inputs = app
+        .world
+        .get_resource_mut::<PlayerInputs<GGRSConfig>>()
+        .unwrap();
+    inputs[handle].0 = BoxInput {
+        inp: encode_turns(turns),
+    };
+}
+
+pub fn release(app: &mut App, handle: usize) {
+    press(app, handle, &[]);
+}
+
+pub fn snake_entities(app: &mut App, handle: usize) -> Vec<Entity> {
+    app.world
+        .query::<(&Player, &Snake)>()
+        .iter(&app.world)
+        .find(|(player, _)| player.handle == handle)
+        .map(|(_, snake)| snake.0.clone())
+        .unwrap_or_default()
+}
+
+// The cells of `handle`'s snake, head first, or none once it's gone.
+pub fn snake_cells(app: &mut App, handle: usize) -> Vec<GridPos> {
+    snake_entities(app, handle)
+        .into_iter()
+        .map(|entity| app.world.get::<Segment>(entity).unwrap().pos)
+        .collect()
+}
+
+pub fn head(app: &mut App, handle: usize) -> Segment {
+    let entity = snake_entities(app, handle)[0];
+    *app.world.get::<Segment>(entity).unwrap()
+}
+
+// Puts `handle`'s snake in a straight line from `cell`, trailing back from `dir`, keeping the
+// occupancy in step.
+pub fn place_snake(app: &mut App, handle: usize, cell: GridPos, dir: Direction) {
+    let len = snake_entities(app, handle).len();
+    let cells = std::iter::successors(Some(cell), |cell| Some(cell.step(dir.opposite())))
+        .take(len)
+        .collect::<Vec<_>>();
+    lay_snake(app, handle, &cells, dir);
+}
+
+// Moves `handle`'s segments onto `cells`, head first, all facing `dir`.
+pub fn lay_snake(app: &mut App, handle: usize, cells: &[GridPos], dir: Direction) {
+    for (entity, cell) in snake_entities(app, handle).into_iter().zip(cells) {
+        let mut seg = app.world.get_mut::<Segment>(entity).unwrap();
+        let old = seg.pos;
+        *seg = Segment {
+            pos: *cell,
+            curr_dir: dir,
+            next_dir: dir,
+        };
+        let mut occupancy = app.world.get_resource_mut::<Occupancy>().unwrap();
+        occupancy.remove(old);
+        occupancy.insert(*cell);
+    }
+}
+
+pub fn spawn_food(app: &mut App, cell: GridPos, value: FoodValue) -> Entity {
+    let grid = *app.world.get_resource::<GridConfig>().unwrap();
+    app.world
+        .get_resource_mut::<Occupancy>()
+        .unwrap()
+        .insert(cell);
+    app.world
+        .spawn()
+        .insert(Food)
+        .insert(value)
+        .insert(Transform::from_translation(cell.to_translation(&grid)))
+        .id()
+}
+
+pub fn spawn_power_up(app: &mut App, cell: GridPos, power_up: PowerUp) -> Entity {
+    let grid = *app.world.get_resource::<GridConfig>().unwrap();
+    app.world
+        .get_resource_mut::<Occupancy>()
+        .unwrap()
+        .insert(cell);
+    app.world
+        .spawn()
+        .insert(power_up)
+        .insert(Transform::from_translation(cell.to_translation(&grid)))
+        .id()
+}
+
+pub fn food_cells(app: &mut App) -> Vec<GridPos> {
+    let grid = *app.world.get_resource::<GridConfig>().unwrap();
+    let mut cells = app
+        .world
+        .query_filtered::<&Transform, With<Food>>()
+        .iter(&app.world)
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells
+}
+
+// Runs one frame, with `step` or `app.update()`, and returns the events of type `T` sent in it.
+pub fn events_during<T: Clone + Send + Sync + 'static>(
+    app: &mut App,
+    run: impl FnOnce(&mut App),
+) -> Vec<T> {
+    app.world.get_resource_mut::<Events<T>>().unwrap().clear();
+    run(app);
+    let events = app.world.get_resource::<Events<T>>().unwrap();
+    events.get_reader().iter(events).cloned().collect()
+}
+
+pub fn state(app: &App) -> AppState {
+    app.world
+        .get_resource::<State<AppState>>()
+        .unwrap()
+        .current()
+        .clone()
+}
+
+// Queues a switch to `state` and runs the frame that makes it.
+pub fn switch_state(app: &mut App, state: AppState) {
+    app.world
+        .get_resource_mut::<State<AppState>>()
+        .unwrap()
+        .set(state)
+        .unwrap();
+    app.update();
+}
+
+pub fn resource<T: Send + Sync + 'static>(app: &App) -> &T {
+    app.world.get_resource::<T>().unwrap()
+}
+
+pub fn press_key(app: &mut App, key: KeyCode) {
+    app.world
+        .get_resource_mut::<Events<KeyboardInput>>()
+        .unwrap()
+        .send(KeyboardInput {
+            scan_code: 0,
+            key_code: Some(key),
+            state: ElementState::Pressed,
+        });
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, FoodValue, Obstacle, ShapeIcon, ShapeOverlay, Trail};
+use game2d::config::{AccessibilityConfig, GridConfig, GridPos};
+use game2d::resources::{ScreenShake, ShapeImages, SCREEN_SHAKE_SECS};
+use game2d::systems::{queue_screen_shake, spawn_shape_overlays, spawn_trails};
+
+fn trail_cells(app: &mut App) -> Vec<GridPos> {
+    let grid = *resource::<GridConfig>(app);
+    let mut cells = app
+        .world
+        .query_filtered::<&Transform, With<Trail>>()
+        .iter(&app.world)
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    cells.sort();
+    cells
+}
+
+#[test]
+fn a_moving_snake_leaves_a_trail_where_it_was() {
+    let mut app = build_headless_app();
+    app.add_system_to_stage(CoreStage::PostUpdate, spawn_trails);
+    start(&mut app);
+    app.update();
+    assert!(trail_cells(&mut app).is_empty());
+    step(&mut app);
+    assert_eq!(
+        trail_cells(&mut app),
+        vec![GridPos::new(3, 5), GridPos::new(6, 14)]
+    );
+}
+
+#[test]
+fn a_death_shakes_the_camera_until_it_settles() {
+    let mut app = build_headless_app();
+    app.add_system_to_stage(CoreStage::Last, queue_screen_shake);
+    start(&mut app);
+    no_grace(&mut app);
+    step(&mut app);
+    assert_eq!(resource::<ScreenShake>(&app).magnitude, 0.);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    step(&mut app);
+
+    let mut shake = app.world.get_resource_mut::<ScreenShake>().unwrap();
+    assert_eq!(shake.magnitude, 1.);
+    assert_ne!(shake.offset(13.), Vec2::ZERO);
+    shake.decay(SCREEN_SHAKE_SECS / 2.);
+    assert!(shake.magnitude > 0. && shake.magnitude < 1.);
+    shake.decay(SCREEN_SHAKE_SECS);
+    assert_eq!(shake.magnitude, 0.);
+    assert_eq!(shake.offset(13.), Vec2::ZERO);
+}
+
+// The icons on `entity`'s children.
+fn overlays(app: &App, entity: Entity) -> Vec<ShapeIcon> {
+    app.world
+        .get::<Children>(entity)
+        .map_or(vec![], |children| {
+            children
+                .iter()
+                .filter_map(|child| app.world.get::<ShapeOverlay>(*child))
+                .map(|overlay| overlay.0)
+                .collect()
+        })
+}
+
+fn app_with_shapes(colorblind_shapes: bool) -> App {
+    let images = ShapeIcon::ALL
+        .iter()
+        .map(|icon| (*icon, Handle::weak(HandleId::random::<Image>())))
+        .collect();
+    let mut app = build_headless_app();
+    app.insert_resource(AccessibilityConfig { colorblind_shapes })
+        .insert_resource(ShapeImages(images))
+        .add_system_to_stage(CoreStage::PostUpdate, spawn_shape_overlays);
+    start(&mut app);
+    app
+}
+
+#[test]
+fn each_food_kind_gets_its_own_icon() {
+    let mut app = app_with_shapes(true);
+    let kinds = [
+        (FoodValue::NORMAL, ShapeIcon::Circle),
+        (FoodValue::GOLDEN, ShapeIcon::Star),
+        (FoodValue::POISON, ShapeIcon::Cross),
+        (FoodValue::MAGNET, ShapeIcon::Ring),
+    ];
+    let food = kinds
+        .iter()
+        .enumerate()
+        .map(|(x, (value, _))| spawn_food(&mut app, GridPos::new(x as i32, 18), *value))
+        .collect::<Vec<_>>();
+    app.update();
+    for (entity, (_, icon)) in food.into_iter().zip(kinds) {
+        assert_eq!(overlays(&app, entity), vec![icon]);
+    }
+    let obstacles = app
+        .world
+        .query_filtered::<Entity, With<Obstacle>>()
+        .iter(&app.world)
+        .collect::<Vec<_>>();
+    assert!(!obstacles.is_empty());
+    for entity in obstacles {
+        assert_eq!(overlays(&app, entity), vec![ShapeIcon::Square]);
+    }
+
+    let mut app = app_with_shapes(false);
+    let food = spawn_food(&mut app, GridPos::new(0, 18), FoodValue::GOLDEN);
+    app.update();
+    assert!(overlays(&app, food).is_empty());
+}
@@ -0,0 +1,246 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::audio::{Sound, SoundQueue};
+use game2d::build_headless_app;
+use game2d::components::{Direction, FoodValue, Player, PlayerScore};
+use game2d::config::{FoodSettings, GridConfig, GridPos, MaxPlayers, StartLength};
+use game2d::resources::{FrameCount, GameSpeed, MoveTimer, Occupancy, Score};
+use game2d::systems::{CollisionEvent, GameEvent, MAGNET_STEP_FRAMES};
+
+#[test]
+fn every_food_eaten_scores_a_point() {
+    let mut app = started_app();
+    for y in 6..9 {
+        spawn_food(&mut app, GridPos::new(3, y), FoodValue::NORMAL);
+    }
+    let mut safe = 0;
+    for _ in 0..3 {
+        let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+        safe += collisions
+            .iter()
+            .filter(|collision| matches!(collision, CollisionEvent::Safe { handle: 0, .. }))
+            .count();
+    }
+    assert_eq!(safe, 3);
+    assert_eq!(**resource::<Score>(&app), 3);
+}
+
+fn player_score(app: &mut bevy::prelude::App, handle: usize) -> u32 {
+    app.world
+        .query::<(&Player, &PlayerScore)>()
+        .iter(&app.world)
+        .find(|(player, _)| player.handle == handle)
+        .map(|(_, score)| **score)
+        .unwrap()
+}
+
+#[test]
+fn only_the_snake_that_eats_scores() {
+    let mut app = started_app();
+    spawn_food(&mut app, GridPos::new(3, 6), FoodValue::NORMAL);
+    step(&mut app);
+    assert_eq!(player_score(&mut app, 0), 1);
+    assert_eq!(player_score(&mut app, 1), 0);
+}
+
+// The countdown is left running, so the snakes stay put while food spawns every frame.
+#[test]
+fn food_spawns_inside_a_small_board() {
+    let grid = GridConfig {
+        width: 5,
+        height: 5,
+        ..GridConfig::default()
+    };
+    let mut app = build_headless_app();
+    app.insert_resource(grid);
+    app.update();
+    app.insert_resource(FoodSettings {
+        spawn_interval: 0.,
+        max_active: 25,
+    });
+    for _ in 0..30 {
+        app.update();
+    }
+    let mut food = food_cells(&mut app);
+    assert!(!food.is_empty());
+    assert!(food.iter().all(|cell| grid.in_bounds(*cell)));
+    let spawned = food.len();
+    food.dedup();
+    assert_eq!(food.len(), spawned);
+}
+
+#[test]
+fn no_more_food_spawns_once_the_board_holds_max_active() {
+    let mut app = build_headless_app();
+    app.update();
+    app.insert_resource(FoodSettings {
+        spawn_interval: 0.,
+        max_active: 2,
+    });
+    for _ in 0..10 {
+        app.update();
+    }
+    assert_eq!(food_cells(&mut app).len(), 2);
+}
+
+#[test]
+fn poison_shrinks_the_snake_and_kills_a_short_one() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(4));
+    start(&mut app);
+    spawn_food(&mut app, GridPos::new(3, 6), FoodValue::POISON);
+    step(&mut app);
+    assert_eq!(
+        snake_cells(&mut app, 0),
+        vec![GridPos::new(3, 6), GridPos::new(3, 5)]
+    );
+
+    spawn_food(&mut app, GridPos::new(3, 7), FoodValue::POISON);
+    let collisions = events_during(&mut app, step);
+    assert!(collisions.contains(&CollisionEvent::Deadly { handle: 0 }));
+}
+
+#[test]
+fn a_full_board_gets_no_food() {
+    let mut app = build_headless_app();
+    app.update();
+    app.insert_resource(FoodSettings {
+        spawn_interval: 0.,
+        max_active: 10,
+    });
+    let grid = *resource::<GridConfig>(&app);
+    let mut occupancy = app.world.get_resource_mut::<Occupancy>().unwrap();
+    for cell in grid.cells() {
+        occupancy.insert(cell);
+    }
+    for _ in 0..5 {
+        app.update();
+    }
+    assert!(food_cells(&mut app).is_empty());
+}
+
+#[test]
+fn eating_speeds_the_snakes_up() {
+    let mut app = started_app();
+    let before = resource::<GameSpeed>(&app).interval;
+    spawn_food(&mut app, GridPos::new(3, 6), FoodValue::NORMAL);
+    step(&mut app);
+    assert!(resource::<GameSpeed>(&app).interval < before);
+}
+
+#[test]
+fn golden_food_grows_the_snake_by_three() {
+    let mut app = started_app();
+    spawn_food(&mut app, GridPos::new(3, 6), FoodValue::GOLDEN);
+    step(&mut app);
+    assert_eq!(
+        snake_cells(&mut app, 0),
+        vec![
+            GridPos::new(3, 6),
+            GridPos::new(3, 5),
+            GridPos::new(3, 4),
+            GridPos::new(3, 3),
+        ]
+    );
+}
+
+// Only one of the three new segments fits between the head and the wall, so the other two wait
+// on the cell next to it instead of going off the board.
+#[test]
+fn golden_food_by_the_wall_grows_the_snake_on_the_board() {
+    let mut app = started_app();
+    place_snake(&mut app, 0, GridPos::new(0, 10), Direction::Right);
+    spawn_food(&mut app, GridPos::new(1, 10), FoodValue::GOLDEN);
+    step(&mut app);
+    let wall = GridPos::new(0, 10);
+    assert_eq!(
+        snake_cells(&mut app, 0),
+        vec![GridPos::new(1, 10), wall, wall, wall]
+    );
+
+    steps(&mut app, 3);
+    let unfolded = (1..5).rev().map(|x| GridPos::new(x, 10));
+    assert_eq!(snake_cells(&mut app, 0), unfolded.collect::<Vec<_>>());
+}
+
+#[test]
+fn the_safe_collision_names_the_food_eaten() {
+    let mut app = started_app();
+    spawn_food(&mut app, GridPos::new(8, 8), FoodValue::NORMAL);
+    let food = spawn_food(&mut app, GridPos::new(3, 6), FoodValue::NORMAL);
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    assert_eq!(collisions, vec![CollisionEvent::Safe { handle: 0, food }]);
+}
+
+#[test]
+fn eating_sends_food_eaten() {
+    let mut app = started_app();
+    let cell = GridPos::new(3, 6);
+    spawn_food(&mut app, cell, FoodValue::GOLDEN);
+    let events: Vec<GameEvent> = events_during(&mut app, step);
+    assert!(events.contains(&GameEvent::FoodEaten {
+        cell,
+        value: FoodValue::GOLDEN,
+    }));
+}
+
+#[test]
+fn eating_queues_the_eat_sound() {
+    let mut app = started_app();
+    spawn_food(&mut app, GridPos::new(3, 6), FoodValue::NORMAL);
+    step(&mut app);
+    assert_eq!(**resource::<SoundQueue>(&app), vec![Sound::Eat]);
+}
+
+// Runs the next frame magnet food moves on, without moving the snakes.
+fn magnet_step(app: &mut App) {
+    let frame = **resource::<FrameCount>(app);
+    let next = (frame / MAGNET_STEP_FRAMES + 1) * MAGNET_STEP_FRAMES;
+    app.insert_resource(FrameCount(next - 1));
+    app.insert_resource(MoveTimer(0.));
+    app.update();
+}
+
+#[test]
+fn magnet_food_drifts_a_cell_at_a_time_towards_the_head() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1));
+    start(&mut app);
+    let magnet = spawn_food(&mut app, GridPos::new(5, 5), FoodValue::MAGNET);
+    let cell = |app: &App| {
+        let grid = resource::<GridConfig>(app);
+        grid.cell_at(app.world.get::<Transform>(magnet).unwrap().translation)
+    };
+
+    app.update();
+    assert_eq!(cell(&app), GridPos::new(5, 5));
+    magnet_step(&mut app);
+    assert_eq!(cell(&app), GridPos::new(4, 5));
+    assert!(resource::<Occupancy>(&app).contains(GridPos::new(4, 5)));
+    assert!(!resource::<Occupancy>(&app).contains(GridPos::new(5, 5)));
+    // The head's cell is taken, so it waits next to it.
+    magnet_step(&mut app);
+    assert_eq!(cell(&app), GridPos::new(4, 5));
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+}
+
+#[test]
+fn eaten_food_is_counted_and_despawned_exactly_once() {
+    let mut app = started_app();
+    let food = spawn_food(&mut app, GridPos::new(3, 6), FoodValue::NORMAL);
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    let eaten = collisions
+        .iter()
+        .filter(|event| matches!(event, CollisionEvent::Safe { food: eaten, .. } if *eaten == food))
+        .count();
+    assert_eq!(eaten, 1);
+    assert!(app.world.get_entity(food).is_none());
+    assert!(!food_cells(&mut app).contains(&GridPos::new(3, 6)));
+
+    let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+    assert!(collisions.is_empty());
+    assert_eq!(**resource::<Score>(&app), 1);
+}
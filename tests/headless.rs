@@ -0,0 +1,47 @@
+mod common;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{
+    BotController, Direction, InputBuffer, Invincible, Player, PlayerScore, Snake, SpeedBoost,
+};
+use game2d::config::{GridPos, MaxPlayers};
+use game2d::resources::FrameCount;
+use game2d::AppState;
+
+#[test]
+fn the_headless_app_steps_one_frame_per_update() {
+    let mut app = build_headless_app();
+    for _ in 0..5 {
+        app.update();
+    }
+    assert_eq!(**resource::<FrameCount>(&app), 5);
+    assert_eq!(state(&app), AppState::InGame);
+    assert_eq!(snake_cells(&mut app, 0).len(), 1);
+    assert_eq!(snake_cells(&mut app, 1).len(), 1);
+}
+
+// Snakes without a segment, one steered by input and one by the bot, are skipped by every system
+// while the real snake plays on.
+#[test]
+fn an_empty_snake_is_skipped() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1));
+    start(&mut app);
+    for handle in [1, 2] {
+        let mut player = app.world.spawn();
+        player
+            .insert(Player { handle })
+            .insert(Snake(Vec::new()))
+            .insert(PlayerScore::default())
+            .insert(InputBuffer::default())
+            .insert(SpeedBoost::default())
+            .insert(Invincible::default());
+        if handle == 2 {
+            player.insert(BotController);
+        }
+    }
+    press(&mut app, 1, &[Direction::Left]);
+    steps(&mut app, 3);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 8)]);
+}
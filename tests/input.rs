@@ -0,0 +1,137 @@
+mod common;
+
+use bevy::ecs::event::Events;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::touch::{touch_screen_input_system, TouchInput, TouchPhase};
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::Direction;
+use game2d::config::{GridPos, KeyBindings};
+use game2d::gamepad::LocalGamepad;
+use game2d::net::{
+    input, local_input, offline_handle, store_offline_input, GGRSConfig, PlayerInputs, SessionKind,
+};
+use game2d::systems::update_dir;
+use game2d::FixedUpdateStage;
+
+// A headless app reading handle 0's input the way a session would, with `store_offline_input`
+// handing it to every snake.
+fn input_app(session_kind: SessionKind) -> App {
+    let mut app = build_headless_app();
+    app.add_event::<KeyboardInput>()
+        .insert_resource(session_kind)
+        .insert_resource(KeyBindings::default())
+        .insert_resource(LocalGamepad::default())
+        .insert_resource(Input::<GamepadButton>::default())
+        .insert_resource(Axis::<GamepadAxis>::default())
+        .insert_resource(Touches::default())
+        .add_system_to_stage(
+            FixedUpdateStage,
+            offline_handle
+                .chain(input)
+                .chain(store_offline_input)
+                .before(update_dir),
+        );
+    app
+}
+
+fn pressed_input(app: &App) -> u8 {
+    resource::<PlayerInputs<GGRSConfig>>(app)[0].0.inp
+}
+
+#[test]
+fn each_local_player_steers_with_their_own_keys() {
+    let mut app = build_headless_app();
+    app.add_event::<KeyboardInput>()
+        .add_system_to_stage(FixedUpdateStage, local_input.before(update_dir));
+    start(&mut app);
+    press_key(&mut app, KeyCode::D);
+    press_key(&mut app, KeyCode::Left);
+    step(&mut app);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(4, 5)]);
+    assert_eq!(snake_cells(&mut app, 1), vec![GridPos::new(5, 14)]);
+}
+
+#[test]
+fn a_spectator_sends_no_input() {
+    let mut player = input_app(SessionKind::Player);
+    start(&mut player);
+    press_key(&mut player, KeyCode::Right);
+    step(&mut player);
+    assert_ne!(pressed_input(&player), 0);
+
+    let mut spectator = input_app(SessionKind::Spectator {
+        host: "host".to_string(),
+    });
+    start(&mut spectator);
+    press_key(&mut spectator, KeyCode::Right);
+    step(&mut spectator);
+    assert_eq!(pressed_input(&spectator), 0);
+    assert_eq!(snake_cells(&mut spectator, 0), vec![GridPos::new(3, 6)]);
+}
+
+#[test]
+fn a_rebound_key_steers_instead_of_the_arrow() {
+    let mut app = input_app(SessionKind::Player);
+    app.insert_resource(KeyBindings {
+        up: KeyCode::W,
+        ..KeyBindings::default()
+    });
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(5, 10), Direction::Left);
+    press_key(&mut app, KeyCode::Up);
+    step(&mut app);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(4, 10)]);
+    press_key(&mut app, KeyCode::W);
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Up);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(4, 11)]);
+}
+
+#[test]
+fn the_dpad_steers_the_local_snake() {
+    let mut app = input_app(SessionKind::Player);
+    let gamepad = Gamepad(0);
+    app.insert_resource(LocalGamepad(Some(gamepad)));
+    start(&mut app);
+    app.world
+        .get_resource_mut::<Input<GamepadButton>>()
+        .unwrap()
+        .press(GamepadButton(gamepad, GamepadButtonType::DPadRight));
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Right);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(4, 5)]);
+}
+
+fn touch(app: &mut App, phase: TouchPhase, position: Vec2) {
+    app.world
+        .get_resource_mut::<Events<TouchInput>>()
+        .unwrap()
+        .send(TouchInput {
+            phase,
+            position,
+            force: None,
+            id: 0,
+        });
+}
+
+#[test]
+fn a_swipe_turns_the_snake_once() {
+    let mut app = input_app(SessionKind::Player);
+    app.add_event::<TouchInput>()
+        .add_system_to_stage(CoreStage::PreUpdate, touch_screen_input_system);
+    start(&mut app);
+    touch(&mut app, TouchPhase::Started, Vec2::new(100., 100.));
+    touch(&mut app, TouchPhase::Moved, Vec2::new(200., 100.));
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Right);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(4, 5)]);
+    assert_ne!(pressed_input(&app), 0);
+
+    // The finger keeps going, but the swipe already turned.
+    touch(&mut app, TouchPhase::Moved, Vec2::new(300., 100.));
+    step(&mut app);
+    assert_eq!(pressed_input(&app), 0);
+}
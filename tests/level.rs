@@ -0,0 +1,56 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, Obstacle};
+use game2d::config::{GridConfig, GridPos};
+use game2d::level::{spawn_level_food, Level};
+use game2d::AppState;
+
+// A walled room with an obstacle, two spawns and a food in it.
+const MAP: &str = "\
+#####
+#S.O#
+#...#
+#F.S#
+#####
+";
+
+#[test]
+fn a_loaded_map_puts_everything_on_its_own_cell() {
+    let level = Level::parse(MAP).unwrap();
+    assert_eq!((level.width, level.height), (5, 5));
+    assert_eq!(level.obstacles, vec![GridPos::new(3, 3)]);
+    assert_eq!(level.spawns, vec![GridPos::new(1, 3), GridPos::new(3, 1)]);
+    assert_eq!(level.food, vec![GridPos::new(1, 1)]);
+    assert_eq!(level.walls.len(), 16);
+    assert!(level.check_fits(&GridConfig::default()).is_ok());
+
+    let mut app = build_headless_app();
+    app.insert_resource(level.clone())
+        .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(spawn_level_food));
+    start(&mut app);
+
+    let grid = *resource::<GridConfig>(&app);
+    let mut obstacles = app
+        .world
+        .query_filtered::<&Transform, With<Obstacle>>()
+        .iter(&app.world)
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    obstacles.sort();
+    let mut expected = level
+        .walls
+        .iter()
+        .chain(&level.obstacles)
+        .copied()
+        .collect::<Vec<_>>();
+    expected.sort();
+    assert_eq!(obstacles, expected);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(1, 3)]);
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Up);
+    assert_eq!(snake_cells(&mut app, 1), vec![GridPos::new(3, 1)]);
+    assert_eq!(food_cells(&mut app), vec![GridPos::new(1, 1)]);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+use matchbox_socket::WebRtcSocket;
+
+use common::*;
+use game2d::config::{Difficulty, GridConfig, MaxPlayers};
+use game2d::net::{
+    wait_for_players, LobbyError, NetConfig, PlayingOffline, SessionKind, SignalingStatus,
+    SIGNALING_ERROR,
+};
+use game2d::ui::{
+    play_offline_interaction, show_play_offline_button, LobbyText, PlayOfflineButton,
+};
+use game2d::AppState;
+
+// A lobby whose socket never came up, like when the signaling server is down.
+fn unreachable_lobby() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(AssetPlugin)
+        .insert_resource(None::<WebRtcSocket>)
+        .insert_resource(SessionKind::Player)
+        .init_resource::<MaxPlayers>()
+        .init_resource::<Difficulty>()
+        .init_resource::<GridConfig>()
+        .init_resource::<SignalingStatus>()
+        .init_resource::<LobbyError>()
+        .init_resource::<NetConfig>()
+        .init_resource::<PlayingOffline>()
+        .add_state(AppState::Lobby)
+        .add_system_set(
+            SystemSet::on_update(AppState::Lobby)
+                .with_system(wait_for_players)
+                .with_system(show_play_offline_button.after(wait_for_players))
+                .with_system(play_offline_interaction),
+        );
+    app.world.spawn().insert(LobbyText);
+    app
+}
+
+#[test]
+fn an_unreachable_server_offers_to_play_offline() {
+    let mut app = unreachable_lobby();
+    app.update();
+    assert_eq!(
+        resource::<LobbyError>(&app).as_deref(),
+        Some(SIGNALING_ERROR)
+    );
+    app.update();
+    let button = app
+        .world
+        .query_filtered::<Entity, With<PlayOfflineButton>>()
+        .single(&app.world);
+
+    app.world.entity_mut(button).insert(Interaction::Clicked);
+    app.update();
+    app.update();
+    assert!(**resource::<PlayingOffline>(&app));
+    assert_eq!(**resource::<MaxPlayers>(&app), 1);
+    assert_eq!(state(&app), AppState::InGame);
+}
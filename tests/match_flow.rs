@@ -0,0 +1,156 @@
+mod common;
+
+use bevy::app::AppExit;
+use bevy::asset::AssetPlugin;
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, FoodValue};
+use game2d::config::{GridPos, KeyBindings, MaxPlayers, TimeTrial};
+use game2d::highscore::HighScore;
+use game2d::resources::{MatchOutcome, Paused, RestartRequested, Score, TrialTimer};
+use game2d::systems::GameEvent;
+use game2d::ui::{pause_menu_interaction, setup_game_over_screen, PauseButton};
+use game2d::AppState;
+
+// A single-player game about to run its snake into the right wall.
+fn about_to_die() -> App {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1));
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    app
+}
+
+#[test]
+fn a_dead_snake_starts_again_from_its_spawn() {
+    let mut app = about_to_die();
+    step(&mut app);
+    app.update();
+    assert_eq!(state(&app), AppState::GameOver);
+    switch_state(&mut app, AppState::InGame);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+}
+
+#[test]
+fn the_last_snake_alive_wins() {
+    let mut app = started_app();
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    step(&mut app);
+    assert_eq!(*resource::<MatchOutcome>(&app), MatchOutcome::Winner(1));
+    assert_eq!(snake_cells(&mut app, 1).len(), 1);
+    app.update();
+    assert_eq!(state(&app), AppState::GameOver);
+}
+
+#[test]
+fn two_snakes_dying_together_is_a_draw() {
+    let mut app = started_app();
+    no_grace(&mut app);
+    place_snake(&mut app, 1, GridPos::new(0, 10), Direction::Left);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let events: Vec<GameEvent> = events_during(&mut app, step);
+    let died = events
+        .into_iter()
+        .filter(|event| matches!(event, GameEvent::SnakeDied { .. }))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        died,
+        vec![
+            GameEvent::SnakeDied { handle: 0 },
+            GameEvent::SnakeDied { handle: 1 }
+        ]
+    );
+    assert_eq!(*resource::<MatchOutcome>(&app), MatchOutcome::Draw);
+}
+
+#[derive(Default)]
+struct GameOvers(u32);
+
+fn count_game_overs(mut game_overs: ResMut<GameOvers>) {
+    game_overs.0 += 1;
+}
+
+// The restart skips the game-over screen, and with it everything saved on the way in.
+#[test]
+fn restart_from_the_pause_menu_starts_a_new_game() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .init_resource::<Input<KeyCode>>()
+        .init_resource::<KeyBindings>()
+        .init_resource::<GameOvers>()
+        .add_event::<AppExit>()
+        .add_system_set(SystemSet::on_update(AppState::InGame).with_system(pause_menu_interaction))
+        .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(count_game_overs));
+    start(&mut app);
+    steps(&mut app, 3);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 8)]);
+
+    app.insert_resource(Paused(true));
+    app.world
+        .spawn()
+        .insert(Interaction::Clicked)
+        .insert(PauseButton::Restart);
+    for _ in 0..3 {
+        app.update();
+    }
+    assert_eq!(state(&app), AppState::InGame);
+    assert!(!**resource::<Paused>(&app));
+    assert!(!**resource::<RestartRequested>(&app));
+    assert_eq!(resource::<GameOvers>(&app).0, 0);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+}
+
+#[test]
+fn the_game_over_screen_shows_the_score_at_death() {
+    let mut app = build_headless_app();
+    app.add_plugin(AssetPlugin)
+        .insert_resource(MaxPlayers(1))
+        .insert_resource(HighScore(5))
+        .init_resource::<KeyBindings>()
+        .add_system_set(
+            SystemSet::on_enter(AppState::GameOver).with_system(setup_game_over_screen),
+        );
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(8, 10), Direction::Right);
+    spawn_food(&mut app, GridPos::new(9, 10), FoodValue::NORMAL);
+    step(&mut app);
+    step(&mut app);
+    app.update();
+    assert_eq!(state(&app), AppState::GameOver);
+    assert_eq!(**resource::<Score>(&app), 1);
+
+    let text = app.world.query::<&Text>().single(&app.world).sections[0]
+        .value
+        .clone();
+    assert!(text.contains("Score: 1  Best: 5"), "{}", text);
+}
+
+#[test]
+fn the_trial_timer_stops_on_the_frame_the_target_is_reached() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(TimeTrial { target_len: 3 });
+    start(&mut app);
+    assert_eq!(resource::<TrialTimer>(&app).frames, 0);
+    for y in 6..8 {
+        spawn_food(&mut app, GridPos::new(3, y), FoodValue::NORMAL);
+    }
+
+    let mut frames = 0;
+    while snake_cells(&mut app, 0).len() < 3 {
+        assert!(!resource::<TrialTimer>(&app).finished);
+        assert!(frames < 20, "the snake never grew");
+        step(&mut app);
+        frames += 1;
+    }
+    let timer = resource::<TrialTimer>(&app);
+    assert!(timer.finished);
+    assert_eq!(timer.frames, frames);
+    steps(&mut app, 5);
+    assert_eq!(resource::<TrialTimer>(&app).frames, frames);
+}
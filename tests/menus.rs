@@ -0,0 +1,38 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::config::{Difficulty, MaxPlayers};
+use game2d::net::PlayingOffline;
+use game2d::ui::{menu_interaction, MenuButton};
+use game2d::AppState;
+
+fn menu_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .init_resource::<Difficulty>()
+        .init_resource::<MaxPlayers>()
+        .init_resource::<PlayingOffline>()
+        .add_state(AppState::MainMenu)
+        .add_system_set(SystemSet::on_update(AppState::MainMenu).with_system(menu_interaction));
+    app
+}
+
+fn click<T: Component>(app: &mut App, button: T) {
+    app.world
+        .spawn()
+        .insert(Interaction::Clicked)
+        .insert(UiColor::default())
+        .insert(button);
+}
+
+#[test]
+fn play_opens_the_match_settings() {
+    let mut app = menu_app();
+    app.update();
+    click(&mut app, MenuButton::Play);
+    app.update();
+    app.update();
+    assert_eq!(state(&app), AppState::Settings);
+}
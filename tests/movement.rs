@@ -0,0 +1,214 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, FoodValue, Obstacle};
+use game2d::config::{
+    BoundaryMode, GridConfig, GridPos, MaxPlayers, PortalLayout, SimConfig, StartLength,
+    MAX_TICK_RATE, MIN_TICK_RATE,
+};
+use game2d::resources::{
+    GameSpeed, MoveTimer, Occupancy, Paused, StartCountdown, BASE_MOVE_INTERVAL,
+};
+use game2d::systems::interpolate_movement;
+
+#[test]
+fn each_snake_follows_its_own_input() {
+    let mut app = started_app();
+    press(&mut app, 0, &[Direction::Right]);
+    press(&mut app, 1, &[Direction::Left]);
+    steps(&mut app, 2);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(5, 5)]);
+    assert_eq!(snake_cells(&mut app, 1), vec![GridPos::new(4, 14)]);
+}
+
+#[test]
+fn a_paused_game_holds_the_snakes_still() {
+    let mut app = started_app();
+    app.insert_resource(Paused(true));
+    steps(&mut app, 3);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+    app.insert_resource(Paused(false));
+    step(&mut app);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 6)]);
+}
+
+#[test]
+fn the_countdown_holds_the_snakes_still() {
+    let mut app = build_headless_app();
+    app.update();
+    steps(&mut app, 10);
+    assert!(!resource::<StartCountdown>(&app).finished());
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+    finish_countdown(&mut app);
+    step(&mut app);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 6)]);
+}
+
+#[test]
+fn at_thirty_ticks_a_second_the_snake_moves_every_third_tick() {
+    let sim = SimConfig::new(30);
+    assert_eq!(sim.ticks_per_second, 30);
+    assert_eq!(sim.tick_secs(), 1. / 30.);
+    assert_eq!(SimConfig::new(1).ticks_per_second, MIN_TICK_RATE);
+    assert_eq!(SimConfig::new(1000).ticks_per_second, MAX_TICK_RATE);
+
+    let mut app = build_headless_app();
+    app.insert_resource(sim);
+    start(&mut app);
+    assert_eq!(resource::<GameSpeed>(&app).interval, BASE_MOVE_INTERVAL);
+    app.insert_resource(MoveTimer(0.));
+    for _ in 0..2 {
+        app.update();
+        assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 5)]);
+    }
+    app.update();
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(3, 6)]);
+}
+
+#[test]
+fn up_then_down_in_one_frame_never_reverses() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(3));
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(5, 10), Direction::Left);
+    press(&mut app, 0, &[Direction::Up, Direction::Down]);
+    step(&mut app);
+    release(&mut app, 0);
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Up);
+    assert_eq!(
+        snake_cells(&mut app, 0),
+        vec![
+            GridPos::new(5, 12),
+            GridPos::new(5, 11),
+            GridPos::new(5, 10)
+        ]
+    );
+}
+
+#[test]
+fn the_body_follows_the_head_round_a_corner() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(3));
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(5, 10), Direction::Up);
+    press(&mut app, 0, &[Direction::Right]);
+    step(&mut app);
+    assert_eq!(
+        snake_cells(&mut app, 0),
+        vec![GridPos::new(6, 10), GridPos::new(5, 10), GridPos::new(5, 9)]
+    );
+}
+
+#[test]
+fn two_turns_pressed_together_are_taken_one_move_each() {
+    let mut app = started_app();
+    place_snake(&mut app, 0, GridPos::new(5, 10), Direction::Left);
+    press(&mut app, 0, &[Direction::Up, Direction::Right]);
+    step(&mut app);
+    release(&mut app, 0);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(5, 11)]);
+    step(&mut app);
+    assert_eq!(snake_cells(&mut app, 0), vec![GridPos::new(6, 11)]);
+}
+
+// The snake loops up its column on a wrapping board, eating a food on the way.
+#[test]
+fn occupancy_follows_the_snake_over_a_hundred_moves() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(BoundaryMode::Wrap)
+        .insert_resource(StartLength(3));
+    start(&mut app);
+    spawn_food(&mut app, GridPos::new(3, 10), FoodValue::NORMAL);
+    steps(&mut app, 100);
+
+    let grid = *resource::<GridConfig>(&app);
+    let obstacles = app
+        .world
+        .query_filtered::<&Transform, With<Obstacle>>()
+        .iter(&app.world)
+        .map(|trans| grid.cell_at(trans.translation))
+        .collect::<Vec<_>>();
+    let snake = snake_cells(&mut app, 0);
+    assert!(snake.len() >= 4);
+    let mut expected = Occupancy::default();
+    for cell in snake
+        .into_iter()
+        .chain(obstacles)
+        .chain(food_cells(&mut app))
+    {
+        expected.insert(cell);
+    }
+    assert_eq!(resource::<Occupancy>(&app).0, expected.0);
+}
+
+#[test]
+fn a_head_entering_a_portal_comes_out_of_its_pair() {
+    let mut app = build_headless_app();
+    app.insert_resource(PortalLayout(vec![(GridPos::new(3, 7), GridPos::new(8, 2))]));
+    start(&mut app);
+    steps(&mut app, 2);
+    assert_eq!(head(&mut app, 0).pos, GridPos::new(8, 2));
+    assert_eq!(head(&mut app, 0).curr_dir, Direction::Up);
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).pos, GridPos::new(8, 3));
+}
+
+// Checks `move_snake` against the plain way of moving a snake: a new head in front, the tail gone.
+#[test]
+fn a_ten_segment_snake_trails_like_a_shifted_list() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(BoundaryMode::Wrap)
+        .insert_resource(StartLength(10));
+    start(&mut app);
+    place_snake(&mut app, 0, GridPos::new(5, 12), Direction::Up);
+    let grid = *resource::<GridConfig>(&app);
+    let mut expected = snake_cells(&mut app, 0);
+
+    let script = [
+        (Direction::Up, 4),
+        (Direction::Right, 6),
+        (Direction::Down, 5),
+        (Direction::Right, 5),
+    ];
+    for (dir, ticks) in script {
+        for _ in 0..ticks {
+            press(&mut app, 0, &[dir]);
+            step(&mut app);
+            expected.insert(0, grid.wrap(expected[0].step(dir)));
+            expected.pop();
+            assert_eq!(snake_cells(&mut app, 0), expected);
+        }
+    }
+}
+
+#[test]
+fn halfway_through_a_move_the_head_is_drawn_halfway_between_cells() {
+    let mut app = build_headless_app();
+    app.add_system_to_stage(CoreStage::PostUpdate, interpolate_movement);
+    start(&mut app);
+    step(&mut app);
+    assert_eq!(head(&mut app, 0).pos, GridPos::new(3, 6));
+
+    let interval = resource::<GameSpeed>(&app).interval;
+    let tick = resource::<SimConfig>(&app).tick_secs() as f32;
+    app.insert_resource(MoveTimer(interval / 2. - tick));
+    app.update();
+    assert_eq!(head(&mut app, 0).pos, GridPos::new(3, 6));
+    let grid = *resource::<GridConfig>(&app);
+    let halfway = GridPos::new(3, 5)
+        .to_translation(&grid)
+        .lerp(GridPos::new(3, 6).to_translation(&grid), 0.5);
+    let entity = snake_entities(&mut app, 0)[0];
+    let drawn = app.world.get::<Transform>(entity).unwrap().translation;
+    assert!(
+        (drawn.truncate() - halfway.truncate()).length() < 1e-3,
+        "{:?}",
+        drawn
+    );
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use bevy::prelude::*;
+use matchbox_socket::WebRtcSocket;
+
+use common::*;
+use game2d::config::{GridConfig, MaxPlayers, Palette, SimConfig, SinglePlayer};
+use game2d::resources::{GameRng, GameSpeed, Occupancy, Score, StartCountdown};
+use game2d::{AppState, GamePlugin};
+
+#[test]
+fn the_plugin_installs_the_game_resources() {
+    let mut app = App::new();
+    app.add_plugin(GamePlugin);
+    assert!(app.world.contains_resource::<GridConfig>());
+    assert!(app.world.contains_resource::<Palette>());
+    assert!(app.world.contains_resource::<SimConfig>());
+    assert!(app.world.contains_resource::<Score>());
+    assert!(app.world.contains_resource::<GameSpeed>());
+    assert!(app.world.contains_resource::<GameRng>());
+    assert!(app.world.contains_resource::<Occupancy>());
+    assert!(app.world.contains_resource::<StartCountdown>());
+    assert_eq!(*resource::<MaxPlayers>(&app), MaxPlayers::default());
+    assert_eq!(state(&app), AppState::MainMenu);
+}
+
+#[test]
+fn single_player_never_opens_a_socket() {
+    let mut app = App::new();
+    app.insert_resource(SinglePlayer(true))
+        .add_plugin(GamePlugin);
+    assert!(!app.world.contains_resource::<Option<WebRtcSocket>>());
+    assert!(!app.world.contains_resource::<WebRtcSocket>());
+    assert_eq!(*resource::<MaxPlayers>(&app), MaxPlayers(1));
+    assert_eq!(state(&app), AppState::InGame);
+}
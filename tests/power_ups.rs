@@ -0,0 +1,78 @@
+mod common;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, Player, PowerUp, SpeedBoost};
+use game2d::config::{BoundaryMode, GridPos, MaxPlayers};
+use game2d::resources::GameSpeed;
+use game2d::systems::{CollisionEvent, SHIELD_FRAMES, SPEED_BOOST_FRAMES};
+
+fn boost(app: &mut bevy::prelude::App, handle: usize) -> SpeedBoost {
+    *app.world
+        .query::<(&Player, &SpeedBoost)>()
+        .iter(&app.world)
+        .find(|(player, _)| player.handle == handle)
+        .unwrap()
+        .1
+}
+
+// On a wrapping board both snakes can run for as long as the boost lasts.
+#[test]
+fn a_speed_boost_halves_the_move_interval_until_it_runs_out() {
+    let mut app = build_headless_app();
+    app.insert_resource(BoundaryMode::Wrap);
+    start(&mut app);
+    spawn_power_up(&mut app, GridPos::new(3, 6), PowerUp::Speed);
+    step(&mut app);
+    let interval = resource::<GameSpeed>(&app).interval;
+    assert_eq!(boost(&mut app, 0).interval(interval), interval / 2.);
+    assert!(!boost(&mut app, 1).active());
+
+    let mut moves = [0, 0];
+    let mut heads = [head(&mut app, 0).pos, head(&mut app, 1).pos];
+    for _ in 0..60 {
+        app.update();
+        for handle in 0..2 {
+            let pos = head(&mut app, handle).pos;
+            if pos != heads[handle] {
+                moves[handle] += 1;
+                heads[handle] = pos;
+            }
+        }
+    }
+    assert!(
+        (moves[0] as i32 - 2 * moves[1] as i32).abs() <= 2,
+        "{:?}",
+        moves
+    );
+
+    for _ in 60..SPEED_BOOST_FRAMES {
+        app.update();
+    }
+    assert!(!boost(&mut app, 0).active());
+    assert_eq!(boost(&mut app, 0).interval(interval), interval);
+}
+
+#[test]
+fn a_shielded_snake_waits_at_the_wall_until_the_shield_runs_out() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1));
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(8, 10), Direction::Right);
+    spawn_power_up(&mut app, GridPos::new(9, 10), PowerUp::Shield);
+    step(&mut app);
+
+    let death = CollisionEvent::Deadly { handle: 0 };
+    let mut frames = 0;
+    loop {
+        let collisions: Vec<CollisionEvent> = events_during(&mut app, step);
+        frames += 1;
+        if collisions.contains(&death) {
+            break;
+        }
+        assert_eq!(head(&mut app, 0).pos, GridPos::new(9, 10));
+        assert!(frames <= SHIELD_FRAMES + 1, "the shield never ran out");
+    }
+    assert!(frames + 2 >= SHIELD_FRAMES, "died after {} frames", frames);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::config::{AssistMode, Difficulty, GridConfig, MaxPlayers};
+use game2d::net::MatchboxConfig;
+use game2d::ui::{
+    settings_interaction, SettingsButton, SettingsField, SettingsStepper, SettingsValue,
+};
+use game2d::AppState;
+
+fn settings_app() -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .init_resource::<GridConfig>()
+        .init_resource::<MaxPlayers>()
+        .init_resource::<Difficulty>()
+        .init_resource::<AssistMode>()
+        .init_resource::<MatchboxConfig>()
+        .add_state(AppState::Settings)
+        .add_system_set(SystemSet::on_update(AppState::Settings).with_system(settings_interaction));
+    app.world
+        .spawn()
+        .insert(SettingsValue(SettingsField::Width))
+        .insert(Text::with_section(
+            "10",
+            TextStyle::default(),
+            TextAlignment::default(),
+        ));
+    app
+}
+
+fn click(app: &mut App, button: impl Component) {
+    app.world
+        .spawn()
+        .insert(Interaction::Clicked)
+        .insert(button);
+    app.update();
+}
+
+#[test]
+fn the_width_stepper_resizes_the_board_before_the_lobby() {
+    let mut app = settings_app();
+    app.update();
+    click(
+        &mut app,
+        SettingsStepper {
+            field: SettingsField::Width,
+            delta: 1,
+        },
+    );
+    assert_eq!(resource::<GridConfig>(&app).width, 11);
+    let mut text_query = app.world.query::<(&SettingsValue, &Text)>();
+    let (_, text) = text_query.iter(&app.world).next().unwrap();
+    assert_eq!(text.sections[0].value, "11");
+
+    click(&mut app, SettingsButton::Start);
+    assert_eq!(state(&app), AppState::Lobby);
+    assert_eq!(resource::<GridConfig>(&app).width, 11);
+}
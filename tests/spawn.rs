@@ -0,0 +1,164 @@
+mod common;
+
+use bevy::ecs::system::CommandQueue;
+use bevy::prelude::*;
+use bevy_ggrs::RollbackIdProvider;
+use ggrs::{InputStatus, PlayerType, SessionBuilder};
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, Player, Segment, Snake};
+use game2d::config::{GridConfig, GridPos, MaxPlayers, StartLength, MIN_GRID_CELLS};
+use game2d::net::{BoxInput, GGRSConfig, PlayerInputs};
+use game2d::resources::{Occupancy, SegmentPool};
+use game2d::systems::{spawn_positions, spawn_segments};
+use game2d::AppState;
+
+#[test]
+fn a_longer_start_spawns_a_straight_line() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(5));
+    app.update();
+    let cells = (1..=5)
+        .rev()
+        .map(|y| GridPos::new(3, y))
+        .collect::<Vec<_>>();
+    assert_eq!(snake_cells(&mut app, 0), cells);
+}
+
+#[test]
+fn four_players_get_four_snakes() {
+    let players = MaxPlayers(4);
+    let mut session = SessionBuilder::<GGRSConfig>::new().with_num_players(players.count());
+    for handle in 0..players.count() {
+        session = session.add_player(PlayerType::Local, handle).unwrap();
+    }
+    assert!(session.start_synctest_session().is_ok());
+
+    let mut app = build_headless_app();
+    let inputs: PlayerInputs<GGRSConfig> =
+        vec![(BoxInput { inp: 0 }, InputStatus::Confirmed); players.count()];
+    app.insert_resource(players).insert_resource(inputs);
+    app.update();
+    let mut handles = app
+        .world
+        .query_filtered::<&Player, With<Snake>>()
+        .iter(&app.world)
+        .map(|player| player.handle)
+        .collect::<Vec<_>>();
+    handles.sort_unstable();
+    assert_eq!(handles, vec![0, 1, 2, 3]);
+    for handle in handles {
+        assert_eq!(snake_cells(&mut app, handle).len(), 1);
+    }
+}
+
+#[test]
+fn four_players_start_on_four_different_cells() {
+    let smallest = GridConfig {
+        width: MIN_GRID_CELLS,
+        height: MIN_GRID_CELLS,
+        ..GridConfig::default()
+    };
+    for grid in [GridConfig::default(), smallest] {
+        let positions = spawn_positions(4, &grid);
+        assert_eq!(positions.len(), 4);
+        for (i, (cell, _)) in positions.iter().enumerate() {
+            assert!(grid.in_bounds(*cell), "{:?}", cell);
+            assert!(positions[..i].iter().all(|(other, _)| other != cell));
+        }
+    }
+}
+
+#[test]
+fn new_segments_line_up_behind_the_tail() {
+    let mut world = World::new();
+    let mut queue = CommandQueue::default();
+    let tail = Segment {
+        pos: GridPos::new(4, 10),
+        curr_dir: Direction::Left,
+        next_dir: Direction::Left,
+    };
+    let segments = spawn_segments(
+        &mut Commands::new(&mut queue, &world),
+        &mut RollbackIdProvider::default(),
+        &GridConfig::default(),
+        &Occupancy::default(),
+        Color::GREEN,
+        5,
+        tail,
+    );
+    queue.apply(&mut world);
+
+    let cells = (5..10).map(|x| GridPos::new(x, 10)).collect::<Vec<_>>();
+    assert_eq!(
+        segments.iter().map(|(_, cell)| *cell).collect::<Vec<_>>(),
+        cells
+    );
+    for (entity, cell) in segments {
+        let seg = world.get::<Segment>(entity).unwrap();
+        assert_eq!((seg.pos, seg.curr_dir), (cell, Direction::Left));
+    }
+}
+
+// A tail two cells off the left wall, and another with the cell behind it taken.
+#[test]
+fn new_segments_stack_up_where_the_tail_is_blocked() {
+    let grow = |tail: Segment, occupancy: &Occupancy| {
+        let world = World::new();
+        let mut queue = CommandQueue::default();
+        spawn_segments(
+            &mut Commands::new(&mut queue, &world),
+            &mut RollbackIdProvider::default(),
+            &GridConfig::default(),
+            occupancy,
+            Color::GREEN,
+            4,
+            tail,
+        )
+        .into_iter()
+        .map(|(_, cell)| cell)
+        .collect::<Vec<_>>()
+    };
+    let tail = |x| Segment {
+        pos: GridPos::new(x, 10),
+        curr_dir: Direction::Right,
+        next_dir: Direction::Right,
+    };
+    let wall = GridPos::new(0, 10);
+    assert_eq!(
+        grow(tail(2), &Occupancy::default()),
+        vec![GridPos::new(1, 10), wall, wall, wall]
+    );
+
+    let mut occupancy = Occupancy::default();
+    occupancy.insert(GridPos::new(4, 10));
+    assert_eq!(grow(tail(5), &occupancy), vec![GridPos::new(5, 10); 4]);
+}
+
+#[test]
+fn a_restart_takes_its_segments_back_out_of_the_pool() {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(StartLength(3));
+    start(&mut app);
+    no_grace(&mut app);
+    place_snake(&mut app, 0, GridPos::new(9, 10), Direction::Right);
+    let mut before = snake_entities(&mut app, 0);
+    step(&mut app);
+    app.update();
+    assert_eq!(state(&app), AppState::GameOver);
+
+    switch_state(&mut app, AppState::InGame);
+    let mut after = snake_entities(&mut app, 0);
+    before.sort();
+    after.sort();
+    assert_eq!(after, before);
+    assert!(resource::<SegmentPool>(&app).is_empty());
+    for entity in after {
+        assert!(app.world.get::<Visibility>(entity).unwrap().is_visible);
+        assert!(app.world.get::<Segment>(entity).is_some());
+    }
+    let segments = app.world.query::<&Segment>().iter(&app.world).count();
+    assert_eq!(segments, 3);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::config::{Palette, StartLength};
+
+fn segment_colors(app: &mut App, handle: usize) -> Vec<Color> {
+    snake_entities(app, handle)
+        .into_iter()
+        .map(|entity| app.world.get::<Sprite>(entity).unwrap().color)
+        .collect()
+}
+
+#[test]
+fn the_head_is_drawn_apart_from_the_body() {
+    let mut app = build_headless_app();
+    app.insert_resource(StartLength(3));
+    app.update();
+    let colors = segment_colors(&mut app, 0);
+    assert_ne!(colors[0], colors[1]);
+    assert_eq!(colors[1], colors[2]);
+}
+
+#[test]
+fn a_custom_palette_colors_new_segments() {
+    let palette = Palette {
+        heads: [Color::CYAN, Color::PINK, Color::GOLD, Color::OLIVE],
+        body: Color::PURPLE,
+        ..Palette::default()
+    };
+    let mut app = build_headless_app();
+    app.insert_resource(palette).insert_resource(StartLength(2));
+    app.update();
+    assert_eq!(
+        segment_colors(&mut app, 0),
+        vec![Color::CYAN, Color::PURPLE]
+    );
+    assert_eq!(
+        segment_colors(&mut app, 1),
+        vec![Color::PINK, Color::PURPLE]
+    );
+}
@@ -0,0 +1,179 @@
+mod common;
+
+use bevy::prelude::*;
+
+use common::*;
+use game2d::build_headless_app;
+use game2d::components::{Direction, Segment};
+use game2d::config::{BoundaryMode, FoodSettings, GridConfig, GridPos, MaxPlayers, ObstacleLayout};
+use game2d::replay::{replay_input, ReplayPlayback, ReplayRecorder};
+use game2d::resources::{FrameCount, GameRng, Paused};
+use game2d::systems::{sync_segment_transforms, update_dir};
+use game2d::FixedUpdateStage;
+
+// Presses the same turns on both snakes of `app` at fixed frames, and returns where they end up.
+fn run_inputs(app: &mut App) -> Vec<Vec<GridPos>> {
+    let turns = [
+        (3, 0, Direction::Right),
+        (3, 1, Direction::Left),
+        (6, 0, Direction::Up),
+        (8, 1, Direction::Down),
+        (10, 0, Direction::Left),
+    ];
+    start(app);
+    for frame in 0..12 {
+        release(app, 0);
+        release(app, 1);
+        for (_, handle, dir) in turns.iter().filter(|(at, _, _)| *at == frame) {
+            press(app, *handle, &[*dir]);
+        }
+        step(app);
+    }
+    vec![snake_cells(app, 0), snake_cells(app, 1)]
+}
+
+#[test]
+fn the_same_inputs_leave_two_sessions_in_the_same_place() {
+    let (mut first, mut second) = (build_headless_app(), build_headless_app());
+    let positions = run_inputs(&mut first);
+    assert_eq!(positions, run_inputs(&mut second));
+    assert_eq!(
+        positions,
+        vec![vec![GridPos::new(4, 12)], vec![GridPos::new(1, 7)]]
+    );
+}
+
+// A lone snake climbing a staircase round a wrapping board for 1000 moves, returned as its cell and
+// the exact bits of where its head is drawn.
+fn thousand_moves() -> (GridPos, [u32; 2]) {
+    let mut app = build_headless_app();
+    app.insert_resource(MaxPlayers(1))
+        .insert_resource(BoundaryMode::Wrap)
+        .insert_resource(ObstacleLayout(Vec::new()))
+        .add_system_to_stage(CoreStage::PostUpdate, sync_segment_transforms);
+    start(&mut app);
+    app.insert_resource(FoodSettings {
+        spawn_interval: 0.,
+        max_active: 0,
+    });
+    for moves in 0..1000 {
+        let dir = if moves / 7 % 2 == 0 {
+            Direction::Right
+        } else {
+            Direction::Up
+        };
+        press(&mut app, 0, &[dir]);
+        step(&mut app);
+    }
+    let entity = snake_entities(&mut app, 0)[0];
+    let translation = app.world.get::<Transform>(entity).unwrap().translation;
+    let pos = app.world.get::<Segment>(entity).unwrap().pos;
+    (pos, translation.truncate().to_array().map(f32::to_bits))
+}
+
+#[test]
+fn a_thousand_moves_end_up_bit_for_bit_the_same() {
+    let (pos, bits) = thousand_moves();
+    assert_eq!(thousand_moves(), (pos, bits));
+    let drawn = pos.to_translation(&GridConfig::default()).truncate();
+    assert_eq!(bits, drawn.to_array().map(f32::to_bits));
+}
+
+// Where food lands in a game seeded with `seed`, spawning a piece every frame while the countdown
+// holds the snakes still.
+fn food_for_seed(seed: u64) -> Vec<GridPos> {
+    let mut app = build_headless_app();
+    app.insert_resource(GameRng::new(seed));
+    app.update();
+    app.insert_resource(FoodSettings {
+        spawn_interval: 0.,
+        max_active: 10,
+    });
+    for _ in 0..10 {
+        app.update();
+    }
+    food_cells(&mut app)
+}
+
+#[test]
+fn the_same_seed_spawns_the_same_food() {
+    let food = food_for_seed(7);
+    assert_eq!(food.len(), 10);
+    assert_eq!(food, food_for_seed(7));
+}
+
+// The turns pressed on `frame` of the recorded run, one list per handle. The countdown takes the
+// first 180 frames.
+fn recorded_turns(frame: u32) -> [&'static [Direction]; 2] {
+    match frame {
+        190 => [&[Direction::Right], &[Direction::Left]],
+        215 => [&[Direction::Up], &[]],
+        230 => [&[], &[Direction::Down]],
+        250 => [&[Direction::Left, Direction::Up], &[Direction::Right]],
+        _ => [&[], &[]],
+    }
+}
+
+#[test]
+fn a_replay_ends_where_the_recorded_match_did() {
+    let mut recorded = build_headless_app();
+    for frame in 0..300 {
+        for (handle, turns) in recorded_turns(frame).iter().enumerate() {
+            press(&mut recorded, handle, turns);
+        }
+        recorded.update();
+    }
+    let replay = resource::<ReplayRecorder>(&recorded).clone();
+    assert_eq!(replay.frames.len(), 300);
+
+    let mut replayed = build_headless_app();
+    replayed
+        .insert_resource(GameRng::resume(replay.seed, replay.draws))
+        .insert_resource(ReplayPlayback::new(replay))
+        .add_system_to_stage(FixedUpdateStage, replay_input.before(update_dir));
+    for _ in 0..300 {
+        replayed.update();
+    }
+    for handle in 0..2 {
+        let cells = snake_cells(&mut recorded, handle);
+        assert!(!cells.is_empty());
+        assert_eq!(cells, snake_cells(&mut replayed, handle));
+    }
+    assert_eq!(food_cells(&mut recorded), food_cells(&mut replayed));
+}
+
+// Pausing mid-recording, with a turn pressed while paused, leaves a replay that never pauses
+// ending in the same place.
+#[test]
+fn a_replay_skips_the_frames_the_recording_was_paused_for() {
+    let mut recorded = build_headless_app();
+    for frame in 0..300 {
+        recorded.insert_resource(Paused((220..240).contains(&frame)));
+        for (handle, turns) in recorded_turns(frame).iter().enumerate() {
+            press(&mut recorded, handle, turns);
+        }
+        if frame == 225 {
+            press(&mut recorded, 0, &[Direction::Right]);
+        }
+        recorded.update();
+    }
+    let replay = resource::<ReplayRecorder>(&recorded).clone();
+    assert_eq!(replay.frames.len(), 280);
+    assert_eq!(**resource::<FrameCount>(&recorded), 280);
+
+    let mut replayed = build_headless_app();
+    replayed
+        .insert_resource(GameRng::resume(replay.seed, replay.draws))
+        .insert_resource(ReplayPlayback::new(replay))
+        .add_system_to_stage(FixedUpdateStage, replay_input.before(update_dir));
+    for _ in 0..280 {
+        replayed.update();
+    }
+    for handle in 0..2 {
+        assert_eq!(
+            snake_cells(&mut recorded, handle),
+            snake_cells(&mut replayed, handle)
+        );
+    }
+    assert_eq!(food_cells(&mut recorded), food_cells(&mut replayed));
+}